@@ -0,0 +1,26 @@
+use std::env;
+
+/// libplacebo API versions newer than `MIN_API_VER` in libplacebo-sys that
+/// wrappers are gated on, as `cfg(pl_api_N)`, along with what they
+/// introduced.
+///
+/// Empty for now: the existing wrappers, including `pl_options`, have only
+/// been built against v7.349, so that is what `MIN_API_VER` requires rather
+/// than an older release they have not been checked against. Wrappers for
+/// anything newer go here instead of raising it.
+const API_GATES: &[(u32, &str)] = &[];
+
+fn main() {
+  // Set by libplacebo-sys, from the headers the bindings were generated from.
+  let api_ver: u32 = env::var("DEP_PLACEBO_API_VER")
+    .expect("DEP_PLACEBO_API_VER not set by libplacebo-sys")
+    .parse()
+    .expect("DEP_PLACEBO_API_VER is not a number");
+
+  for &(gate, _) in API_GATES {
+    println!("cargo::rustc-check-cfg=cfg(pl_api_{gate})");
+    if api_ver >= gate {
+      println!("cargo::rustc-cfg=pl_api_{gate}");
+    }
+  }
+}
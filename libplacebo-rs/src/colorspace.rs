@@ -1,4 +1,7 @@
-use libplacebo_sys::{pl_bit_encoding, pl_color_repr, pl_color_system};
+use libplacebo_sys::{
+  pl_bit_encoding, pl_chroma_location, pl_color_levels, pl_color_primaries, pl_color_repr,
+  pl_color_system, pl_color_transfer,
+};
 
 /// The underlying bit-wise representation of a color sample.
 pub struct BitEncoding(pl_bit_encoding);
@@ -46,3 +49,180 @@ impl ColorRepr {
     self
   }
 }
+
+// Mappings between the code points of ITU-T H.273 (as used by VapourSynth
+// frame properties and most containers) and their libplacebo equivalents.
+// Values libplacebo has no equivalent for map to `None`.
+
+/// Matrix value for CIE 1931 XYZ (`PL_COLOR_SYSTEM_XYZ`, as used by DCI),
+/// which H.273 has no code point for. libplacebo has no XYB system.
+pub const MATRIX_XYZ: i64 = 100;
+
+#[must_use]
+pub const fn system_from_matrix(
+  matrix: i64,
+  transfer: Option<pl_color_transfer>,
+) -> Option<pl_color_system> {
+  Some(match matrix {
+    0 => pl_color_system::PL_COLOR_SYSTEM_RGB,
+    1 => pl_color_system::PL_COLOR_SYSTEM_BT_709,
+    5 | 6 => pl_color_system::PL_COLOR_SYSTEM_BT_601,
+    7 => pl_color_system::PL_COLOR_SYSTEM_SMPTE_240M,
+    8 => pl_color_system::PL_COLOR_SYSTEM_YCGCO,
+    9 => pl_color_system::PL_COLOR_SYSTEM_BT_2020_NC,
+    10 => pl_color_system::PL_COLOR_SYSTEM_BT_2020_C,
+    14 => match transfer {
+      Some(pl_color_transfer::PL_COLOR_TRC_HLG) => pl_color_system::PL_COLOR_SYSTEM_BT_2100_HLG,
+      _ => pl_color_system::PL_COLOR_SYSTEM_BT_2100_PQ,
+    },
+    MATRIX_XYZ => pl_color_system::PL_COLOR_SYSTEM_XYZ,
+    _ => return None,
+  })
+}
+
+#[must_use]
+pub const fn matrix_from_system(sys: pl_color_system) -> Option<i64> {
+  Some(match sys {
+    pl_color_system::PL_COLOR_SYSTEM_RGB => 0,
+    pl_color_system::PL_COLOR_SYSTEM_BT_709 => 1,
+    pl_color_system::PL_COLOR_SYSTEM_BT_601 => 6,
+    pl_color_system::PL_COLOR_SYSTEM_SMPTE_240M => 7,
+    pl_color_system::PL_COLOR_SYSTEM_YCGCO => 8,
+    pl_color_system::PL_COLOR_SYSTEM_BT_2020_NC => 9,
+    pl_color_system::PL_COLOR_SYSTEM_BT_2020_C => 10,
+    pl_color_system::PL_COLOR_SYSTEM_BT_2100_PQ | pl_color_system::PL_COLOR_SYSTEM_BT_2100_HLG => {
+      14
+    }
+    pl_color_system::PL_COLOR_SYSTEM_XYZ => MATRIX_XYZ,
+    _ => return None,
+  })
+}
+
+#[must_use]
+pub const fn transfer_from_h273(transfer: i64) -> Option<pl_color_transfer> {
+  Some(match transfer {
+    1 | 6 | 14 | 15 => pl_color_transfer::PL_COLOR_TRC_BT_1886,
+    4 => pl_color_transfer::PL_COLOR_TRC_GAMMA22,
+    5 => pl_color_transfer::PL_COLOR_TRC_GAMMA28,
+    8 => pl_color_transfer::PL_COLOR_TRC_LINEAR,
+    13 => pl_color_transfer::PL_COLOR_TRC_SRGB,
+    16 => pl_color_transfer::PL_COLOR_TRC_PQ,
+    18 => pl_color_transfer::PL_COLOR_TRC_HLG,
+    _ => return None,
+  })
+}
+
+#[must_use]
+pub const fn transfer_to_h273(trc: pl_color_transfer) -> Option<i64> {
+  Some(match trc {
+    pl_color_transfer::PL_COLOR_TRC_BT_1886 => 1,
+    pl_color_transfer::PL_COLOR_TRC_GAMMA22 => 4,
+    pl_color_transfer::PL_COLOR_TRC_GAMMA28 => 5,
+    pl_color_transfer::PL_COLOR_TRC_LINEAR => 8,
+    pl_color_transfer::PL_COLOR_TRC_SRGB => 13,
+    pl_color_transfer::PL_COLOR_TRC_PQ => 16,
+    pl_color_transfer::PL_COLOR_TRC_HLG => 18,
+    _ => return None,
+  })
+}
+
+#[must_use]
+pub const fn primaries_from_h273(primaries: i64) -> Option<pl_color_primaries> {
+  Some(match primaries {
+    1 => pl_color_primaries::PL_COLOR_PRIM_BT_709,
+    4 => pl_color_primaries::PL_COLOR_PRIM_BT_470M,
+    5 => pl_color_primaries::PL_COLOR_PRIM_BT_601_625,
+    6 | 7 => pl_color_primaries::PL_COLOR_PRIM_BT_601_525,
+    8 => pl_color_primaries::PL_COLOR_PRIM_FILM_C,
+    9 => pl_color_primaries::PL_COLOR_PRIM_BT_2020,
+    10 => pl_color_primaries::PL_COLOR_PRIM_CIE_1931,
+    11 => pl_color_primaries::PL_COLOR_PRIM_DCI_P3,
+    12 => pl_color_primaries::PL_COLOR_PRIM_DISPLAY_P3,
+    22 => pl_color_primaries::PL_COLOR_PRIM_EBU_3213,
+    _ => return None,
+  })
+}
+
+#[must_use]
+pub const fn primaries_to_h273(prim: pl_color_primaries) -> Option<i64> {
+  Some(match prim {
+    pl_color_primaries::PL_COLOR_PRIM_BT_709 => 1,
+    pl_color_primaries::PL_COLOR_PRIM_BT_470M => 4,
+    pl_color_primaries::PL_COLOR_PRIM_BT_601_625 => 5,
+    pl_color_primaries::PL_COLOR_PRIM_BT_601_525 => 6,
+    pl_color_primaries::PL_COLOR_PRIM_FILM_C => 8,
+    pl_color_primaries::PL_COLOR_PRIM_BT_2020 => 9,
+    pl_color_primaries::PL_COLOR_PRIM_CIE_1931 => 10,
+    pl_color_primaries::PL_COLOR_PRIM_DCI_P3 => 11,
+    pl_color_primaries::PL_COLOR_PRIM_DISPLAY_P3 => 12,
+    pl_color_primaries::PL_COLOR_PRIM_EBU_3213 => 22,
+    _ => return None,
+  })
+}
+
+#[must_use]
+pub const fn levels_from_range(range: i64) -> Option<pl_color_levels> {
+  match range {
+    0 => Some(pl_color_levels::PL_COLOR_LEVELS_FULL),
+    1 => Some(pl_color_levels::PL_COLOR_LEVELS_LIMITED),
+    _ => None,
+  }
+}
+
+#[must_use]
+pub const fn levels_to_range(levels: pl_color_levels) -> Option<i64> {
+  match levels {
+    pl_color_levels::PL_COLOR_LEVELS_FULL => Some(0),
+    pl_color_levels::PL_COLOR_LEVELS_LIMITED => Some(1),
+    _ => None,
+  }
+}
+
+#[must_use]
+pub const fn chroma_location_from_h273(location: i64) -> Option<pl_chroma_location> {
+  Some(match location {
+    0 => pl_chroma_location::PL_CHROMA_LEFT,
+    1 => pl_chroma_location::PL_CHROMA_CENTER,
+    2 => pl_chroma_location::PL_CHROMA_TOP_LEFT,
+    3 => pl_chroma_location::PL_CHROMA_TOP_CENTER,
+    4 => pl_chroma_location::PL_CHROMA_BOTTOM_LEFT,
+    5 => pl_chroma_location::PL_CHROMA_BOTTOM_CENTER,
+    _ => return None,
+  })
+}
+
+#[must_use]
+pub const fn chroma_location_to_h273(location: pl_chroma_location) -> Option<i64> {
+  Some(match location {
+    pl_chroma_location::PL_CHROMA_LEFT => 0,
+    pl_chroma_location::PL_CHROMA_CENTER => 1,
+    pl_chroma_location::PL_CHROMA_TOP_LEFT => 2,
+    pl_chroma_location::PL_CHROMA_TOP_CENTER => 3,
+    pl_chroma_location::PL_CHROMA_BOTTOM_LEFT => 4,
+    pl_chroma_location::PL_CHROMA_BOTTOM_CENTER => 5,
+    _ => return None,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn matrices_round_trip() {
+    for matrix in [0, 1, 6, 7, 8, 9, 10, 14, MATRIX_XYZ] {
+      let sys = system_from_matrix(matrix, None).unwrap();
+      assert_eq!(matrix_from_system(sys), Some(matrix));
+    }
+
+    assert_eq!(
+      system_from_matrix(MATRIX_XYZ, None),
+      Some(pl_color_system::PL_COLOR_SYSTEM_XYZ)
+    );
+    assert_eq!(
+      system_from_matrix(14, Some(pl_color_transfer::PL_COLOR_TRC_HLG)),
+      Some(pl_color_system::PL_COLOR_SYSTEM_BT_2100_HLG)
+    );
+    assert_eq!(system_from_matrix(2, None), None);
+  }
+}
@@ -1,5 +1,3 @@
-use std::ptr::NonNull;
-
 use foreign_types::{foreign_type, ForeignType};
 use libplacebo_sys::{
   pl_dispatch, pl_dispatch_begin, pl_dispatch_create, pl_dispatch_destroy, pl_dispatch_finish,
@@ -13,7 +11,10 @@ foreign_type! {
   pub unsafe type Dispatch: Send + Sync
   {
       type CType = pl_dispatch;
-      fn drop = pl_dispatch_destroy;
+      fn drop = |x: *mut pl_dispatch| {
+        pl_dispatch_destroy(x);
+        drop(Box::from_raw(x));
+      };
   }
 }
 
@@ -28,11 +29,9 @@ impl Dispatch {
   /// Will panic if `pl_dispatch_create()` returns a null pointer.
   #[must_use]
   pub fn new(log: &Log, gpu: &pl_gpu) -> Self {
-    unsafe {
-      let mut ptr = pl_dispatch_create(log.0, *gpu);
-      assert!(!ptr.is_null());
-      Self(NonNull::new_unchecked(&mut ptr))
-    }
+    let ptr = unsafe { pl_dispatch_create(log.0, *gpu) };
+    assert!(!ptr.is_null());
+    unsafe { Self::from_ptr(Box::into_raw(Box::new(ptr))) }
   }
 
   /// Returns a blank `pl_shader` object, suitable for recording rendering
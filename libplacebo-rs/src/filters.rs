@@ -0,0 +1,28 @@
+use std::ffi::CStr;
+
+use libplacebo_sys::{pl_filter_config, pl_filter_usage, pl_find_filter_config};
+
+/// Finds a built-in filter configuration by name, e.g. `ewa_lanczos` for
+/// scaling or `oversample` for frame mixing. Returns `None` if there is no
+/// such filter, or if it does not support `usage`.
+#[must_use]
+pub fn find_filter_config(
+  name: &CStr,
+  usage: pl_filter_usage,
+) -> Option<&'static pl_filter_config> {
+  // Built-in configurations are statically allocated by libplacebo.
+  unsafe { pl_find_filter_config(name.as_ptr(), usage).as_ref() }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn finds_frame_mixers() {
+    let usage = pl_filter_usage::PL_FILTER_FRAME_MIXING;
+    assert!(find_filter_config(c"oversample", usage).is_some());
+    assert!(find_filter_config(c"mitchell_clamp", usage).is_some());
+    assert!(find_filter_config(c"not_a_filter", usage).is_none());
+  }
+}
@@ -1,6 +1,155 @@
+use std::ffi::CStr;
 use std::ptr::NonNull;
 
-use libplacebo_sys::{pl_fmt_t, pl_tex, pl_tex_params, pl_tex_t};
+use libplacebo_sys::{
+  pl_buf, pl_buf_params, pl_buf_t, pl_fmt, pl_fmt_caps, pl_fmt_t, pl_fmt_type, pl_glsl_version,
+  pl_gpu, pl_gpu_handle_caps, pl_gpu_limits, pl_gpu_t, pl_tex, pl_tex_params, pl_tex_t, pl_timer,
+  pl_timer_t,
+};
+
+/// A GPU, as seen by libplacebo. Borrowed from the context that created it,
+/// e.g. [`crate::vulkan::Vulkan::as_gpu`].
+#[derive(Clone, Copy)]
+pub struct Gpu(NonNull<pl_gpu_t>);
+
+impl Gpu {
+  /// # Safety
+  ///
+  /// `ptr` must point to a `pl_gpu_t` that outlives the returned value.
+  pub const unsafe fn new_unchecked(ptr: *mut pl_gpu_t) -> Self {
+    Self(NonNull::new_unchecked(ptr))
+  }
+
+  #[must_use]
+  pub const fn as_ptr(&self) -> pl_gpu {
+    self.0.as_ptr()
+  }
+
+  const fn raw(&self) -> &pl_gpu_t {
+    unsafe { self.0.as_ref() }
+  }
+
+  /// GLSL version and compute shader capabilities.
+  #[must_use]
+  pub const fn glsl(&self) -> pl_glsl_version {
+    self.raw().glsl
+  }
+
+  /// Size, alignment and queue limits, plus miscellaneous capabilities like
+  /// `callbacks` and `thread_safe`.
+  #[must_use]
+  pub const fn limits(&self) -> pl_gpu_limits {
+    self.raw().limits
+  }
+
+  /// Handle types that buffers, textures and semaphores can be imported from.
+  #[must_use]
+  pub const fn import_caps(&self) -> pl_gpu_handle_caps {
+    self.raw().import_caps
+  }
+
+  /// Handle types that buffers, textures and semaphores can be exported to.
+  #[must_use]
+  pub const fn export_caps(&self) -> pl_gpu_handle_caps {
+    self.raw().export_caps
+  }
+
+  /// Every texture format the GPU supports, sorted from best to worst.
+  #[must_use]
+  #[allow(clippy::cast_sign_loss)]
+  pub fn formats(&self) -> Vec<Format> {
+    let gpu = self.raw();
+    if gpu.formats.is_null() {
+      return Vec::new();
+    }
+
+    unsafe { std::slice::from_raw_parts(gpu.formats, gpu.num_formats as usize) }
+      .iter()
+      .filter_map(|&format| Format::from_ptr(format))
+      .collect()
+  }
+}
+
+/// A texture format supported by a GPU. Formats are owned by the GPU and stay
+/// valid for as long as it does.
+#[derive(Clone, Copy)]
+pub struct Format(NonNull<pl_fmt_t>);
+
+impl Format {
+  /// # Safety
+  ///
+  /// `ptr` must point to a `pl_fmt_t` owned by a live GPU.
+  pub const unsafe fn new_unchecked(ptr: *mut pl_fmt_t) -> Self {
+    Self(NonNull::new_unchecked(ptr))
+  }
+
+  pub(crate) fn from_ptr(ptr: pl_fmt) -> Option<Self> {
+    NonNull::new(ptr.cast_mut()).map(Self)
+  }
+
+  /// Note that this is the exact pointer handed out by libplacebo, which must
+  /// be passed back unchanged, e.g. in `pl_tex_params.format`.
+  #[must_use]
+  pub const fn as_ptr(&self) -> pl_fmt {
+    self.0.as_ptr()
+  }
+
+  const fn raw(&self) -> &pl_fmt_t {
+    unsafe { self.0.as_ref() }
+  }
+
+  /// Name of the format, e.g. `rgba16f`.
+  #[must_use]
+  pub fn name(&self) -> &CStr {
+    unsafe { CStr::from_ptr(self.raw().name) }
+  }
+
+  #[must_use]
+  pub const fn type_(&self) -> pl_fmt_type {
+    self.raw().type_
+  }
+
+  #[must_use]
+  pub const fn caps(&self) -> pl_fmt_caps {
+    self.raw().caps
+  }
+
+  /// Whether the format has all of `caps`.
+  #[must_use]
+  pub const fn supports(&self, caps: pl_fmt_caps) -> bool {
+    self.raw().caps.0 & caps.0 == caps.0
+  }
+
+  #[must_use]
+  pub const fn num_components(&self) -> i32 {
+    self.raw().num_components
+  }
+
+  /// Number of meaningful bits of each component, as stored on the GPU.
+  #[must_use]
+  pub const fn component_depth(&self) -> [i32; 4] {
+    self.raw().component_depth
+  }
+
+  /// Number of bits each component occupies in host memory, including
+  /// padding. Zero for opaque formats.
+  #[must_use]
+  pub const fn host_bits(&self) -> [i32; 4] {
+    self.raw().host_bits
+  }
+
+  /// Size of a single texel in host memory, in bytes.
+  #[must_use]
+  pub const fn texel_size(&self) -> usize {
+    self.raw().texel_size
+  }
+
+  /// Whether the format can be used with `textureGather`.
+  #[must_use]
+  pub const fn gatherable(&self) -> bool {
+    self.raw().gatherable
+  }
+}
 
 #[derive(Clone)]
 pub struct Tex(NonNull<pl_tex_t>);
@@ -20,19 +169,15 @@ impl Tex {
 
   /// # Panics
   ///
-  /// TODO
+  /// Will panic if the texture has no format.
   #[must_use]
-  pub fn format(&self) -> pl_fmt_t {
-    unsafe {
-      let params = self.params();
-      assert!(!params.format.is_null());
-      *(params.format)
-    }
+  pub fn format(&self) -> Format {
+    Format::from_ptr(self.params().format).expect("texture has no format")
   }
 
   #[must_use]
   pub fn num_components(&self) -> i32 {
-    self.format().num_components
+    self.format().num_components()
   }
 
   /// # Panics
@@ -55,4 +200,63 @@ impl Tex {
   pub fn width(&self) -> i32 {
     self.params().w
   }
+
+  #[must_use]
+  pub fn height(&self) -> i32 {
+    self.params().h
+  }
+}
+
+#[derive(Clone)]
+pub struct Buf(NonNull<pl_buf_t>);
+
+impl Buf {
+  /// # Safety
+  ///
+  /// `ptr` must point to a live `pl_buf_t`.
+  pub const unsafe fn new_unchecked(ptr: *mut pl_buf_t) -> Self {
+    Self(NonNull::new_unchecked(ptr))
+  }
+
+  #[must_use]
+  pub const fn as_ptr(&self) -> pl_buf {
+    self.0.as_ptr()
+  }
+
+  #[must_use]
+  pub fn params(&self) -> pl_buf_params {
+    unsafe { (*self.as_ptr()).params }
+  }
+
+  #[must_use]
+  pub fn size(&self) -> usize {
+    self.params().size
+  }
+
+  /// Persistently mapped host memory of the buffer. Only available for
+  /// buffers created with `host_mapped`, and only safe to access while the
+  /// buffer is not in use by the GPU (see `Vulkan::buf_poll`).
+  #[must_use]
+  pub fn data(&self) -> Option<NonNull<u8>> {
+    NonNull::new(unsafe { (*self.as_ptr()).data })
+  }
+}
+
+/// Measures the GPU time of the operations it is attached to, e.g. through
+/// `pl_dispatch_params.timer`. Results arrive asynchronously and are read
+/// with `Vulkan::timer_query`.
+pub struct Timer(NonNull<pl_timer_t>);
+
+impl Timer {
+  /// # Safety
+  ///
+  /// `ptr` must point to a live `pl_timer_t`.
+  pub const unsafe fn new_unchecked(ptr: *mut pl_timer_t) -> Self {
+    Self(NonNull::new_unchecked(ptr))
+  }
+
+  #[must_use]
+  pub const fn as_ptr(&self) -> pl_timer {
+    self.0.as_ptr()
+  }
 }
@@ -8,11 +8,14 @@ pub mod utils;
 
 pub mod colorspace;
 pub mod dispatch;
+pub mod filters;
 pub mod gpu;
 pub mod log;
 pub mod options;
 pub mod renderer;
 pub mod shaders_root;
+pub mod version;
+#[cfg(feature = "vulkan")]
 pub mod vulkan;
 
 #[cfg(test)]
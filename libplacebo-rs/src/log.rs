@@ -3,9 +3,10 @@ use std::{
   os::raw::{c_char, c_void},
 };
 
-use libplacebo_sys::{
-  pl_log, pl_log_create_349, pl_log_destroy, pl_log_level, pl_log_params, PL_API_VER,
-};
+use libplacebo_sys::{pl_log, pl_log_create, pl_log_destroy, pl_log_level, pl_log_params};
+use miette::{miette, Result};
+
+use crate::version::{check_api_version, API_VERSION};
 
 /// # Safety
 ///
@@ -25,20 +26,37 @@ unsafe impl Send for Log {}
 unsafe impl Sync for Log {}
 
 impl Log {
+  /// Like [`Self::try_new`], but panics on failure.
+  ///
   /// # Panics
   ///
-  /// TODO
+  /// Will panic if `api_ver` is not supported (see
+  /// [`check_api_version`]), or if `pl_log_create()` fails.
   #[must_use]
   pub fn new(api_ver: i32) -> Self {
-    unsafe {
-      let params = pl_log_params {
-        log_cb: Some(log_cb),
-        log_level: pl_log_level::PL_LOG_ERR,
-        ..pl_log_params::default()
-      };
-      let ptr = pl_log_create_349(api_ver, &params);
-      assert!(!ptr.is_null());
-      Self(ptr)
+    Self::try_new(api_ver).unwrap_or_else(|err| panic!("{err}"))
+  }
+
+  /// Creates a log for callers written against libplacebo API version
+  /// `api_ver`, which must be the version of the bindings.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if `api_ver` is not supported (see
+  /// [`check_api_version`]), or if `pl_log_create()` returns a null pointer.
+  pub fn try_new(api_ver: i32) -> Result<Self> {
+    check_api_version(api_ver)?;
+
+    let params = pl_log_params {
+      log_cb: Some(log_cb),
+      log_level: pl_log_level::PL_LOG_ERR,
+      ..pl_log_params::default()
+    };
+    let ptr = unsafe { pl_log_create(api_ver, &params) };
+    if ptr.is_null() {
+      Err(miette!("Failed to create log."))
+    } else {
+      Ok(Self(ptr))
     }
   }
 
@@ -51,7 +69,7 @@ impl Log {
 impl Default for Log {
   #[allow(clippy::cast_possible_wrap)]
   fn default() -> Self {
-    Self::new(PL_API_VER as i32)
+    Self::new(API_VERSION as i32)
   }
 }
 
@@ -78,4 +96,9 @@ mod tests {
     assert_trace_thru_ref(&log);
     assert!(!log.0.is_null());
   }
+
+  #[test]
+  fn rejects_other_api_versions() {
+    assert!(Log::try_new(0).is_err());
+  }
 }
@@ -8,14 +8,24 @@ foreign_type! {
   pub unsafe type Options
   {
     type CType = pl_options;
-    fn drop = pl_options_free;
+    fn drop = |x: *mut pl_options| {
+      pl_options_free(x);
+      drop(Box::from_raw(x));
+    };
   }
 }
 
 impl Options {
+  /// Requires libplacebo API version 309 or newer.
+  ///
+  /// # Panics
+  ///
+  /// Will panic if `pl_options_alloc()` returns a null pointer.
   #[must_use]
   pub fn new(log: &Log) -> Self {
-    unsafe { Self::from_ptr(&mut pl_options_alloc(log.0)) }
+    let ptr = unsafe { pl_options_alloc(log.0) };
+    assert!(!ptr.is_null());
+    unsafe { Self::from_ptr(Box::into_raw(Box::new(ptr))) }
   }
 }
 
@@ -1,14 +1,21 @@
 use foreign_types::{foreign_type, ForeignType};
-use libplacebo_sys::{pl_gpu, pl_renderer, pl_renderer_create, pl_renderer_destroy};
+use libplacebo_sys::{
+  pl_frame, pl_frame_mix, pl_frame_mix_radius, pl_gpu, pl_render_image, pl_render_image_mix,
+  pl_render_params, pl_renderer, pl_renderer_create, pl_renderer_destroy, pl_renderer_flush_cache,
+};
+use miette::{miette, Result};
 
 use crate::log::Log;
 
 foreign_type! {
   /// Thread-safety: unsafe.
-  pub unsafe type Renderer
+  pub unsafe type Renderer: Send
   {
     type CType = pl_renderer;
-    fn drop = pl_renderer_destroy;
+    fn drop = |x: *mut pl_renderer| {
+      pl_renderer_destroy(x);
+      drop(Box::from_raw(x));
+    };
   }
 }
 
@@ -25,8 +32,61 @@ impl Renderer {
     assert!(!log.0.is_null());
     assert!(!gpu.is_null());
 
-    let mut ptr = unsafe { pl_renderer_create(log.0, *gpu) };
+    let ptr = unsafe { pl_renderer_create(log.0, *gpu) };
     assert!(!ptr.is_null());
-    unsafe { Self::from_ptr(&mut ptr) }
+    unsafe { Self::from_ptr(Box::into_raw(Box::new(ptr))) }
   }
+
+  /// Flushes the internal frame cache. Needed whenever frame signatures are
+  /// reused for different contents, e.g. after resetting a `pl_queue`.
+  pub fn flush_cache(&mut self) {
+    unsafe { pl_renderer_flush_cache(*self.as_ptr()) }
+  }
+
+  /// Renders a single frame to `target`, performing any conversion needed
+  /// between their color spaces, representations, sizes and chroma locations.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if `pl_render_image()` is unsuccessful.
+  pub fn render_image(
+    &mut self,
+    image: &pl_frame,
+    target: &pl_frame,
+    params: &pl_render_params,
+  ) -> Result<()> {
+    if unsafe { pl_render_image(*self.as_ptr(), image, target, params) } {
+      Ok(())
+    } else {
+      Err(miette!("Failed to render frame."))
+    }
+  }
+
+  /// Renders a mixture of frames to `target`, blending them together according
+  /// to `params.frame_mixer`. The frames and their timestamps are typically
+  /// produced by [`crate::utils::frame_queue::Queue::update`].
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if `pl_render_image_mix()` is unsuccessful.
+  pub fn render_image_mix(
+    &mut self,
+    images: &pl_frame_mix,
+    target: &pl_frame,
+    params: &pl_render_params,
+  ) -> Result<()> {
+    if unsafe { pl_render_image_mix(*self.as_ptr(), images, target, params) } {
+      Ok(())
+    } else {
+      Err(miette!("Failed to render frame mix."))
+    }
+  }
+}
+
+/// Radius of the frame mixer configured in `params`, in units of the output
+/// frame duration. Frames further than this from the target timestamp do not
+/// contribute to the output.
+#[must_use]
+pub fn frame_mix_radius(params: &pl_render_params) -> f32 {
+  unsafe { pl_frame_mix_radius(params) }
 }
@@ -0,0 +1,491 @@
+use std::{
+  collections::HashSet,
+  ffi::{CStr, CString},
+  marker::PhantomData,
+  mem::size_of,
+  ptr::{from_ref, null},
+  slice,
+};
+
+use libplacebo_sys::{
+  pl_buffer_var, pl_custom_shader, pl_desc, pl_desc_access, pl_desc_binding, pl_desc_type,
+  pl_shader_const, pl_shader_desc, pl_shader_sig, pl_shader_var, pl_std140_layout,
+  pl_std430_layout, pl_tex_address_mode, pl_tex_sample_mode, pl_var, pl_var_type,
+};
+use miette::{miette, IntoDiagnostic, Result};
+
+use crate::{
+  gpu::{Buf, Tex},
+  shaders_root::Shader,
+};
+
+/// Rust types that correspond to a GLSL variable type, for passing values to
+/// and declaring variables in custom shaders. Matrices are arrays of columns.
+///
+/// # Safety
+///
+/// `Self` must consist of exactly `DIM_V * DIM_M` tightly packed values of
+/// the type described by `TYPE`.
+pub unsafe trait ShaderValue: Copy {
+  const TYPE: pl_var_type;
+  const DIM_V: i32;
+  const DIM_M: i32 = 1;
+
+  /// Describes a variable of this type named `name`, as an array of `dim_a`
+  /// elements.
+  fn var(name: &CStr, dim_a: i32) -> pl_var {
+    pl_var {
+      name: name.as_ptr(),
+      type_: Self::TYPE,
+      dim_v: Self::DIM_V,
+      dim_m: Self::DIM_M,
+      dim_a,
+    }
+  }
+}
+
+/// Scalar [`ShaderValue`]s, the only ones allowed as specialization
+/// constants.
+pub trait ShaderScalar: ShaderValue {}
+
+macro_rules! impl_shader_value {
+  ($($t:ty => $var:ident),*) => {
+    $(
+      unsafe impl ShaderValue for $t {
+        const TYPE: pl_var_type = pl_var_type::$var;
+        const DIM_V: i32 = 1;
+      }
+
+      impl ShaderScalar for $t {}
+
+      unsafe impl<const N: usize> ShaderValue for [$t; N] {
+        const TYPE: pl_var_type = pl_var_type::$var;
+        #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+        const DIM_V: i32 = {
+          assert!(N >= 2 && N <= 4, "vectors must have 2 to 4 components");
+          N as i32
+        };
+      }
+    )*
+  };
+}
+
+impl_shader_value!(f32 => PL_VAR_FLOAT, i32 => PL_VAR_SINT, u32 => PL_VAR_UINT);
+
+unsafe impl<const N: usize> ShaderValue for [[f32; N]; N] {
+  const TYPE: pl_var_type = pl_var_type::PL_VAR_FLOAT;
+  #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+  const DIM_V: i32 = {
+    assert!(N >= 2 && N <= 4, "matrices must be 2x2 to 4x4");
+    N as i32
+  };
+  const DIM_M: i32 = Self::DIM_V;
+}
+
+fn value_bytes<T: ShaderValue>(value: &T) -> Vec<u8> {
+  unsafe { slice::from_raw_parts(from_ref(value).cast::<u8>(), size_of::<T>()) }.to_vec()
+}
+
+/// Which kind of buffer a buffer descriptor binds.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum BufferKind {
+  /// A read-only uniform buffer, laid out according to std140.
+  Uniform,
+
+  /// A storage buffer, laid out according to std430.
+  Storage(pl_desc_access),
+}
+
+/// A GLSL snippet, plus everything it refers to, to be inserted into a
+/// [`Shader`] with [`CustomShader::apply`]. This checks what it can up front,
+/// like identifier names and whether bound objects support how they are used,
+/// instead of leaving it to shader compilation.
+///
+/// The body works like any other shader stage: it reads `vec4 color` if
+/// `input` is `PL_SHADER_SIG_COLOR`, and must write it if `output` is.
+pub struct CustomShader<'a> {
+  description: CString,
+  header: Option<CString>,
+  body: CString,
+  input: pl_shader_sig,
+  output: pl_shader_sig,
+  output_size: (i32, i32),
+  compute_group_size: [i32; 2],
+  compute_shmem: usize,
+
+  /// Owns the names and values the arrays below point to.
+  names: Vec<CString>,
+  data: Vec<Vec<u8>>,
+  buffer_vars: Vec<Vec<pl_buffer_var>>,
+  identifiers: HashSet<String>,
+
+  variables: Vec<pl_shader_var>,
+  descriptors: Vec<pl_shader_desc>,
+  constants: Vec<pl_shader_const>,
+
+  /// Bound textures and buffers must outlive the shader's dispatch.
+  objects: PhantomData<&'a ()>,
+}
+
+impl<'a> CustomShader<'a> {
+  /// Creates a shader stage running `body`, which takes `input` and produces
+  /// `output`.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if `description` or `body` contain a nul byte.
+  pub fn new(
+    description: &str,
+    body: &str,
+    input: pl_shader_sig,
+    output: pl_shader_sig,
+  ) -> Result<Self> {
+    Ok(Self {
+      description: CString::new(description).into_diagnostic()?,
+      header: None,
+      body: CString::new(body).into_diagnostic()?,
+      input,
+      output,
+      output_size: (0, 0),
+      compute_group_size: [0; 2],
+      compute_shmem: 0,
+      names: Vec::new(),
+      data: Vec::new(),
+      buffer_vars: Vec::new(),
+      identifiers: HashSet::new(),
+      variables: Vec::new(),
+      descriptors: Vec::new(),
+      constants: Vec::new(),
+      objects: PhantomData,
+    })
+  }
+
+  /// GLSL inserted before the shader's function, e.g. helper functions.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if `header` contains a nul byte.
+  pub fn header(mut self, header: &str) -> Result<Self> {
+    self.header = Some(CString::new(header).into_diagnostic()?);
+    Ok(self)
+  }
+
+  /// Size of the shader's output. Required when `input` is
+  /// `PL_SHADER_SIG_NONE`, as there is nothing to infer it from.
+  #[must_use]
+  pub const fn output_size(mut self, width: i32, height: i32) -> Self {
+    self.output_size = (width, height);
+    self
+  }
+
+  /// Runs the shader as a compute shader with the given work group size and
+  /// amount of shared memory, in bytes.
+  #[must_use]
+  pub const fn compute(mut self, group_size: [i32; 2], shmem: usize) -> Self {
+    self.compute_group_size = group_size;
+    self.compute_shmem = shmem;
+    self
+  }
+
+  /// Claims `name` for a new identifier.
+  fn name(&mut self, name: &str) -> Result<*const std::os::raw::c_char> {
+    let valid = name
+      .chars()
+      .next()
+      .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+      && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+      return Err(miette!("{name:?} is not a valid GLSL identifier."));
+    }
+
+    if !self.identifiers.insert(name.to_owned()) {
+      return Err(miette!("{name} is bound more than once."));
+    }
+
+    let name = CString::new(name).into_diagnostic()?;
+    let ptr = name.as_ptr();
+    self.names.push(name);
+    Ok(ptr)
+  }
+
+  /// Declares a uniform `name` holding `value`. Dynamic variables are
+  /// expected to change between dispatches, which libplacebo takes into
+  /// account when deciding how to pass them.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if `name` is invalid or already in use.
+  pub fn uniform<T: ShaderValue>(mut self, name: &str, value: T, dynamic: bool) -> Result<Self> {
+    let name = self.name(name)?;
+    let data = value_bytes(&value);
+
+    self.variables.push(pl_shader_var {
+      var: pl_var {
+        name,
+        ..T::var(c"", 1)
+      },
+      data: data.as_ptr().cast(),
+      dynamic,
+    });
+    self.data.push(data);
+    Ok(self)
+  }
+
+  /// Declares a specialization constant `name` holding `value`. If
+  /// `compile_time` is set, the value is always hard-coded into the shader.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if `name` is invalid or already in use.
+  pub fn constant<T: ShaderScalar>(
+    mut self,
+    name: &str,
+    value: T,
+    compile_time: bool,
+  ) -> Result<Self> {
+    let name = self.name(name)?;
+    let data = value_bytes(&value);
+
+    self.constants.push(pl_shader_const {
+      type_: T::TYPE,
+      name,
+      data: data.as_ptr().cast(),
+      compile_time,
+    });
+    self.data.push(data);
+    Ok(self)
+  }
+
+  fn descriptor(
+    &mut self,
+    name: &str,
+    type_: pl_desc_type,
+    access: pl_desc_access,
+    binding: pl_desc_binding,
+  ) -> Result<()> {
+    let name = self.name(name)?;
+    self.descriptors.push(pl_shader_desc {
+      desc: pl_desc {
+        name,
+        type_,
+        access,
+        ..pl_desc::default()
+      },
+      binding,
+      ..pl_shader_desc::default()
+    });
+    Ok(())
+  }
+
+  /// Binds `tex` as `sampler2D name` (or the sampler type matching its
+  /// dimensions).
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if `name` is invalid or already in use, or if `tex` is
+  /// not sampleable.
+  pub fn texture(
+    mut self,
+    name: &str,
+    tex: &'a Tex,
+    sample_mode: pl_tex_sample_mode,
+    address_mode: pl_tex_address_mode,
+  ) -> Result<Self> {
+    if !tex.sampleable() {
+      return Err(miette!("texture {name} is not sampleable."));
+    }
+
+    self.descriptor(
+      name,
+      pl_desc_type::PL_DESC_SAMPLED_TEX,
+      pl_desc_access::PL_DESC_ACCESS_READONLY,
+      pl_desc_binding {
+        object: tex.as_ptr().cast(),
+        address_mode,
+        sample_mode,
+      },
+    )?;
+    Ok(self)
+  }
+
+  /// Binds `tex` as `image2D name` (or the image type matching its
+  /// dimensions).
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if `name` is invalid or already in use, or if `tex` is
+  /// not storable.
+  pub fn image(mut self, name: &str, tex: &'a Tex, access: pl_desc_access) -> Result<Self> {
+    if !tex.params().storable {
+      return Err(miette!("texture {name} is not storable."));
+    }
+
+    self.descriptor(
+      name,
+      pl_desc_type::PL_DESC_STORAGE_IMG,
+      access,
+      pl_desc_binding {
+        object: tex.as_ptr().cast(),
+        ..pl_desc_binding::default()
+      },
+    )?;
+    Ok(self)
+  }
+
+  /// Binds `buf` as buffer block `name`, whose members are `vars`. Members are
+  /// laid out as `kind` requires; see [`ShaderValue::var`] for describing
+  /// them.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if a name is invalid or already in use, if `buf` was
+  /// not created for `kind`, or if it is too small to hold `vars`.
+  pub fn buffer(
+    mut self,
+    name: &str,
+    buf: &'a Buf,
+    kind: BufferKind,
+    vars: &[pl_var],
+  ) -> Result<Self> {
+    let params = buf.params();
+    let (type_, access) = match kind {
+      BufferKind::Uniform if params.uniform => (
+        pl_desc_type::PL_DESC_BUF_UNIFORM,
+        pl_desc_access::PL_DESC_ACCESS_READONLY,
+      ),
+      BufferKind::Storage(access) if params.storable => (pl_desc_type::PL_DESC_BUF_STORAGE, access),
+      _ => {
+        return Err(miette!(
+          "buffer {name} does not support this kind of binding."
+        ))
+      }
+    };
+
+    let mut offset = 0;
+    let mut buffer_vars = Vec::with_capacity(vars.len());
+    for var in vars {
+      let member = unsafe { CStr::from_ptr(var.name) }
+        .to_str()
+        .into_diagnostic()?
+        .to_owned();
+      let var = pl_var {
+        name: self.name(&member)?,
+        ..*var
+      };
+
+      let layout = unsafe {
+        match kind {
+          BufferKind::Uniform => pl_std140_layout(offset, &var),
+          BufferKind::Storage(_) => pl_std430_layout(offset, &var),
+        }
+      };
+      offset = layout.offset + layout.size;
+      buffer_vars.push(pl_buffer_var { var, layout });
+    }
+
+    if offset > buf.size() {
+      return Err(miette!(
+        "buffer {name} holds {} bytes, but its members need {offset}.",
+        buf.size()
+      ));
+    }
+
+    self.descriptor(
+      name,
+      type_,
+      access,
+      pl_desc_binding {
+        object: buf.as_ptr().cast(),
+        ..pl_desc_binding::default()
+      },
+    )?;
+
+    let descriptor = self.descriptors.last_mut().unwrap();
+    descriptor.buffer_vars = buffer_vars.as_ptr();
+    descriptor.num_buffer_vars = i32::try_from(buffer_vars.len()).into_diagnostic()?;
+    self.buffer_vars.push(buffer_vars);
+    Ok(self)
+  }
+
+  /// Inserts the shader stage into `shader`.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if `pl_shader_custom()` is unsuccessful, e.g. because
+  /// the signature or size does not fit the current state of `shader`.
+  #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+  pub fn apply(&self, shader: &mut Shader) -> Result<()> {
+    shader.custom(&pl_custom_shader {
+      description: self.description.as_ptr(),
+      header: self
+        .header
+        .as_ref()
+        .map_or(null(), |header| header.as_ptr()),
+      body: self.body.as_ptr(),
+      input: self.input,
+      output: self.output,
+      output_w: self.output_size.0,
+      output_h: self.output_size.1,
+      compute_group_size: self.compute_group_size,
+      compute_shmem: self.compute_shmem,
+      variables: self.variables.as_ptr(),
+      num_variables: self.variables.len() as i32,
+      descriptors: self.descriptors.as_ptr(),
+      num_descriptors: self.descriptors.len() as i32,
+      constants: self.constants.as_ptr(),
+      num_constants: self.constants.len() as i32,
+      ..pl_custom_shader::default()
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn shader() -> CustomShader<'static> {
+    CustomShader::new(
+      "test",
+      "color.rgb *= gain;",
+      pl_shader_sig::PL_SHADER_SIG_COLOR,
+      pl_shader_sig::PL_SHADER_SIG_COLOR,
+    )
+    .unwrap()
+  }
+
+  #[test]
+  fn value_types() {
+    let var = <[f32; 3]>::var(c"v", 1);
+    assert_eq!(var.type_, pl_var_type::PL_VAR_FLOAT);
+    assert_eq!((var.dim_v, var.dim_m), (3, 1));
+
+    let var = <[[f32; 4]; 4]>::var(c"m", 2);
+    assert_eq!((var.dim_v, var.dim_m, var.dim_a), (4, 4, 2));
+
+    assert_eq!(<u32>::TYPE, pl_var_type::PL_VAR_UINT);
+    assert_eq!(value_bytes(&[1i32, 2]), [1, 0, 0, 0, 2, 0, 0, 0]);
+  }
+
+  #[test]
+  fn identifiers_are_checked() {
+    assert!(shader().uniform("gain", 2.0f32, false).is_ok());
+    assert!(shader().uniform("2gain", 2.0f32, false).is_err());
+    assert!(shader().uniform("", 2.0f32, false).is_err());
+    assert!(shader()
+      .uniform("gain", 2.0f32, false)
+      .unwrap()
+      .constant("gain", 1u32, false)
+      .is_err());
+  }
+
+  #[test]
+  fn uniforms_keep_their_values() {
+    let shader = shader().uniform("gain", [0.5f32, 1.0, 2.0], true).unwrap();
+    let var = &shader.variables[0];
+    assert!(var.dynamic);
+    assert_eq!(var.var.dim_v, 3);
+
+    let values = unsafe { slice::from_raw_parts(var.data.cast::<f32>(), 3) };
+    assert_eq!(values, [0.5, 1.0, 2.0]);
+  }
+}
@@ -0,0 +1,398 @@
+//! Render hooks (`pl_hook`) implemented in Rust. A [`Hook`] is called by the
+//! renderer at the stages it asks for, and may modify the image there by
+//! continuing the stage's shader or by replacing its texture.
+
+use std::{
+  ffi::c_void,
+  panic::{catch_unwind, AssertUnwindSafe},
+  ptr::{null, null_mut},
+  sync::Mutex,
+};
+
+use libplacebo_sys::{
+  pl_color_repr, pl_color_space, pl_dispatch_begin, pl_hook, pl_hook_params, pl_hook_res,
+  pl_hook_sig, pl_hook_stage, pl_rect2df, pl_render_params, pl_tex_t,
+};
+use miette::Result;
+
+use crate::{
+  gpu::{Gpu, Tex},
+  shaders_root::Shader,
+};
+
+/// A custom render stage. The renderer calls [`Hook::hook`] for every stage
+/// in [`Hook::stages`] it runs; the implementation keeps whatever state it
+/// needs across calls.
+pub trait Hook: Send {
+  /// Stages to hook, combined with `|`.
+  fn stages(&self) -> pl_hook_stage;
+
+  /// How the stage's image should be passed in: as a shader that leaves it in
+  /// `vec4 color` (`PL_HOOK_SIG_COLOR`) or as a texture (`PL_HOOK_SIG_TEX`).
+  fn input(&self) -> pl_hook_sig {
+    pl_hook_sig::PL_HOOK_SIG_COLOR
+  }
+
+  /// Drops any state carried over between frames. Called by the renderer
+  /// when its own state is reset, e.g. after seeking.
+  fn reset(&mut self) {}
+
+  /// Runs the hook for one stage. Returning an error makes the renderer
+  /// skip this hook for the stage.
+  ///
+  /// # Errors
+  ///
+  /// Implementation defined.
+  fn hook(&mut self, params: &HookParams) -> Result<HookResult>;
+}
+
+/// The stage a [`Hook`] is called for, with its image.
+pub struct HookParams<'a>(&'a pl_hook_params);
+
+impl HookParams<'_> {
+  #[must_use]
+  pub const fn raw(&self) -> &pl_hook_params {
+    self.0
+  }
+
+  #[must_use]
+  pub const fn stage(&self) -> pl_hook_stage {
+    self.0.stage
+  }
+
+  #[must_use]
+  pub fn gpu(&self) -> Gpu {
+    unsafe { Gpu::new_unchecked(self.0.gpu.cast_mut()) }
+  }
+
+  /// The stage's shader, for hooks with a `PL_HOOK_SIG_COLOR` input. It can
+  /// be continued and returned as [`HookOutput::Color`].
+  #[must_use]
+  pub fn shader(&self) -> Option<Shader> {
+    (!self.0.sh.is_null()).then(|| Shader::from_ptr(self.0.sh))
+  }
+
+  /// The stage's texture, for hooks with a `PL_HOOK_SIG_TEX` input.
+  #[must_use]
+  pub fn tex(&self) -> Option<Tex> {
+    (!self.0.tex.is_null()).then(|| unsafe { Tex::new_unchecked(self.0.tex.cast_mut()) })
+  }
+
+  /// Starts a new shader on the renderer's dispatch, to be returned as
+  /// [`HookOutput::Color`].
+  #[must_use]
+  pub fn begin_shader(&self) -> Shader {
+    Shader::from_ptr(unsafe { pl_dispatch_begin(self.0.dispatch) })
+  }
+
+  /// Gets a temporary texture from the renderer, e.g. to render the stage
+  /// into before returning it as [`HookOutput::Tex`]. It stays valid until
+  /// the frame is done rendering.
+  #[must_use]
+  pub fn get_tex(&self, width: i32, height: i32) -> Option<Tex> {
+    let get_tex = self.0.get_tex?;
+    let tex: *const pl_tex_t = unsafe { get_tex(self.0.priv_, width, height) };
+    (!tex.is_null()).then(|| unsafe { Tex::new_unchecked(tex.cast_mut()) })
+  }
+
+  #[must_use]
+  pub const fn repr(&self) -> pl_color_repr {
+    self.0.repr
+  }
+
+  #[must_use]
+  pub const fn color(&self) -> pl_color_space {
+    self.0.color
+  }
+
+  #[must_use]
+  pub const fn components(&self) -> i32 {
+    self.0.components
+  }
+
+  /// Region of the stage's image that is actually used.
+  #[must_use]
+  pub const fn rect(&self) -> pl_rect2df {
+    self.0.rect
+  }
+}
+
+/// What a [`Hook`] replaces the stage's image with.
+pub enum HookOutput {
+  /// Leaves the image as it was.
+  Unchanged,
+
+  /// A shader that leaves the new image in `vec4 color`.
+  Color(Shader),
+
+  /// A texture holding the new image.
+  Tex(Tex),
+}
+
+/// The output of a [`Hook`], along with the properties of the new image.
+/// Properties left as `None` are taken from the stage's input.
+pub struct HookResult {
+  pub output: HookOutput,
+  pub repr: Option<pl_color_repr>,
+  pub color: Option<pl_color_space>,
+  pub components: Option<i32>,
+  pub rect: Option<pl_rect2df>,
+}
+
+impl HookResult {
+  #[must_use]
+  pub const fn unchanged() -> Self {
+    Self::from_output(HookOutput::Unchanged)
+  }
+
+  const fn from_output(output: HookOutput) -> Self {
+    Self {
+      output,
+      repr: None,
+      color: None,
+      components: None,
+      rect: None,
+    }
+  }
+
+  fn into_raw(self, params: &pl_hook_params) -> pl_hook_res {
+    let (output, sh, tex) = match self.output {
+      HookOutput::Unchanged => (pl_hook_sig::PL_HOOK_SIG_NONE, null_mut(), null()),
+      HookOutput::Color(shader) => (pl_hook_sig::PL_HOOK_SIG_COLOR, shader.as_ptr(), null()),
+      HookOutput::Tex(tex) => (
+        pl_hook_sig::PL_HOOK_SIG_TEX,
+        null_mut(),
+        tex.as_ptr().cast_const(),
+      ),
+    };
+
+    pl_hook_res {
+      failed: false,
+      output,
+      sh,
+      tex,
+      repr: self.repr.unwrap_or(params.repr),
+      color: self.color.unwrap_or(params.color),
+      components: self.components.unwrap_or(params.components),
+      rect: self.rect.unwrap_or(params.rect),
+    }
+  }
+}
+
+impl From<HookOutput> for HookResult {
+  fn from(output: HookOutput) -> Self {
+    Self::from_output(output)
+  }
+}
+
+/// A [`Hook`] registered with libplacebo. The `pl_hook` and the
+/// implementation are boxed so that they keep their addresses.
+struct RegisteredHook {
+  raw: Box<pl_hook>,
+  _imp: Box<Mutex<Box<dyn Hook>>>,
+}
+
+impl RegisteredHook {
+  fn new(hook: Box<dyn Hook>) -> Self {
+    let raw = pl_hook {
+      stages: hook.stages(),
+      input: hook.input(),
+      ..Default::default()
+    };
+    let mut imp = Box::new(Mutex::new(hook));
+
+    Self {
+      raw: Box::new(pl_hook {
+        priv_: std::ptr::from_mut(&mut *imp).cast(),
+        reset: Some(reset_trampoline),
+        hook: Some(hook_trampoline),
+        ..raw
+      }),
+      _imp: imp,
+    }
+  }
+}
+
+/// Hooks to run during rendering, in order.
+#[derive(Default)]
+pub struct Hooks {
+  hooks: Vec<RegisteredHook>,
+
+  /// Pointers to each `pl_hook`, as `pl_render_params.hooks` wants them.
+  ptrs: Vec<*const pl_hook>,
+}
+
+// The pointers refer to the boxed hooks owned alongside them, whose
+// implementations are `Send` and only reached through a `Mutex`.
+unsafe impl Send for Hooks {}
+unsafe impl Sync for Hooks {}
+
+impl Hooks {
+  #[must_use]
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn push(&mut self, hook: impl Hook + 'static) {
+    let hook = RegisteredHook::new(Box::new(hook));
+    self.ptrs.push(std::ptr::from_ref(&*hook.raw));
+    self.hooks.push(hook);
+  }
+
+  #[must_use]
+  pub fn len(&self) -> usize {
+    self.hooks.len()
+  }
+
+  #[must_use]
+  pub fn is_empty(&self) -> bool {
+    self.hooks.is_empty()
+  }
+
+  /// Makes the renderer run these hooks. `self` must outlive rendering with
+  /// `params`.
+  #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+  pub fn attach(&self, params: &mut pl_render_params) {
+    params.hooks = self.ptrs.as_ptr();
+    params.num_hooks = self.ptrs.len() as i32;
+  }
+}
+
+// Panics must not unwind into libplacebo, so both trampolines catch them. A
+// hook that panicked (or whose lock is poisoned as a result) fails from then
+// on, until it is dropped.
+
+unsafe extern "C" fn hook_trampoline(
+  priv_: *mut c_void,
+  params: *const pl_hook_params,
+) -> pl_hook_res {
+  let imp = &*priv_.cast::<Mutex<Box<dyn Hook>>>();
+  let params = &*params;
+
+  let res = catch_unwind(AssertUnwindSafe(|| {
+    let mut imp = imp.lock().ok()?;
+    imp.hook(&HookParams(params)).ok()
+  }));
+
+  match res {
+    Ok(Some(res)) => res.into_raw(params),
+    _ => pl_hook_res {
+      failed: true,
+      ..Default::default()
+    },
+  }
+}
+
+unsafe extern "C" fn reset_trampoline(priv_: *mut c_void) {
+  let imp = &*priv_.cast::<Mutex<Box<dyn Hook>>>();
+
+  let _ = catch_unwind(AssertUnwindSafe(|| {
+    if let Ok(mut imp) = imp.lock() {
+      imp.reset();
+    }
+  }));
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use miette::miette;
+
+  struct Counter {
+    calls: u32,
+    fail_after: u32,
+  }
+
+  impl Hook for Counter {
+    fn stages(&self) -> pl_hook_stage {
+      pl_hook_stage::PL_HOOK_RGB_INPUT | pl_hook_stage::PL_HOOK_SCALED
+    }
+
+    fn reset(&mut self) {
+      self.calls = 0;
+    }
+
+    fn hook(&mut self, params: &HookParams) -> Result<HookResult> {
+      self.calls += 1;
+      if self.calls > self.fail_after {
+        return Err(miette!("too many calls"));
+      }
+
+      Ok(HookResult {
+        components: Some(params.components() + 1),
+        ..HookResult::unchanged()
+      })
+    }
+  }
+
+  struct Panics;
+
+  impl Hook for Panics {
+    fn stages(&self) -> pl_hook_stage {
+      pl_hook_stage::PL_HOOK_OUTPUT
+    }
+
+    fn hook(&mut self, _: &HookParams) -> Result<HookResult> {
+      panic!("hook panicked");
+    }
+  }
+
+  fn call(hook: &pl_hook, params: &pl_hook_params) -> pl_hook_res {
+    unsafe { hook.hook.unwrap()(hook.priv_, params) }
+  }
+
+  #[test]
+  fn hooks_are_attached() {
+    let mut hooks = Hooks::new();
+    hooks.push(Counter {
+      calls: 0,
+      fail_after: 1,
+    });
+    hooks.push(Panics);
+
+    let mut params = pl_render_params::default();
+    hooks.attach(&mut params);
+    assert_eq!(params.num_hooks, 2);
+
+    let first = unsafe { &**params.hooks };
+    assert_eq!(
+      first.stages,
+      pl_hook_stage::PL_HOOK_RGB_INPUT | pl_hook_stage::PL_HOOK_SCALED
+    );
+    assert_eq!(first.input, pl_hook_sig::PL_HOOK_SIG_COLOR);
+  }
+
+  #[test]
+  fn state_persists_until_reset() {
+    let mut hooks = Hooks::new();
+    hooks.push(Counter {
+      calls: 0,
+      fail_after: 1,
+    });
+    let hook = &*hooks.hooks[0].raw;
+    let params = pl_hook_params {
+      components: 3,
+      ..Default::default()
+    };
+
+    let res = call(hook, &params);
+    assert!(!res.failed);
+    assert_eq!(res.output, pl_hook_sig::PL_HOOK_SIG_NONE);
+    assert_eq!(res.components, 4);
+
+    assert!(call(hook, &params).failed);
+
+    unsafe { hook.reset.unwrap()(hook.priv_) };
+    assert!(!call(hook, &params).failed);
+  }
+
+  #[test]
+  fn panics_fail_the_hook() {
+    let mut hooks = Hooks::new();
+    hooks.push(Panics);
+    let hook = &*hooks.hooks[0].raw;
+
+    assert!(call(hook, &pl_hook_params::default()).failed);
+    assert!(call(hook, &pl_hook_params::default()).failed);
+  }
+}
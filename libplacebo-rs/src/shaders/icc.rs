@@ -0,0 +1,264 @@
+use std::path::Path;
+
+use libplacebo_sys::{
+  pl_color_primaries, pl_color_space, pl_color_transfer, pl_icc_close, pl_icc_object, pl_icc_open,
+  pl_icc_params, pl_icc_profile, pl_icc_profile_compute_signature,
+};
+use miette::{miette, IntoDiagnostic, Result};
+
+use crate::log::Log;
+
+/// Builds a `pl_icc_profile` referencing `data`, with its signature computed
+/// from the contents so that libplacebo can cache derived LUTs.
+fn profile(data: &[u8]) -> pl_icc_profile {
+  let mut profile = pl_icc_profile {
+    data: data.as_ptr().cast(),
+    len: data.len(),
+    ..pl_icc_profile::default()
+  };
+  unsafe { pl_icc_profile_compute_signature(&mut profile) };
+  profile
+}
+
+/// An opened ICC profile. Attach it to `pl_frame.icc` for the renderer, or use
+/// it directly through [`crate::shaders_root::Shader::icc_decode`] and
+/// [`crate::shaders_root::Shader::icc_encode`].
+///
+/// Requires libplacebo to be built with LittleCMS 2 (the `lcms` feature);
+/// otherwise opening any profile fails.
+pub struct Icc(pl_icc_object);
+
+unsafe impl Send for Icc {}
+unsafe impl Sync for Icc {}
+
+impl Icc {
+  /// Opens an ICC profile from its raw bytes. If `params` is left as `None`,
+  /// it defaults to `pl_icc_default_params`.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if `pl_icc_open()` fails, e.g. because the data is not
+  /// a valid ICC profile or libplacebo was built without LittleCMS 2.
+  pub fn open(log: &Log, data: &[u8], params: Option<&pl_icc_params>) -> Result<Self> {
+    let profile = profile(data);
+    let params = params.map_or(std::ptr::null(), std::ptr::from_ref);
+    let ptr = unsafe { pl_icc_open(log.0, &profile, params) };
+    if ptr.is_null() {
+      Err(miette!("Failed to open ICC profile."))
+    } else {
+      Ok(Self(ptr))
+    }
+  }
+
+  /// Reads and opens an ICC profile from a file.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if the file cannot be read or is not a usable ICC
+  /// profile.
+  pub fn from_file(
+    log: &Log,
+    path: impl AsRef<Path>,
+    params: Option<&pl_icc_params>,
+  ) -> Result<Self> {
+    let path = path.as_ref();
+    let data = std::fs::read(path).into_diagnostic()?;
+    Self::open(log, &data, params)
+      .map_err(|_| miette!("Failed to open ICC profile {}.", path.display()))
+  }
+
+  /// Replaces the profile with `data`. This is a no-op if `params` is `None`
+  /// and the profile is unchanged, so it is cheap to call for every frame.
+  ///
+  /// `pl_icc_update()` is not used, as it closes the old profile when the new
+  /// one fails to open.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if `data` can't be opened, in which case the old
+  /// profile is kept.
+  pub fn update(&mut self, log: &Log, data: &[u8], params: Option<&pl_icc_params>) -> Result<()> {
+    if params.is_none() && profile(data).signature == self.signature() {
+      return Ok(());
+    }
+
+    *self = Self::open(log, data, params).map_err(|_| miette!("Failed to update ICC profile."))?;
+    Ok(())
+  }
+
+  #[must_use]
+  pub const fn as_ptr(&self) -> pl_icc_object {
+    self.0
+  }
+
+  /// Detected color space of the profile, including its luminance range.
+  #[must_use]
+  pub fn csp(&self) -> pl_color_space {
+    unsafe { (*self.0).csp }
+  }
+
+  #[must_use]
+  pub fn primaries(&self) -> pl_color_primaries {
+    self.csp().primaries
+  }
+
+  #[must_use]
+  pub fn transfer(&self) -> pl_color_transfer {
+    self.csp().transfer
+  }
+
+  /// Minimum luminance of the profile, in cd/m².
+  #[must_use]
+  pub fn min_luma(&self) -> f32 {
+    self.csp().hdr.min_luma
+  }
+
+  /// Maximum luminance of the profile, in cd/m².
+  #[must_use]
+  pub fn max_luma(&self) -> f32 {
+    self.csp().hdr.max_luma
+  }
+
+  /// Best-fit gamma of the profile's tone response.
+  #[must_use]
+  pub fn gamma(&self) -> f32 {
+    unsafe { (*self.0).gamma }
+  }
+
+  /// Signature of the profile contents, used for caching.
+  #[must_use]
+  pub fn signature(&self) -> u64 {
+    unsafe { (*self.0).signature }
+  }
+}
+
+impl Drop for Icc {
+  fn drop(&mut self) {
+    unsafe {
+      pl_icc_close(&mut self.0);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[cfg(feature = "lcms")]
+  fn s15_fixed16(value: f64) -> [u8; 4] {
+    #[allow(clippy::cast_possible_truncation)]
+    ((value * 65536.0).round() as i32).to_be_bytes()
+  }
+
+  #[cfg(feature = "lcms")]
+  fn xyz(values: [f64; 3]) -> Vec<u8> {
+    let mut tag = b"XYZ \0\0\0\0".to_vec();
+    tag.extend(values.into_iter().flat_map(s15_fixed16));
+    tag
+  }
+
+  /// A minimal ICC v2 display profile with the BT.709 primaries (adapted to
+  /// D50, as ICC requires) and a pure power `gamma` tone response.
+  #[cfg(feature = "lcms")]
+  #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+  fn bt709_profile(gamma: f64) -> Vec<u8> {
+    let mut desc = b"desc\0\0\0\0".to_vec();
+    desc.extend(5u32.to_be_bytes());
+    desc.extend(b"test\0");
+    // Empty Unicode and ScriptCode descriptions.
+    desc.extend([0; 8]);
+    desc.extend([0; 3 + 67]);
+
+    let mut curv = b"curv\0\0\0\0".to_vec();
+    curv.extend(1u32.to_be_bytes());
+    curv.extend(((gamma * 256.0).round() as u16).to_be_bytes());
+
+    let d50 = [0.9642, 1.0, 0.8249];
+    let tags: [(&[u8; 4], Vec<u8>); 8] = [
+      (b"desc", desc),
+      (b"wtpt", xyz(d50)),
+      (b"rXYZ", xyz([0.4361, 0.2225, 0.0139])),
+      (b"gXYZ", xyz([0.3851, 0.7169, 0.0971])),
+      (b"bXYZ", xyz([0.1431, 0.0606, 0.7141])),
+      (b"rTRC", curv.clone()),
+      (b"gTRC", curv.clone()),
+      (b"bTRC", curv),
+    ];
+
+    let mut table = (tags.len() as u32).to_be_bytes().to_vec();
+    let mut data = Vec::new();
+    let data_start = 128 + 4 + 12 * tags.len();
+    for (signature, tag) in &tags {
+      table.extend(*signature);
+      table.extend(((data_start + data.len()) as u32).to_be_bytes());
+      table.extend((tag.len() as u32).to_be_bytes());
+      data.extend(tag);
+      data.resize(data.len().next_multiple_of(4), 0);
+    }
+
+    let mut header = vec![0; 128];
+    header[8..12].copy_from_slice(&[2, 0x10, 0, 0]);
+    header[12..16].copy_from_slice(b"mntr");
+    header[16..20].copy_from_slice(b"RGB ");
+    header[20..24].copy_from_slice(b"XYZ ");
+    header[36..40].copy_from_slice(b"acsp");
+    header[68..80].copy_from_slice(&xyz(d50)[8..]);
+
+    let mut profile = [header, table, data].concat();
+    let size = (profile.len() as u32).to_be_bytes();
+    profile[..4].copy_from_slice(&size);
+    profile
+  }
+
+  #[test]
+  fn rejects_invalid_profile() {
+    let log = Log::default();
+    assert!(Icc::open(&log, b"definitely not an ICC profile", None).is_err());
+  }
+
+  #[cfg(feature = "lcms")]
+  #[test]
+  fn detects_profile_color_space() {
+    let log = Log::default();
+    let icc = Icc::open(&log, &bt709_profile(2.2), None).expect("profile should open");
+
+    assert_eq!(icc.primaries(), pl_color_primaries::PL_COLOR_PRIM_BT_709);
+    assert!((icc.gamma() - 2.2).abs() < 0.05, "gamma {}", icc.gamma());
+    // A pure power curve has no defined transfer function, so libplacebo
+    // must not claim one of the standard ones.
+    assert!(!matches!(
+      icc.transfer(),
+      pl_color_transfer::PL_COLOR_TRC_SRGB
+        | pl_color_transfer::PL_COLOR_TRC_PQ
+        | pl_color_transfer::PL_COLOR_TRC_HLG
+    ));
+    // Profiles without a black point are assumed to be perfectly black, and
+    // SDR profiles to peak at SDR white.
+    assert!(icc.min_luma() >= 0.0 && icc.min_luma() < 0.5);
+    assert!(
+      (icc.max_luma() - 203.0).abs() < 1.0,
+      "max {}",
+      icc.max_luma()
+    );
+  }
+
+  #[cfg(feature = "lcms")]
+  #[test]
+  fn update_replaces_profile() {
+    let log = Log::default();
+    let mut icc = Icc::open(&log, &bt709_profile(2.2), None).unwrap();
+    let signature = icc.signature();
+
+    icc.update(&log, &bt709_profile(2.2), None).unwrap();
+    assert_eq!(icc.signature(), signature);
+
+    icc.update(&log, &bt709_profile(2.8), None).unwrap();
+    assert_ne!(icc.signature(), signature);
+    assert!((icc.gamma() - 2.8).abs() < 0.05, "gamma {}", icc.gamma());
+
+    // A failed update keeps the old profile usable.
+    assert!(icc.update(&log, b"not a profile", None).is_err());
+    assert!((icc.gamma() - 2.8).abs() < 0.05);
+    assert_eq!(icc.primaries(), pl_color_primaries::PL_COLOR_PRIM_BT_709);
+  }
+}
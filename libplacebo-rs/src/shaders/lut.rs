@@ -0,0 +1,224 @@
+use std::{path::Path, ptr::null_mut, slice};
+
+use foreign_types::{foreign_type, ForeignType, ForeignTypeRef};
+use libplacebo_sys::{
+  pl_color_repr, pl_color_space, pl_custom_lut, pl_lut_free, pl_lut_parse_cube,
+};
+use miette::{miette, IntoDiagnostic, Result};
+
+use crate::log::Log;
+
+foreign_type! {
+  /// Struct defining custom LUTs. Custom LUTs are applied either through
+  /// [`crate::shaders_root::Shader::custom_lut`] or by attaching them to
+  /// `pl_render_params.lut`.
+  pub unsafe type CustomLut: Send + Sync
+  {
+    type CType = pl_custom_lut;
+    fn drop = |mut x: *mut pl_custom_lut| pl_lut_free(&mut x);
+  }
+}
+
+impl CustomLut {
+  /// Parses a 3DLUT in .cube format. Returns `Err` on error, in which case
+  /// libplacebo also logs the reason to `log`.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if `pl_lut_parse_cube()` fails to parse `cube`.
+  pub fn parse_cube(log: Option<&Log>, cube: &str) -> Result<Self> {
+    let log = log.map_or(null_mut(), |log| log.0);
+    let ptr = unsafe { pl_lut_parse_cube(log, cube.as_ptr().cast(), cube.len()) };
+    if ptr.is_null() {
+      Err(miette!("Failed to parse .cube LUT."))
+    } else {
+      Ok(unsafe { Self::from_ptr(ptr) })
+    }
+  }
+
+  /// Reads and parses a .cube file.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if the file cannot be read or fails to parse.
+  pub fn from_file(log: Option<&Log>, path: impl AsRef<Path>) -> Result<Self> {
+    let path = path.as_ref();
+    let cube = std::fs::read_to_string(path).into_diagnostic()?;
+    Self::parse_cube(log, &cube).map_err(|_| miette!("Failed to parse {}.", path.display()))
+  }
+}
+
+/// Range of input values a .cube LUT covers, from its `DOMAIN_MIN` and
+/// `DOMAIN_MAX` (or `LUT_3D_INPUT_RANGE`) keywords. `pl_custom_lut` doesn't
+/// carry it, so shaders sampling the LUT data directly must map their input
+/// to it themselves.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CubeDomain {
+  pub min: [f32; 3],
+  pub max: [f32; 3],
+}
+
+impl Default for CubeDomain {
+  fn default() -> Self {
+    Self {
+      min: [0.0; 3],
+      max: [1.0; 3],
+    }
+  }
+}
+
+impl CubeDomain {
+  /// Reads the domain of a .cube LUT, defaulting to 0.0 to 1.0 for anything
+  /// missing or malformed. Only the header is looked at; parsing the LUT
+  /// itself is left to [`CustomLut::parse_cube`].
+  #[must_use]
+  pub fn parse(cube: &str) -> Self {
+    let mut domain = Self::default();
+    let floats = |values: &str| -> Option<Vec<f32>> {
+      values.split_whitespace().map(|v| v.parse().ok()).collect()
+    };
+
+    for line in cube.lines().map(str::trim) {
+      let Some((keyword, values)) = line.split_once(char::is_whitespace) else {
+        continue;
+      };
+      match (keyword, floats(values).as_deref()) {
+        ("DOMAIN_MIN", Some(&[r, g, b])) => domain.min = [r, g, b],
+        ("DOMAIN_MAX", Some(&[r, g, b])) => domain.max = [r, g, b],
+        ("LUT_3D_INPUT_RANGE", Some(&[min, max])) => {
+          domain.min = [min; 3];
+          domain.max = [max; 3];
+        }
+        // Entries start after the header.
+        _ if keyword.parse::<f32>().is_ok() => break,
+        _ => {}
+      }
+    }
+
+    domain
+  }
+}
+
+impl CustomLutRef {
+  /// Dimensions of the LUT. 1D LUTs leave the second and third dimension
+  /// unset.
+  #[must_use]
+  pub fn size(&self) -> [i32; 3] {
+    unsafe { (*self.as_ptr()).size }
+  }
+
+  /// Whether this is a 3D LUT, as opposed to a 1D LUT.
+  #[must_use]
+  pub fn is_3d(&self) -> bool {
+    let size = self.size();
+    size[1] > 0 && size[2] > 0
+  }
+
+  /// The LUT entries as interleaved RGB triples, with the first dimension
+  /// varying fastest.
+  #[allow(clippy::cast_sign_loss)]
+  #[must_use]
+  pub fn data(&self) -> &[f32] {
+    let len = self
+      .size()
+      .iter()
+      .map(|&s| s.max(1) as usize)
+      .product::<usize>()
+      * 3;
+    unsafe { slice::from_raw_parts((*self.as_ptr()).data, len) }
+  }
+
+  /// Color representation the LUT expects its input in.
+  #[must_use]
+  pub fn repr_in(&self) -> pl_color_repr {
+    unsafe { (*self.as_ptr()).repr_in }
+  }
+
+  /// Color representation the LUT produces.
+  #[must_use]
+  pub fn repr_out(&self) -> pl_color_repr {
+    unsafe { (*self.as_ptr()).repr_out }
+  }
+
+  /// Color space the LUT expects its input in.
+  #[must_use]
+  pub fn color_in(&self) -> pl_color_space {
+    unsafe { (*self.as_ptr()).color_in }
+  }
+
+  /// Color space the LUT produces.
+  #[must_use]
+  pub fn color_out(&self) -> pl_color_space {
+    unsafe { (*self.as_ptr()).color_out }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_identity_3d() {
+    let lut = CustomLut::parse_cube(None, include_str!("../../testdata/lut/identity_2.cube"))
+      .expect("identity LUT should parse");
+    assert_eq!(lut.size(), [2, 2, 2]);
+    assert!(lut.is_3d());
+    assert_eq!(lut.data().len(), 2 * 2 * 2 * 3);
+    assert_eq!(&lut.data()[..6], &[0.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+    assert_eq!(&lut.data()[21..], &[1.0, 1.0, 1.0]);
+  }
+
+  #[test]
+  fn parses_1d() {
+    let lut = CustomLut::parse_cube(None, include_str!("../../testdata/lut/invert_1d.cube"))
+      .expect("1D LUT should parse");
+    assert_eq!(lut.size()[0], 3);
+    assert!(!lut.is_3d());
+    assert_eq!(lut.data().len(), 3 * 3);
+  }
+
+  #[test]
+  fn parses_domain() {
+    let cube = include_str!("../../testdata/lut/domain.cube");
+    let lut = CustomLut::parse_cube(None, cube).expect("LUT with a custom domain should parse");
+    assert_eq!(lut.size(), [2, 2, 2]);
+    assert_eq!(
+      CubeDomain::parse(cube),
+      CubeDomain {
+        min: [0.0; 3],
+        max: [2.0; 3],
+      }
+    );
+  }
+
+  #[test]
+  fn domain_defaults_to_unit_range() {
+    let cube = include_str!("../../testdata/lut/identity_2.cube");
+    assert_eq!(CubeDomain::parse(cube), CubeDomain::default());
+    assert_eq!(
+      CubeDomain::parse("LUT_3D_INPUT_RANGE -0.5 1.5\nLUT_3D_SIZE 2\n"),
+      CubeDomain {
+        min: [-0.5; 3],
+        max: [1.5; 3],
+      }
+    );
+  }
+
+  #[test]
+  fn rejects_malformed() {
+    for cube in [
+      "",
+      include_str!("../../testdata/lut/missing_size.cube"),
+      include_str!("../../testdata/lut/truncated.cube"),
+      include_str!("../../testdata/lut/garbage.cube"),
+    ] {
+      assert!(CustomLut::parse_cube(None, cube).is_err());
+    }
+  }
+
+  #[test]
+  fn from_file_reports_missing_file() {
+    let log = Log::default();
+    assert!(CustomLut::from_file(Some(&log), "testdata/lut/does_not_exist.cube").is_err());
+  }
+}
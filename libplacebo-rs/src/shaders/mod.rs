@@ -1 +1,5 @@
+pub mod custom;
+pub mod hooks;
+pub mod icc;
+pub mod lut;
 pub mod sampling;
@@ -1 +1,307 @@
-// TODO
+use std::marker::PhantomData;
+
+use libplacebo_sys::{
+  pl_fmt_type, pl_rect2df, pl_sample_filter_params, pl_sample_src, pl_sampler_type,
+  pl_shader_sample_bicubic, pl_shader_sample_bilinear, pl_shader_sample_direct,
+  pl_shader_sample_gaussian, pl_shader_sample_hermite, pl_shader_sample_nearest,
+  pl_shader_sample_ortho2, pl_shader_sample_oversample, pl_shader_sample_polar,
+  pl_tex_address_mode, pl_tex_sample_mode,
+};
+use miette::{miette, Result};
+
+use crate::{
+  gpu::Tex,
+  shaders_root::{Shader, ShaderObjectRef},
+};
+
+/// What a sampling shader samples from: either a texture, or a sampler the
+/// shader takes as its input (`PL_SHADER_SIG_SAMPLER`).
+#[derive(Clone, Copy)]
+pub struct SampleSource<'a> {
+  raw: pl_sample_src,
+  _tex: PhantomData<&'a Tex>,
+}
+
+impl<'a> SampleSource<'a> {
+  /// Samples all of `tex`, at its own size.
+  #[must_use]
+  pub fn new(tex: &'a Tex) -> Self {
+    Self {
+      raw: pl_sample_src {
+        tex: tex.as_ptr(),
+        ..pl_sample_src::default()
+      },
+      _tex: PhantomData,
+    }
+  }
+
+  /// Samples from a sampler passed in by whatever the shader is attached to,
+  /// bound to a `width`x`height` texture with the given format type.
+  #[must_use]
+  pub fn sampler(
+    width: i32,
+    height: i32,
+    format: pl_fmt_type,
+    sampler: pl_sampler_type,
+    mode: pl_tex_sample_mode,
+  ) -> Self {
+    Self {
+      raw: pl_sample_src {
+        tex_w: width,
+        tex_h: height,
+        format,
+        sampler,
+        mode,
+        ..pl_sample_src::default()
+      },
+      _tex: PhantomData,
+    }
+  }
+
+  /// Samples only the given region of the source.
+  #[must_use]
+  pub const fn rect(mut self, rect: pl_rect2df) -> Self {
+    self.raw.rect = rect;
+    self
+  }
+
+  /// Address mode used for the texture, where the sampling method allows it.
+  #[must_use]
+  pub const fn address_mode(mut self, mode: pl_tex_address_mode) -> Self {
+    self.raw.address_mode = mode;
+    self
+  }
+
+  /// Samples the first `components` components. Defaults to all of them.
+  #[must_use]
+  pub const fn components(mut self, components: i32) -> Self {
+    self.raw.components = components;
+    self
+  }
+
+  /// Samples only the components set in `mask`, with bit 0 being the first.
+  #[must_use]
+  pub const fn component_mask(mut self, mask: u8) -> Self {
+    self.raw.component_mask = mask;
+    self
+  }
+
+  /// Size to scale the sampled region to. Defaults to the size of the
+  /// region.
+  #[must_use]
+  pub const fn output_size(mut self, width: i32, height: i32) -> Self {
+    self.raw.new_w = width;
+    self.raw.new_h = height;
+    self
+  }
+
+  /// Factor multiplied into the sampled values. Defaults to 1.0.
+  #[must_use]
+  pub const fn scale(mut self, scale: f32) -> Self {
+    self.raw.scale = scale;
+    self
+  }
+
+  #[must_use]
+  pub const fn as_raw(&self) -> &pl_sample_src {
+    &self.raw
+  }
+}
+
+fn check(ok: bool, method: &str) -> Result<()> {
+  if ok {
+    Ok(())
+  } else {
+    Err(miette!(
+      "Failed to sample the source with {method} sampling."
+    ))
+  }
+}
+
+// Each of these samples `src` into `vec4 color`, and fails if the source
+// can't be sampled that way, e.g. because its texture isn't sampleable or
+// doesn't support the sample mode the method needs.
+impl Shader {
+  /// Samples the source as-is, using the texture's own sample mode.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if `pl_shader_sample_direct()` fails.
+  pub fn sample_direct(&mut self, src: &SampleSource) -> Result<()> {
+    check(
+      unsafe { pl_shader_sample_direct(self.as_ptr(), src.as_raw()) },
+      "direct",
+    )
+  }
+
+  /// Nearest neighbour sampling, regardless of the texture's sample mode.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if `pl_shader_sample_nearest()` fails.
+  pub fn sample_nearest(&mut self, src: &SampleSource) -> Result<()> {
+    check(
+      unsafe { pl_shader_sample_nearest(self.as_ptr(), src.as_raw()) },
+      "nearest",
+    )
+  }
+
+  /// Bilinear sampling. Needs a texture with linear sampling support.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if `pl_shader_sample_bilinear()` fails.
+  pub fn sample_bilinear(&mut self, src: &SampleSource) -> Result<()> {
+    check(
+      unsafe { pl_shader_sample_bilinear(self.as_ptr(), src.as_raw()) },
+      "bilinear",
+    )
+  }
+
+  /// Fast bicubic (B-spline) sampling, built from bilinear samples.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if `pl_shader_sample_bicubic()` fails.
+  pub fn sample_bicubic(&mut self, src: &SampleSource) -> Result<()> {
+    check(
+      unsafe { pl_shader_sample_bicubic(self.as_ptr(), src.as_raw()) },
+      "bicubic",
+    )
+  }
+
+  /// Fast hermite sampling, built from bilinear samples.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if `pl_shader_sample_hermite()` fails.
+  pub fn sample_hermite(&mut self, src: &SampleSource) -> Result<()> {
+    check(
+      unsafe { pl_shader_sample_hermite(self.as_ptr(), src.as_raw()) },
+      "hermite",
+    )
+  }
+
+  /// Fast gaussian sampling, built from bilinear samples.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if `pl_shader_sample_gaussian()` fails.
+  pub fn sample_gaussian(&mut self, src: &SampleSource) -> Result<()> {
+    check(
+      unsafe { pl_shader_sample_gaussian(self.as_ptr(), src.as_raw()) },
+      "gaussian",
+    )
+  }
+
+  /// Oversampling, i.e. nearest neighbour with linear blending at pixel
+  /// edges. Edges narrower than `threshold` (between 0.0 and 0.5) are
+  /// snapped to the nearest pixel.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if `pl_shader_sample_oversample()` fails.
+  pub fn sample_oversample(&mut self, src: &SampleSource, threshold: f32) -> Result<()> {
+    check(
+      unsafe { pl_shader_sample_oversample(self.as_ptr(), src.as_raw(), threshold) },
+      "oversample",
+    )
+  }
+
+  /// Polar (EWA) sampling with the filter in `params`. `lut` holds the
+  /// filter's weights and should be reused for as long as the filter is.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if `pl_shader_sample_polar()` fails, e.g. because the
+  /// filter isn't polar.
+  pub fn sample_polar(
+    &mut self,
+    src: &SampleSource,
+    params: &pl_sample_filter_params,
+    lut: &ShaderObjectRef,
+  ) -> Result<()> {
+    let params = pl_sample_filter_params {
+      lut: lut.as_ptr(),
+      ..*params
+    };
+    check(
+      unsafe { pl_shader_sample_polar(self.as_ptr(), src.as_raw(), &params) },
+      "polar",
+    )
+  }
+
+  /// One pass of separable (orthogonal) sampling with the filter in
+  /// `params`, along the axis with a differing output size. Scaling in both
+  /// directions takes two passes, each with its own `lut`.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if `pl_shader_sample_ortho2()` fails, e.g. because
+  /// both or neither of the axes are scaled.
+  pub fn sample_ortho2(
+    &mut self,
+    src: &SampleSource,
+    params: &pl_sample_filter_params,
+    lut: &ShaderObjectRef,
+  ) -> Result<()> {
+    let params = pl_sample_filter_params {
+      lut: lut.as_ptr(),
+      ..*params
+    };
+    check(
+      unsafe { pl_shader_sample_ortho2(self.as_ptr(), src.as_raw(), &params) },
+      "orthogonal",
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::ptr::null;
+
+  use libplacebo_sys::{
+    pl_gpu_dummy_create, pl_gpu_dummy_destroy, pl_shader_free, pl_shader_params, pl_shader_sig,
+  };
+
+  use super::*;
+  use crate::log::Log;
+
+  #[test]
+  fn sampler_sources_take_a_sampler() {
+    let log = Log::default();
+    let mut gpu = unsafe { pl_gpu_dummy_create(log.0, null()) };
+    assert!(!gpu.is_null());
+
+    let src = SampleSource::sampler(
+      64,
+      32,
+      pl_fmt_type::PL_FMT_UNORM,
+      pl_sampler_type::PL_SAMPLER_NORMAL,
+      pl_tex_sample_mode::PL_TEX_SAMPLE_LINEAR,
+    )
+    .output_size(128, 64)
+    .components(3);
+    assert_eq!((src.as_raw().tex_w, src.as_raw().new_w), (64, 128));
+
+    let mut shader = Shader::new(
+      &log,
+      &pl_shader_params {
+        gpu,
+        ..pl_shader_params::default()
+      },
+    );
+    shader.sample_bicubic(&src).unwrap();
+
+    {
+      let res = shader.finalize().unwrap();
+      assert_eq!(res.input(), pl_shader_sig::PL_SHADER_SIG_SAMPLER);
+      assert_eq!(res.output(), pl_shader_sig::PL_SHADER_SIG_COLOR);
+    }
+
+    unsafe {
+      pl_shader_free(&mut shader.as_ptr());
+      pl_gpu_dummy_destroy(&mut gpu);
+    }
+  }
+}
@@ -1,15 +1,26 @@
-use std::ptr::{null_mut, NonNull};
+use std::ffi::CStr;
+use std::ptr::{null, null_mut};
+use std::slice;
 
-use foreign_types::{foreign_type, ForeignTypeRef};
+use foreign_types::{foreign_type, ForeignType, ForeignTypeRef};
 use libplacebo_sys::{
-  pl_deband_params, pl_dither_params, pl_gpu, pl_sample_src, pl_shader, pl_shader_alloc,
-  pl_shader_deband, pl_shader_dither, pl_shader_free, pl_shader_obj, pl_shader_obj_destroy,
-  pl_shader_obj_t, pl_shader_obj_type, pl_shader_params, pl_shader_reset,
+  pl_color_adjustment, pl_color_map_args, pl_color_map_params, pl_color_repr, pl_color_space,
+  pl_custom_shader, pl_deband_params, pl_deinterlace_params, pl_deinterlace_source,
+  pl_dither_params, pl_icc_decode, pl_icc_encode, pl_shader, pl_shader_alloc,
+  pl_shader_color_map_ex, pl_shader_custom, pl_shader_custom_lut, pl_shader_deband,
+  pl_shader_decode_color, pl_shader_deinterlace, pl_shader_delinearize, pl_shader_dither,
+  pl_shader_encode_color, pl_shader_finalize, pl_shader_free, pl_shader_info, pl_shader_info_deref,
+  pl_shader_info_ref, pl_shader_linearize, pl_shader_obj, pl_shader_obj_destroy, pl_shader_params,
+  pl_shader_res, pl_shader_reset, pl_shader_sig, pl_shader_var, pl_var, pl_var_type,
 };
+use miette::{miette, Result};
 
-use crate::log::Log;
+use crate::{
+  log::Log,
+  shaders::{icc::Icc, lut::CustomLutRef, sampling::SampleSource},
+};
 
-pub struct Shader(pl_shader, pl_shader_obj);
+pub struct Shader(pl_shader);
 
 impl Shader {
   /// Creates a new, blank, mutable `pl_shader` object.
@@ -27,14 +38,14 @@ impl Shader {
     unsafe {
       let ptr = pl_shader_alloc(log.0, params);
       debug_assert!(!ptr.is_null());
-      Self(ptr, null_mut())
+      Self(ptr)
     }
   }
 
   #[must_use]
   pub fn from_ptr(ptr: pl_shader) -> Self {
     debug_assert!(!ptr.is_null());
-    Self(ptr, null_mut())
+    Self(ptr)
   }
 
   #[must_use]
@@ -54,11 +65,21 @@ impl Shader {
   }
 
   /// Debands a given texture.
-  pub fn deband(&mut self, src: &pl_sample_src, params: &pl_deband_params) {
-    debug_assert!(!src.tex.is_null());
+  pub fn deband(&mut self, src: &SampleSource, params: &pl_deband_params) {
+    debug_assert!(!src.as_raw().tex.is_null());
+
+    unsafe {
+      pl_shader_deband(self.as_ptr(), src.as_raw(), params);
+    }
+  }
+
+  /// Deinterlaces a set of interleaved source frames and outputs the result
+  /// into `vec4 color`.
+  pub fn deinterlace(&mut self, src: &pl_deinterlace_source, params: &pl_deinterlace_params) {
+    debug_assert!(!src.cur.is_null());
 
     unsafe {
-      pl_shader_deband(self.as_ptr(), src, params);
+      pl_shader_deinterlace(self.as_ptr(), src, params);
     }
   }
 
@@ -69,11 +90,291 @@ impl Shader {
     dither_state: &ShaderObjectRef,
     params: &pl_dither_params,
   ) {
-    // debug_assert!(!dither_state.is_null());
+    unsafe {
+      pl_shader_dither(self.as_ptr(), new_depth, dither_state.as_ptr(), params);
+    }
+  }
+
+  /// Applies a custom LUT to `vec4 color`. `lut_state` holds the uploaded LUT
+  /// texture, so it should be reused for as long as the same LUT is in use.
+  pub fn custom_lut(&mut self, lut: &CustomLutRef, lut_state: &ShaderObjectRef) {
+    unsafe {
+      pl_shader_custom_lut(self.as_ptr(), lut.as_ptr(), lut_state.as_ptr());
+    }
+  }
 
+  /// Decode the color into normalized RGB, given a specified `repr`. This
+  /// also takes care of additional pre- and post-conversions required for the
+  /// "special" color systems (XYZ, BT.2020-C, etc.). If `params` is left as
+  /// `None`, it defaults to `pl_color_adjustment_neutral`.
+  ///
+  /// Note: This function always returns PC-range RGB with independent alpha.
+  /// It mutates the `pl_color_repr` to reflect the change.
+  pub fn decode_color(&mut self, repr: &mut pl_color_repr, params: Option<&pl_color_adjustment>) {
     unsafe {
-      pl_shader_dither(self.as_ptr(), new_depth, &mut self.1, params);
+      pl_shader_decode_color(
+        self.as_ptr(),
+        repr,
+        params.map_or(null(), std::ptr::from_ref),
+      );
+    }
+  }
+
+  /// Encodes a color from normalized, PC-range, independent alpha RGB into a
+  /// given representation. That is, this performs the inverse operation of
+  /// [`Shader::decode_color`] (minus the color adjustments).
+  pub fn encode_color(&mut self, repr: &pl_color_repr) {
+    unsafe {
+      pl_shader_encode_color(self.as_ptr(), repr);
+    }
+  }
+
+  /// Linearize (expand) `vec4 color`, given a specified color space. In
+  /// essence, this corresponds to the ITU-R EOTF.
+  pub fn linearize(&mut self, csp: &pl_color_space) {
+    unsafe {
+      pl_shader_linearize(self.as_ptr(), csp);
+    }
+  }
+
+  /// Delinearize (compress), given a color space as output. This loosely
+  /// corresponds to the inverse EOTF (not the OETF) in ITU-R terminology,
+  /// again assuming a reference monitor.
+  pub fn delinearize(&mut self, csp: &pl_color_space) {
+    unsafe {
+      pl_shader_delinearize(self.as_ptr(), csp);
+    }
+  }
+
+  /// Maps `vec4 color` from one color space to another color space according
+  /// to the parameters (described in greater depth above). If `params` is left
+  /// as `None`, it defaults to `pl_color_map_default_params`.
+  pub fn color_map_ex(&mut self, params: Option<&pl_color_map_params>, args: &pl_color_map_args) {
+    unsafe {
+      pl_shader_color_map_ex(
+        self.as_ptr(),
+        params.map_or(null(), std::ptr::from_ref),
+        args,
+      );
+    }
+  }
+
+  /// Decodes `vec4 color` from the color space described by an ICC profile
+  /// into linear light. Returns the color space of the decoded result, which
+  /// is what subsequent color mapping should start from. `lut_state` holds the
+  /// generated 3DLUT and should be reused for as long as the profile is.
+  pub fn icc_decode(&mut self, icc: &Icc, lut_state: &ShaderObjectRef) -> pl_color_space {
+    let mut out_csp = pl_color_space::default();
+    unsafe {
+      pl_icc_decode(
+        self.as_ptr(),
+        icc.as_ptr(),
+        lut_state.as_ptr(),
+        &mut out_csp,
+      );
+    }
+    out_csp
+  }
+
+  /// Encodes `vec4 color`, given in linear light in the profile's color space,
+  /// into the color space described by an ICC profile. The inverse of
+  /// [`Shader::icc_decode`].
+  pub fn icc_encode(&mut self, icc: &Icc, lut_state: &ShaderObjectRef) {
+    unsafe {
+      pl_icc_encode(self.as_ptr(), icc.as_ptr(), lut_state.as_ptr());
+    }
+  }
+
+  /// Inject a custom shader into the pipeline.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if `pl_shader_custom()` is unsuccessful, e.g. because
+  /// the shader's input/output signature or size is incompatible with the
+  /// current state of the shader.
+  pub fn custom(&mut self, params: &pl_custom_shader) -> Result<()> {
+    if unsafe { pl_shader_custom(self.as_ptr(), params) } {
+      Ok(())
+    } else {
+      Err(miette!("Failed to apply custom shader."))
+    }
+  }
+}
+
+impl Shader {
+  /// Returns information about the shader so far, like its description and
+  /// signature. Unlike [`Shader::finalize`], this leaves it mutable.
+  #[must_use]
+  pub fn info(&self) -> ShaderInfo {
+    ShaderInfo(unsafe { pl_shader_info(self.as_ptr()) })
+  }
+
+  /// Compiles the shader into a GLSL function and returns it, along with the
+  /// variables and descriptors it needs. This makes the shader immutable, but
+  /// it can still be dispatched.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if `pl_shader_finalize()` is unsuccessful, e.g. because
+  /// generating the shader failed earlier.
+  pub fn finalize(&mut self) -> Result<ShaderRes<'_>> {
+    let res = unsafe { pl_shader_finalize(self.as_ptr()) };
+    if res.is_null() {
+      Err(miette!("Failed to finalize shader."))
+    } else {
+      Ok(ShaderRes(unsafe { &*res }))
+    }
+  }
+
+  /// The GLSL source of the shader, see [`Shader::finalize`].
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if the shader cannot be finalized.
+  pub fn to_glsl(&mut self) -> Result<String> {
+    Ok(self.finalize()?.glsl().to_string_lossy().into_owned())
+  }
+}
+
+/// Converts a possibly null C string to a `&CStr`, treating null as empty.
+unsafe fn cstr_or_empty<'a>(ptr: *const std::os::raw::c_char) -> &'a CStr {
+  if ptr.is_null() {
+    c""
+  } else {
+    CStr::from_ptr(ptr)
+  }
+}
+
+/// Description of a shader, see [`Shader::info`].
+pub struct ShaderInfo(pl_shader_info);
+
+impl ShaderInfo {
+  /// Takes a new reference to `info`, e.g. the shader of a `pl_dispatch_info`
+  /// reported by the renderer.
+  ///
+  /// # Safety
+  ///
+  /// `info` must point to a live `pl_shader_info_t`.
+  #[must_use]
+  pub unsafe fn from_ref(info: pl_shader_info) -> Self {
+    Self(pl_shader_info_ref(info))
+  }
+
+  /// Uniquely identifies the shader's code, excluding variable values.
+  #[must_use]
+  pub fn signature(&self) -> u64 {
+    unsafe { (*self.0).signature }
+  }
+
+  /// Short description of what the shader does.
+  #[must_use]
+  pub fn description(&self) -> &CStr {
+    unsafe { cstr_or_empty((*self.0).description) }
+  }
+
+  /// Descriptions of the individual steps the shader is made of.
+  #[must_use]
+  #[allow(clippy::cast_sign_loss)]
+  pub fn steps(&self) -> Vec<&CStr> {
+    let info = unsafe { &*self.0 };
+    if info.steps.is_null() {
+      return Vec::new();
     }
+
+    unsafe { slice::from_raw_parts(info.steps, info.num_steps as usize) }
+      .iter()
+      .map(|&step| unsafe { cstr_or_empty(step) })
+      .collect()
+  }
+}
+
+impl Drop for ShaderInfo {
+  fn drop(&mut self) {
+    unsafe { pl_shader_info_deref(&mut self.0) }
+  }
+}
+
+/// A finalized shader, see [`Shader::finalize`]. Borrowed from the shader,
+/// which must not be reset while this is alive.
+pub struct ShaderRes<'a>(&'a pl_shader_res);
+
+impl<'a> ShaderRes<'a> {
+  /// Name of the generated GLSL function.
+  #[must_use]
+  pub fn name(&self) -> &'a CStr {
+    unsafe { cstr_or_empty(self.0.name) }
+  }
+
+  /// GLSL source of the shader's function, which takes `input` and returns
+  /// `output`.
+  #[must_use]
+  pub fn glsl(&self) -> &'a CStr {
+    unsafe { cstr_or_empty(self.0.glsl) }
+  }
+
+  #[must_use]
+  pub const fn input(&self) -> pl_shader_sig {
+    self.0.input
+  }
+
+  #[must_use]
+  pub const fn output(&self) -> pl_shader_sig {
+    self.0.output
+  }
+
+  /// Description of the finalized shader.
+  #[must_use]
+  pub fn info(&self) -> ShaderInfo {
+    ShaderInfo(unsafe { pl_shader_info_ref(self.0.info) })
+  }
+
+  /// Variables the shader expects, with their current values.
+  #[must_use]
+  #[allow(clippy::cast_sign_loss)]
+  pub fn variables(&self) -> &'a [pl_shader_var] {
+    if self.0.variables.is_null() {
+      return &[];
+    }
+
+    unsafe { slice::from_raw_parts(self.0.variables, self.0.num_variables as usize) }
+  }
+}
+
+/// Values of `var`, which are tightly packed in host memory.
+///
+/// # Safety
+///
+/// `var.data` must point to the variable's values, as it does for the
+/// variables returned by [`ShaderRes::variables`].
+#[must_use]
+#[allow(clippy::cast_sign_loss)]
+pub unsafe fn var_values(var: &pl_shader_var) -> Vec<f64> {
+  let pl_var {
+    type_,
+    dim_v,
+    dim_m,
+    dim_a,
+    ..
+  } = var.var;
+  let len = (dim_v * dim_m * dim_a) as usize;
+  if var.data.is_null() {
+    return Vec::new();
+  }
+
+  match type_ {
+    pl_var_type::PL_VAR_SINT => slice::from_raw_parts(var.data.cast::<i32>(), len)
+      .iter()
+      .map(|&x| f64::from(x))
+      .collect(),
+    pl_var_type::PL_VAR_UINT => slice::from_raw_parts(var.data.cast::<u32>(), len)
+      .iter()
+      .map(|&x| f64::from(x))
+      .collect(),
+    pl_var_type::PL_VAR_FLOAT => slice::from_raw_parts(var.data.cast::<f32>(), len)
+      .iter()
+      .map(|&x| f64::from(x))
+      .collect(),
+    _ => Vec::new(),
   }
 }
 
@@ -90,21 +391,72 @@ foreign_type! {
   /// order to ensure their operation. This could include shader storage
   /// buffers, generated lookup textures, or other sorts of configured state.
   /// The body of a shader object is fully opaque
-  pub unsafe type ShaderObject
+  pub unsafe type ShaderObject: Send
   {
       type CType = pl_shader_obj;
-      fn drop = |x: *mut pl_shader_obj| if !x.is_null() { pl_shader_obj_destroy(x) };
+      fn drop = |x: *mut pl_shader_obj| {
+        pl_shader_obj_destroy(x);
+        drop(Box::from_raw(x));
+      };
   }
 }
 
 impl ShaderObject {
+  /// Allocates an empty shader object slot. libplacebo creates the object
+  /// itself, with the appropriate type, the first time a shader uses it.
   #[must_use]
-  pub fn new(gpu: pl_gpu, r#type: pl_shader_obj_type) -> Self {
-    debug_assert!(!gpu.is_null());
+  pub fn new() -> Self {
+    let slot: Box<pl_shader_obj> = Box::new(null_mut());
+    unsafe { Self::from_ptr(Box::into_raw(slot)) }
+  }
+}
+
+impl Default for ShaderObject {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use libplacebo_sys::{pl_color_transfer, pl_gpu_dummy_create, pl_gpu_dummy_destroy};
+
+  use super::*;
+
+  #[test]
+  fn finalized_shader_has_glsl() {
+    let log = Log::default();
+    let mut gpu = unsafe { pl_gpu_dummy_create(log.0, null()) };
+    assert!(!gpu.is_null());
+
+    let mut shader = Shader::new(
+      &log,
+      &pl_shader_params {
+        gpu,
+        ..pl_shader_params::default()
+      },
+    );
+    shader.linearize(&pl_color_space {
+      transfer: pl_color_transfer::PL_COLOR_TRC_PQ,
+      ..pl_color_space::default()
+    });
+
+    assert!(!shader.info().steps().is_empty());
+
+    {
+      let res = shader.finalize().unwrap();
+      assert_eq!(res.input(), pl_shader_sig::PL_SHADER_SIG_COLOR);
+      assert_eq!(res.output(), pl_shader_sig::PL_SHADER_SIG_COLOR);
+
+      let glsl = res.glsl().to_str().unwrap();
+      assert!(glsl.contains(res.name().to_str().unwrap()));
+    }
+
+    assert!(!shader.to_glsl().unwrap().is_empty());
 
     unsafe {
-      let mut obj: pl_shader_obj = &mut pl_shader_obj_t { gpu, r#type };
-      Self(NonNull::new_unchecked(&mut obj))
+      pl_shader_free(&mut shader.as_ptr());
+      pl_gpu_dummy_destroy(&mut gpu);
     }
   }
 }
@@ -0,0 +1,84 @@
+use std::ptr::null;
+
+use libplacebo_sys::{
+  pl_frame_mix, pl_gpu, pl_queue, pl_queue_create, pl_queue_destroy, pl_queue_num_frames,
+  pl_queue_params, pl_queue_push, pl_queue_reset, pl_queue_status, pl_queue_update,
+  pl_source_frame,
+};
+use miette::{miette, Result};
+
+/// A queue of source frames, which takes care of mapping them to textures on
+/// demand and of picking the frames relevant for a given output timestamp.
+///
+/// Thread-safety: safe.
+pub struct Queue(pl_queue);
+
+unsafe impl Send for Queue {}
+unsafe impl Sync for Queue {}
+
+impl Queue {
+  /// # Panics
+  ///
+  /// Will panic if `pl_queue_create()` returns a null pointer.
+  #[must_use]
+  pub fn new(gpu: &pl_gpu) -> Self {
+    assert!(!gpu.is_null());
+
+    let ptr = unsafe { pl_queue_create(*gpu) };
+    assert!(!ptr.is_null());
+    Self(ptr)
+  }
+
+  #[must_use]
+  pub const fn as_ptr(&self) -> pl_queue {
+    self.0
+  }
+
+  /// Discards all queued frames and returns the queue to its initial state.
+  pub fn reset(&self) {
+    unsafe { pl_queue_reset(self.0) }
+  }
+
+  /// Pushes a source frame. Frames must be pushed in order of increasing
+  /// `pts`. `frame` is copied, but whatever `frame_data` points to must remain
+  /// valid until the frame is mapped or discarded.
+  pub fn push(&self, frame: &pl_source_frame) {
+    unsafe { pl_queue_push(self.0, frame) }
+  }
+
+  /// Signals that no more frames will be pushed.
+  pub fn push_eof(&self) {
+    unsafe { pl_queue_push(self.0, null()) }
+  }
+
+  /// Number of frames currently in the queue.
+  #[must_use]
+  pub fn num_frames(&self) -> i32 {
+    unsafe { pl_queue_num_frames(self.0) }
+  }
+
+  /// Advances the queue to `params.pts` and returns the frames surrounding
+  /// it, mapping them first if needed. The returned mix remains valid until
+  /// the next call that modifies the queue.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if mapping a frame failed, or if the queue does not
+  /// hold enough frames to cover `params.radius` and has not reached EOF.
+  pub fn update(&self, params: &pl_queue_params) -> Result<pl_frame_mix> {
+    let mut mix = pl_frame_mix::default();
+    match unsafe { pl_queue_update(self.0, &mut mix, params) } {
+      pl_queue_status::PL_QUEUE_OK | pl_queue_status::PL_QUEUE_EOF => Ok(mix),
+      pl_queue_status::PL_QUEUE_MORE => Err(miette!("Frame queue needs more frames.")),
+      pl_queue_status::PL_QUEUE_ERR => Err(miette!("Failed to update frame queue.")),
+    }
+  }
+}
+
+impl Drop for Queue {
+  fn drop(&mut self) {
+    unsafe {
+      pl_queue_destroy(&mut self.0);
+    }
+  }
+}
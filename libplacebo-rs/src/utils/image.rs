@@ -0,0 +1,380 @@
+//! Moving [`ImageBuffer`]s in and out of libplacebo, for processing stills.
+//! An image is uploaded as a single packed plane; the other way around, a
+//! target texture is created whose host layout matches the pixel type, or
+//! has extra components that are dropped when downloading.
+
+use std::{
+  mem::{size_of, size_of_val},
+  ops::Deref,
+  slice,
+};
+
+use image::{ImageBuffer, Luma, LumaA, Pixel, Rgb, Rgba};
+use libplacebo_sys::{
+  pl_alpha_mode, pl_color_levels, pl_color_repr, pl_color_space, pl_color_system, pl_find_fmt,
+  pl_fmt_caps, pl_fmt_type, pl_frame, pl_plane, pl_render_params, pl_tex_create, pl_tex_destroy,
+  pl_tex_params,
+};
+use miette::{miette, IntoDiagnostic, Result};
+
+use crate::{
+  gpu::{Format, Gpu, Tex},
+  renderer::Renderer,
+  utils::upload::{download_tex, upload_plane, PlaneData},
+};
+
+/// Pixel types that map directly to a libplacebo frame.
+///
+/// # Safety
+///
+/// `Self::Subpixel` must have no padding and be valid for any bit pattern,
+/// as images are transferred as raw bytes.
+pub unsafe trait FramePixel: Pixel {
+  const TYPE: pl_fmt_type;
+
+  /// Color system of images of this pixel type. Gray images are luma
+  /// without chroma, which libplacebo fills in as neutral.
+  const SYSTEM: pl_color_system;
+
+  /// Channel (`PL_CHANNEL_*`) of each of the pixel's components.
+  const COMPONENTS: &'static [i32];
+
+  const ALPHA: bool;
+}
+
+macro_rules! impl_frame_pixel {
+  ($($t:ty => $type_:ident),*) => {$(
+    unsafe impl FramePixel for Luma<$t> {
+      const TYPE: pl_fmt_type = pl_fmt_type::$type_;
+      const SYSTEM: pl_color_system = pl_color_system::PL_COLOR_SYSTEM_BT_709;
+      const COMPONENTS: &'static [i32] = &[0];
+      const ALPHA: bool = false;
+    }
+
+    unsafe impl FramePixel for LumaA<$t> {
+      const TYPE: pl_fmt_type = pl_fmt_type::$type_;
+      const SYSTEM: pl_color_system = pl_color_system::PL_COLOR_SYSTEM_BT_709;
+      const COMPONENTS: &'static [i32] = &[0, 3];
+      const ALPHA: bool = true;
+    }
+
+    unsafe impl FramePixel for Rgb<$t> {
+      const TYPE: pl_fmt_type = pl_fmt_type::$type_;
+      const SYSTEM: pl_color_system = pl_color_system::PL_COLOR_SYSTEM_RGB;
+      const COMPONENTS: &'static [i32] = &[0, 1, 2];
+      const ALPHA: bool = false;
+    }
+
+    unsafe impl FramePixel for Rgba<$t> {
+      const TYPE: pl_fmt_type = pl_fmt_type::$type_;
+      const SYSTEM: pl_color_system = pl_color_system::PL_COLOR_SYSTEM_RGB;
+      const COMPONENTS: &'static [i32] = &[0, 1, 2, 3];
+      const ALPHA: bool = true;
+    }
+  )*};
+}
+
+impl_frame_pixel!(u8 => PL_FMT_UNORM, u16 => PL_FMT_UNORM, f32 => PL_FMT_FLOAT);
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+const fn sample_bits<P: FramePixel>() -> i32 {
+  size_of::<P::Subpixel>() as i32 * 8
+}
+
+const fn pixel_size<P: FramePixel>() -> usize {
+  P::CHANNEL_COUNT as usize * size_of::<P::Subpixel>()
+}
+
+fn as_bytes<P: FramePixel>(samples: &[P::Subpixel]) -> &[u8] {
+  unsafe { slice::from_raw_parts(samples.as_ptr().cast(), size_of_val(samples)) }
+}
+
+fn as_bytes_mut<P: FramePixel>(samples: &mut [P::Subpixel]) -> &mut [u8] {
+  unsafe { slice::from_raw_parts_mut(samples.as_mut_ptr().cast(), size_of_val(samples)) }
+}
+
+fn size<P: FramePixel, C>(image: &ImageBuffer<P, C>) -> Result<(i32, i32)> {
+  Ok((
+    i32::try_from(image.width()).into_diagnostic()?,
+    i32::try_from(image.height()).into_diagnostic()?,
+  ))
+}
+
+/// Representation of images of `P`, which are always full range.
+#[must_use]
+pub fn repr<P: FramePixel>() -> pl_color_repr {
+  pl_color_repr {
+    sys: P::SYSTEM,
+    levels: pl_color_levels::PL_COLOR_LEVELS_FULL,
+    alpha: if P::ALPHA {
+      pl_alpha_mode::PL_ALPHA_INDEPENDENT
+    } else {
+      pl_alpha_mode::PL_ALPHA_UNKNOWN
+    },
+    ..pl_color_repr::default()
+  }
+}
+
+/// Describes the pixels of `image` for uploading.
+///
+/// # Errors
+///
+/// Will return `Err` if the image is too large.
+#[allow(clippy::cast_sign_loss)]
+pub fn plane_data<P, C>(image: &ImageBuffer<P, C>) -> Result<PlaneData<'_>>
+where
+  P: FramePixel,
+  C: Deref<Target = [P::Subpixel]>,
+{
+  let (width, height) = size(image)?;
+  let bits = sample_bits::<P>();
+
+  let (mut sizes, mut shifts) = ([0; 4], [0; 4]);
+  for (position, &channel) in (0..).zip(P::COMPONENTS) {
+    sizes[channel as usize] = bits;
+    shifts[channel as usize] = position * bits;
+  }
+
+  Ok(
+    PlaneData::new(
+      as_bytes::<P>(image.as_raw()),
+      P::TYPE,
+      width,
+      height,
+      pixel_size::<P>(),
+      0,
+    )?
+    .from_comps(sizes, shifts),
+  )
+}
+
+/// Uploads `image` to `tex`, creating or recreating it as needed, and
+/// describes it as a frame in `color`.
+///
+/// # Errors
+///
+/// Will return `Err` if the image is too large or can't be uploaded.
+pub fn upload_image<P, C>(
+  gpu: Gpu,
+  tex: &mut Option<Tex>,
+  image: &ImageBuffer<P, C>,
+  color: &pl_color_space,
+) -> Result<pl_frame>
+where
+  P: FramePixel,
+  C: Deref<Target = [P::Subpixel]>,
+{
+  let plane = upload_plane(gpu, tex, &plane_data(image)?)?;
+  Ok(frame::<P>(plane, color))
+}
+
+fn frame<P: FramePixel>(plane: pl_plane, color: &pl_color_space) -> pl_frame {
+  let mut frame = pl_frame {
+    num_planes: 1,
+    repr: repr::<P>(),
+    color: *color,
+    ..pl_frame::default()
+  };
+  frame.planes[0] = plane;
+  frame
+}
+
+/// Best renderable format that can be downloaded as `P`, preferring ones
+/// without extra components.
+fn target_format<P: FramePixel>(gpu: Gpu) -> Result<Format> {
+  let bits = sample_bits::<P>();
+  let caps = pl_fmt_caps::PL_FMT_CAP_RENDERABLE | pl_fmt_caps::PL_FMT_CAP_HOST_READABLE;
+
+  [i32::from(P::CHANNEL_COUNT), 4]
+    .into_iter()
+    .find_map(|components| {
+      Format::from_ptr(unsafe { pl_find_fmt(gpu.as_ptr(), P::TYPE, components, bits, bits, caps) })
+    })
+    .ok_or_else(|| {
+      miette!(
+        "No renderable format for {} {bits}-bit components.",
+        P::CHANNEL_COUNT
+      )
+    })
+}
+
+/// Creates a `width`x`height` texture to render images of `P` into. Destroy
+/// it with `pl_tex_destroy` once done.
+///
+/// # Errors
+///
+/// Will return `Err` if the GPU has no suitable format, or if
+/// `pl_tex_create()` fails.
+pub fn create_target<P: FramePixel>(gpu: Gpu, width: i32, height: i32) -> Result<Tex> {
+  let format = target_format::<P>(gpu)?;
+  let tex = unsafe {
+    pl_tex_create(
+      gpu.as_ptr(),
+      &pl_tex_params {
+        w: width,
+        h: height,
+        format: format.as_ptr(),
+        renderable: true,
+        host_readable: true,
+        sampleable: format.supports(pl_fmt_caps::PL_FMT_CAP_SAMPLEABLE),
+        debug_tag: c"image target".as_ptr(),
+        ..pl_tex_params::default()
+      },
+    )
+  };
+
+  if tex.is_null() {
+    Err(miette!("Failed to create {width}x{height} target texture."))
+  } else {
+    Ok(unsafe { Tex::new_unchecked(tex.cast_mut()) })
+  }
+}
+
+/// Describes `tex`, created by [`create_target`], as a frame of `P` pixels
+/// in `color`.
+#[must_use]
+pub fn target_frame<P: FramePixel>(tex: &Tex, color: &pl_color_space) -> pl_frame {
+  let mut component_mapping = [-1; 4];
+  component_mapping[..P::COMPONENTS.len()].copy_from_slice(P::COMPONENTS);
+
+  frame::<P>(
+    pl_plane {
+      texture: tex.as_ptr(),
+      components: tex.num_components(),
+      component_mapping,
+      ..pl_plane::default()
+    },
+    color,
+  )
+}
+
+/// Downloads `tex`, created by [`create_target`], into a new image.
+///
+/// # Errors
+///
+/// Will return `Err` if `pl_tex_download()` fails.
+#[allow(clippy::cast_sign_loss)]
+pub fn download_image<P: FramePixel>(
+  gpu: Gpu,
+  tex: &Tex,
+) -> Result<ImageBuffer<P, Vec<P::Subpixel>>> {
+  let (width, height) = (tex.width() as usize, tex.height() as usize);
+  let texel_size = tex.format().texel_size();
+  let pixel_size = pixel_size::<P>();
+
+  let mut samples =
+    vec![P::Subpixel::DEFAULT_MIN_VALUE; width * height * P::CHANNEL_COUNT as usize];
+
+  if texel_size == pixel_size {
+    download_tex(gpu, tex, as_bytes_mut::<P>(&mut samples), 0)?;
+  } else {
+    let mut texels = vec![0; width * height * texel_size];
+    download_tex(gpu, tex, &mut texels, 0)?;
+
+    for (pixel, texel) in as_bytes_mut::<P>(&mut samples)
+      .chunks_exact_mut(pixel_size)
+      .zip(texels.chunks_exact(texel_size))
+    {
+      pixel.copy_from_slice(&texel[..pixel_size]);
+    }
+  }
+
+  ImageBuffer::from_raw(tex.width() as u32, tex.height() as u32, samples)
+    .ok_or_else(|| miette!("Downloaded image has the wrong size."))
+}
+
+/// Renders `image`, in `color`, into a new `width`x`height` image of `Q`
+/// pixels in `target_color`.
+///
+/// # Errors
+///
+/// Will return `Err` if uploading, rendering or downloading fails.
+pub fn render_image<P, Q, C>(
+  renderer: &mut Renderer,
+  gpu: Gpu,
+  image: &ImageBuffer<P, C>,
+  color: &pl_color_space,
+  (width, height): (i32, i32),
+  target_color: &pl_color_space,
+  params: &pl_render_params,
+) -> Result<ImageBuffer<Q, Vec<Q::Subpixel>>>
+where
+  P: FramePixel,
+  Q: FramePixel,
+  C: Deref<Target = [P::Subpixel]>,
+{
+  let mut src = None;
+  let dst = create_target::<Q>(gpu, width, height)?;
+
+  let result = upload_image(gpu, &mut src, image, color).and_then(|frame| {
+    renderer.render_image(&frame, &target_frame::<Q>(&dst, target_color), params)?;
+    download_image(gpu, &dst)
+  });
+
+  for tex in src.iter().chain([&dst]) {
+    unsafe { pl_tex_destroy(gpu.as_ptr(), &mut tex.as_ptr()) };
+  }
+
+  result
+}
+
+#[cfg(test)]
+mod tests {
+  use std::ptr::null;
+
+  use libplacebo_sys::{pl_gpu_dummy_create, pl_gpu_dummy_destroy};
+
+  use super::*;
+  use crate::{log::Log, utils::upload::upload_tex};
+
+  #[test]
+  fn pixel_layouts() {
+    let image = ImageBuffer::<LumaA<u16>, _>::from_raw(3, 2, vec![0; 12]).unwrap();
+    let data = plane_data(&image).unwrap();
+    let raw = data.as_raw();
+    assert_eq!((raw.pixel_stride, raw.row_stride), (4, 12));
+    assert_eq!(raw.component_size, [16, 16, 0, 0]);
+    assert_eq!(raw.component_map[..2], [0, 3]);
+
+    let image = ImageBuffer::<Rgb<f32>, _>::from_raw(1, 1, vec![0.0; 3]).unwrap();
+    let raw = *plane_data(&image).unwrap().as_raw();
+    assert_eq!(raw.type_, pl_fmt_type::PL_FMT_FLOAT);
+    assert_eq!(raw.component_size, [32, 32, 32, 0]);
+    assert_eq!(raw.component_map[..3], [0, 1, 2]);
+  }
+
+  #[test]
+  fn targets_round_trip() {
+    let log = Log::default();
+    let mut ptr = unsafe { pl_gpu_dummy_create(log.0, null()) };
+    let gpu = unsafe { Gpu::new_unchecked(ptr.cast_mut()) };
+
+    let samples: Vec<u16> = (0..5 * 3 * 3).collect();
+    let tex = create_target::<Rgb<u16>>(gpu, 5, 3).unwrap();
+    let texel_size = tex.format().texel_size();
+
+    // Fill the texture as it would be laid out, padding included.
+    let texels: Vec<u8> = samples
+      .chunks_exact(3)
+      .flat_map(|pixel| {
+        let mut texel: Vec<u8> = pixel.iter().flat_map(|s| s.to_ne_bytes()).collect();
+        texel.resize(texel_size, 0);
+        texel
+      })
+      .collect();
+    upload_tex(gpu, &tex, &texels, 0).unwrap();
+
+    let image = download_image::<Rgb<u16>>(gpu, &tex).unwrap();
+    assert_eq!(image.dimensions(), (5, 3));
+    assert_eq!(image.as_raw(), &samples);
+
+    let frame = target_frame::<Rgb<u16>>(&tex, &pl_color_space::default());
+    assert_eq!(frame.repr.sys, pl_color_system::PL_COLOR_SYSTEM_RGB);
+    assert_eq!(frame.planes[0].component_mapping[..3], [0, 1, 2]);
+
+    unsafe {
+      pl_tex_destroy(gpu.as_ptr(), &mut tex.as_ptr());
+      pl_gpu_dummy_destroy(&mut ptr);
+    }
+  }
+}
@@ -1 +1,4 @@
+pub mod frame_queue;
+#[cfg(feature = "image")]
+pub mod image;
 pub mod upload;
@@ -1 +1,842 @@
-// TODO
+use std::{marker::PhantomData, ptr::null, slice};
+
+use libplacebo_sys::{
+  pl_bit_encoding, pl_color_repr, pl_color_space, pl_fmt_type, pl_frame, pl_plane, pl_plane_data,
+  pl_plane_data_align, pl_plane_data_from_comps, pl_plane_data_from_mask, pl_tex, pl_tex_download,
+  pl_tex_transfer_params, pl_tex_upload, pl_upload_plane,
+};
+use miette::{miette, Result};
+
+use crate::gpu::{Buf, Gpu, Tex};
+
+/// Color family of a planar frame, which decides what its planes contain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorFamily {
+  /// A single luma plane.
+  Gray,
+
+  /// Luma followed by two, possibly subsampled, chroma planes.
+  Yuv,
+
+  /// Red, green and blue planes.
+  Rgb,
+}
+
+/// Layout of a planar frame that stores one component per plane, like
+/// VapourSynth frames or the output of most decoders.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PlanarFormat {
+  pub color_family: ColorFamily,
+
+  /// Either `PL_FMT_UNORM` or `PL_FMT_FLOAT`.
+  pub sample_type: pl_fmt_type,
+
+  /// Number of significant bits in each sample.
+  pub bits_per_sample: i32,
+
+  /// Number of bytes each sample is stored in.
+  pub bytes_per_sample: i32,
+
+  /// Log2 of the horizontal chroma subsampling.
+  pub sub_sampling_w: i32,
+
+  /// Log2 of the vertical chroma subsampling.
+  pub sub_sampling_h: i32,
+}
+
+impl PlanarFormat {
+  #[must_use]
+  pub const fn num_planes(&self) -> i32 {
+    match self.color_family {
+      ColorFamily::Gray => 1,
+      ColorFamily::Yuv | ColorFamily::Rgb => 3,
+    }
+  }
+
+  /// Whether `plane` holds subsampled chroma.
+  #[must_use]
+  pub const fn is_chroma(&self, plane: i32) -> bool {
+    matches!(self.color_family, ColorFamily::Yuv) && plane > 0
+  }
+
+  /// Dimensions of `plane` in a frame of `width`x`height`. Subsampled
+  /// dimensions are rounded up.
+  #[must_use]
+  pub const fn plane_size(&self, plane: i32, width: i32, height: i32) -> (i32, i32) {
+    if self.is_chroma(plane) {
+      (
+        (width + (1 << self.sub_sampling_w) - 1) >> self.sub_sampling_w,
+        (height + (1 << self.sub_sampling_h) - 1) >> self.sub_sampling_h,
+      )
+    } else {
+      (width, height)
+    }
+  }
+
+  /// Component `plane` holds, in libplacebo's semantic order (Y/Cb/Cr or
+  /// R/G/B).
+  #[must_use]
+  pub const fn component(&self, plane: i32) -> i32 {
+    plane
+  }
+
+  /// Bit mask of the bits in a sample that hold data.
+  #[must_use]
+  pub const fn sample_mask(&self) -> u64 {
+    if self.bits_per_sample >= 64 {
+      u64::MAX
+    } else {
+      (1 << self.bits_per_sample) - 1
+    }
+  }
+
+  /// Describes `plane`, which is `width`x`height` pixels in size (see
+  /// [`Self::plane_size`]), without any pixel data attached. The returned bit
+  /// encoding belongs in `pl_color_repr.bits`.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if the samples cannot be padded to whole bytes, which
+  /// libplacebo requires to find a texture format.
+  #[allow(clippy::cast_sign_loss)]
+  pub fn plane_data(
+    &self,
+    plane: i32,
+    width: i32,
+    height: i32,
+  ) -> Result<(pl_plane_data, pl_bit_encoding)> {
+    let mut data = pl_plane_data {
+      type_: self.sample_type,
+      width,
+      height,
+      pixel_stride: self.bytes_per_sample as usize,
+      ..pl_plane_data::default()
+    };
+
+    let mut mask = [0; 4];
+    mask[self.component(plane) as usize] = self.sample_mask();
+    unsafe { pl_plane_data_from_mask(&mut data, mask.as_mut_ptr()) };
+
+    let mut bits = pl_bit_encoding::default();
+    if !unsafe { pl_plane_data_align(&mut data, &mut bits) } {
+      return Err(miette!(
+        "Failed to align {}-bit samples.",
+        self.bits_per_sample
+      ));
+    }
+
+    Ok((data, bits))
+  }
+
+  /// Describes `texture` as `plane` of a frame. Chroma planes still need
+  /// `pl_frame_set_chroma_location` once the whole frame is assembled.
+  #[must_use]
+  pub fn plane(&self, plane: i32, texture: pl_tex) -> pl_plane {
+    pl_plane {
+      texture,
+      components: 1,
+      component_mapping: [self.component(plane), 0, 0, 0],
+      ..pl_plane::default()
+    }
+  }
+
+  /// Describes `textures`, one per plane, as a single frame. `repr.bits` is
+  /// filled in from the format.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if the number of textures does not match the format,
+  /// or if its samples cannot be described.
+  #[allow(clippy::cast_sign_loss)]
+  pub fn frame(
+    &self,
+    textures: &[pl_tex],
+    repr: &pl_color_repr,
+    color: &pl_color_space,
+  ) -> Result<pl_frame> {
+    if textures.len() != self.num_planes() as usize {
+      return Err(miette!(
+        "Expected {} planes, got {}.",
+        self.num_planes(),
+        textures.len()
+      ));
+    }
+
+    let (_, bits) = self.plane_data(0, 1, 1)?;
+
+    let mut frame = pl_frame {
+      num_planes: self.num_planes(),
+      repr: pl_color_repr { bits, ..*repr },
+      color: *color,
+      ..pl_frame::default()
+    };
+
+    for ((plane, &texture), out) in (0..).zip(textures).zip(&mut frame.planes) {
+      *out = self.plane(plane, texture);
+    }
+
+    Ok(frame)
+  }
+}
+
+/// A plane of pixels in host memory, borrowed for `'a`, along with a
+/// description of how its components are stored. Describe the components with
+/// [`Self::from_mask`] or [`Self::from_comps`] before uploading it.
+#[derive(Clone, Copy)]
+pub struct PlaneData<'a> {
+  raw: pl_plane_data,
+  _pixels: PhantomData<&'a [u8]>,
+}
+
+impl<'a> PlaneData<'a> {
+  /// Describes `pixels` as a `width`x`height` plane of `type_` samples,
+  /// `pixel_stride` bytes per pixel and `row_stride` bytes per row. A
+  /// `row_stride` of 0 means the rows are tightly packed.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if the size is empty, the rows overlap or `pixels` is
+  /// too short to hold them.
+  pub fn new(
+    pixels: &'a [u8],
+    type_: pl_fmt_type,
+    width: i32,
+    height: i32,
+    pixel_stride: usize,
+    row_stride: usize,
+  ) -> Result<Self> {
+    let row_stride = if row_stride == 0 {
+      row_bytes(width, pixel_stride)?
+    } else {
+      row_stride
+    };
+    check_len(pixels.len(), width, height, pixel_stride, row_stride)?;
+
+    Ok(Self {
+      raw: pl_plane_data {
+        type_,
+        width,
+        height,
+        pixel_stride,
+        row_stride,
+        pixels: pixels.as_ptr().cast(),
+        ..pl_plane_data::default()
+      },
+      _pixels: PhantomData,
+    })
+  }
+
+  /// Attaches `pixels` to `layout`, a plane described without pixel data,
+  /// e.g. by [`PlanarFormat::plane_data`].
+  ///
+  /// # Errors
+  ///
+  /// Same as [`Self::new`].
+  pub fn with_layout(pixels: &'a [u8], layout: &pl_plane_data, row_stride: usize) -> Result<Self> {
+    let data = Self::new(
+      pixels,
+      layout.type_,
+      layout.width,
+      layout.height,
+      layout.pixel_stride,
+      row_stride,
+    )?;
+
+    Ok(Self {
+      raw: pl_plane_data {
+        component_size: layout.component_size,
+        component_pad: layout.component_pad,
+        component_map: layout.component_map,
+        ..data.raw
+      },
+      ..data
+    })
+  }
+
+  /// Reads the pixels from `buf` instead, which must hold them at the same
+  /// offsets as the slice, e.g. because it imports the slice's memory.
+  #[must_use]
+  pub fn with_buf(mut self, buf: &'a Buf) -> Self {
+    self.raw.buf = buf.as_ptr();
+    self.raw.buf_offset = 0;
+    self
+  }
+
+  /// Describes the components from the bit mask each occupies within a
+  /// pixel, in little-endian order. Unused components have a mask of 0.
+  #[must_use]
+  pub fn from_mask(mut self, mut masks: [u64; 4]) -> Self {
+    unsafe { pl_plane_data_from_mask(&mut self.raw, masks.as_mut_ptr()) };
+    self
+  }
+
+  /// Describes the components from their size and offset within a pixel,
+  /// both in bits. Like the masks, they are given in RGBA order, e.g. sizes
+  /// `[8, 8, 8, 0]` and shifts `[16, 8, 0, 0]` for little-endian BGR.
+  #[must_use]
+  pub fn from_comps(mut self, mut sizes: [i32; 4], mut shifts: [i32; 4]) -> Self {
+    unsafe { pl_plane_data_from_comps(&mut self.raw, sizes.as_mut_ptr(), shifts.as_mut_ptr()) };
+    self
+  }
+
+  /// Pads the components to whole bytes, as libplacebo needs to find a
+  /// texture format for them. Returns the bit encoding of the samples, which
+  /// belongs in `pl_color_repr.bits`.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if the components can't be padded this way.
+  pub fn align(&mut self) -> Result<pl_bit_encoding> {
+    let mut bits = pl_bit_encoding::default();
+    if unsafe { pl_plane_data_align(&mut self.raw, &mut bits) } {
+      Ok(bits)
+    } else {
+      Err(miette!("Failed to align the plane's components."))
+    }
+  }
+
+  #[must_use]
+  pub const fn width(&self) -> i32 {
+    self.raw.width
+  }
+
+  #[must_use]
+  pub const fn height(&self) -> i32 {
+    self.raw.height
+  }
+
+  #[must_use]
+  pub const fn row_stride(&self) -> usize {
+    self.raw.row_stride
+  }
+
+  #[must_use]
+  pub const fn pixel_stride(&self) -> usize {
+    self.raw.pixel_stride
+  }
+
+  /// Number of components, counting up to the last one with a nonzero size.
+  #[must_use]
+  #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+  pub fn num_components(&self) -> i32 {
+    self
+      .raw
+      .component_size
+      .iter()
+      .rposition(|&size| size != 0)
+      .map_or(0, |last| last as i32 + 1)
+  }
+
+  #[must_use]
+  pub const fn component_size(&self) -> [i32; 4] {
+    self.raw.component_size
+  }
+
+  #[must_use]
+  pub const fn component_pad(&self) -> [i32; 4] {
+    self.raw.component_pad
+  }
+
+  #[must_use]
+  pub const fn component_map(&self) -> [i32; 4] {
+    self.raw.component_map
+  }
+
+  #[must_use]
+  pub const fn as_raw(&self) -> &pl_plane_data {
+    &self.raw
+  }
+}
+
+/// Bytes in one row of `width` pixels.
+#[allow(clippy::cast_sign_loss)]
+fn row_bytes(width: i32, pixel_stride: usize) -> Result<usize> {
+  if width <= 0 || pixel_stride == 0 {
+    return Err(miette!("Plane has no pixels."));
+  }
+
+  (width as usize)
+    .checked_mul(pixel_stride)
+    .ok_or_else(|| miette!("Plane is too large."))
+}
+
+/// Checks that `len` bytes hold `height` rows of `width` pixels, `row_pitch`
+/// bytes apart. The last row only needs its pixels, not the whole pitch.
+#[allow(clippy::cast_sign_loss)]
+fn check_len(
+  len: usize,
+  width: i32,
+  height: i32,
+  pixel_stride: usize,
+  row_pitch: usize,
+) -> Result<()> {
+  let row = row_bytes(width, pixel_stride)?;
+  if height <= 0 {
+    return Err(miette!("Plane has no pixels."));
+  }
+
+  if row_pitch < row {
+    return Err(miette!(
+      "Row pitch of {row_pitch} bytes is shorter than a row of {row} bytes."
+    ));
+  }
+
+  let needed = row_pitch
+    .checked_mul(height as usize - 1)
+    .and_then(|rows| rows.checked_add(row))
+    .ok_or_else(|| miette!("Plane is too large."))?;
+  if len < needed {
+    return Err(miette!(
+      "Buffer of {len} bytes is too short for {width}x{height} pixels with a row pitch of {row_pitch} bytes, which need {needed}."
+    ));
+  }
+
+  Ok(())
+}
+
+/// Alignment a transfer's row pitch needs on `gpu`, for texels of
+/// `texel_size` bytes. libplacebo requires a multiple of the texel size and
+/// the GPU prefers a multiple of `align_tex_xfer_pitch`.
+#[must_use]
+pub fn pitch_alignment(gpu: Gpu, texel_size: usize) -> usize {
+  let (mut a, mut b) = (texel_size.max(1), gpu.limits().align_tex_xfer_pitch.max(1));
+  let product = a * b;
+  while b != 0 {
+    (a, b) = (b, a % b);
+  }
+  product / a
+}
+
+/// Copies `height` rows of `row` bytes from `src`, with rows `src_pitch`
+/// apart, to `dst`, with rows `dst_pitch` apart.
+fn copy_rows(dst: &mut [u8], dst_pitch: usize, src: &[u8], src_pitch: usize, row: usize) {
+  for (dst, src) in dst.chunks_mut(dst_pitch).zip(src.chunks(src_pitch)) {
+    dst[..row].copy_from_slice(&src[..row]);
+  }
+}
+
+/// `height` rows of `row` bytes, copied from `src` into a staging buffer
+/// whose row pitch is the smallest multiple of `alignment` that fits a row.
+/// Returns the buffer and its row pitch.
+#[allow(clippy::cast_sign_loss)]
+fn stage_rows(
+  src: &[u8],
+  src_pitch: usize,
+  row: usize,
+  height: i32,
+  alignment: usize,
+) -> (Vec<u8>, usize) {
+  let pitch = row.next_multiple_of(alignment);
+  let mut staging = vec![0; pitch * height as usize];
+  copy_rows(&mut staging, pitch, src, src_pitch, row);
+  (staging, pitch)
+}
+
+/// Uploads `data` to `tex`, first creating it or, if it doesn't fit the
+/// plane, recreating it. Returns the plane, which refers to `tex`.
+///
+/// Planes whose row stride doesn't meet the GPU's alignment are copied to a
+/// suitably aligned staging buffer first, unless they're read from a buffer.
+///
+/// # Errors
+///
+/// Will return `Err` if there's no texture format for the plane, if a plane
+/// read from a buffer isn't aligned, or if `pl_upload_plane()` fails
+/// otherwise.
+#[allow(clippy::cast_sign_loss)]
+pub fn upload_plane(gpu: Gpu, tex: &mut Option<Tex>, data: &PlaneData) -> Result<pl_plane> {
+  let alignment = pitch_alignment(gpu, data.pixel_stride());
+  let aligned = data.row_stride() % alignment == 0;
+  if !aligned && !data.raw.buf.is_null() {
+    return Err(miette!(
+      "Row stride of {} bytes is not a multiple of {alignment}.",
+      data.row_stride()
+    ));
+  }
+
+  let staged = (!aligned).then(|| {
+    let row = data.width() as usize * data.pixel_stride();
+    let len = data.row_stride() * (data.height() as usize - 1) + row;
+    let src = unsafe { slice::from_raw_parts(data.raw.pixels.cast::<u8>(), len) };
+    stage_rows(src, data.row_stride(), row, data.height(), alignment)
+  });
+
+  let raw = match &staged {
+    Some((staging, pitch)) => pl_plane_data {
+      row_stride: *pitch,
+      pixels: staging.as_ptr().cast(),
+      ..data.raw
+    },
+    None => data.raw,
+  };
+
+  let mut ptr = tex.as_ref().map_or(null(), Tex::as_ptr);
+  let mut plane = pl_plane::default();
+  let ok = unsafe { pl_upload_plane(gpu.as_ptr(), &mut plane, &mut ptr, &raw) };
+
+  // The texture may have been created or recreated even if the upload failed.
+  *tex = (!ptr.is_null()).then(|| unsafe { Tex::new_unchecked(ptr.cast_mut()) });
+
+  if ok {
+    Ok(plane)
+  } else {
+    Err(miette!("Failed to upload plane."))
+  }
+}
+
+/// Uploads `pixels`, whose rows are `row_pitch` bytes apart, to all of
+/// `tex`. A `row_pitch` of 0 means the rows are tightly packed. Rows that
+/// don't meet the GPU's alignment go through a staging buffer.
+///
+/// # Errors
+///
+/// Will return `Err` if `pixels` is too short for the texture, or if
+/// `pl_tex_upload()` fails.
+pub fn upload_tex(gpu: Gpu, tex: &Tex, pixels: &[u8], row_pitch: usize) -> Result<()> {
+  let (row_pitch, row, alignment) = tex_row_pitch(gpu, tex, pixels.len(), row_pitch)?;
+
+  let staged = (row_pitch % alignment != 0)
+    .then(|| stage_rows(pixels, row_pitch, row, tex.height(), alignment));
+  let (pixels, row_pitch) = staged
+    .as_ref()
+    .map_or((pixels, row_pitch), |(staging, pitch)| (staging, *pitch));
+
+  let ok = unsafe {
+    pl_tex_upload(
+      gpu.as_ptr(),
+      &pl_tex_transfer_params {
+        tex: tex.as_ptr(),
+        row_pitch,
+        ptr: pixels.as_ptr().cast_mut().cast(),
+        ..pl_tex_transfer_params::default()
+      },
+    )
+  };
+
+  if ok {
+    Ok(())
+  } else {
+    Err(miette!("Failed to upload texture."))
+  }
+}
+
+/// Downloads all of `tex` into `pixels`, with rows `row_pitch` bytes apart.
+/// A `row_pitch` of 0 means the rows are tightly packed. Rows that don't
+/// meet the GPU's alignment go through a staging buffer.
+///
+/// # Errors
+///
+/// Will return `Err` if `pixels` is too short for the texture, or if
+/// `pl_tex_download()` fails.
+#[allow(clippy::cast_sign_loss)]
+pub fn download_tex(gpu: Gpu, tex: &Tex, pixels: &mut [u8], row_pitch: usize) -> Result<()> {
+  let (row_pitch, row, alignment) = tex_row_pitch(gpu, tex, pixels.len(), row_pitch)?;
+
+  let mut staging = (row_pitch % alignment != 0).then(|| {
+    let pitch = row.next_multiple_of(alignment);
+    (vec![0; pitch * tex.height() as usize], pitch)
+  });
+  let (ptr, pitch) = match &mut staging {
+    Some((staging, pitch)) => (staging.as_mut_ptr(), *pitch),
+    None => (pixels.as_mut_ptr(), row_pitch),
+  };
+
+  let ok = unsafe {
+    pl_tex_download(
+      gpu.as_ptr(),
+      &pl_tex_transfer_params {
+        tex: tex.as_ptr(),
+        row_pitch: pitch,
+        ptr: ptr.cast(),
+        ..pl_tex_transfer_params::default()
+      },
+    )
+  };
+
+  if !ok {
+    return Err(miette!("Failed to download texture."));
+  }
+
+  if let Some((staging, pitch)) = &staging {
+    copy_rows(pixels, row_pitch, staging, *pitch, row);
+  }
+
+  Ok(())
+}
+
+/// Resolves a `row_pitch` of 0 for `tex` and checks that `len` bytes hold the
+/// whole texture. Returns the row pitch, the size of a row and the alignment
+/// the pitch needs.
+fn tex_row_pitch(
+  gpu: Gpu,
+  tex: &Tex,
+  len: usize,
+  row_pitch: usize,
+) -> Result<(usize, usize, usize)> {
+  let texel_size = tex.format().texel_size();
+  let row = row_bytes(tex.width(), texel_size)?;
+  let row_pitch = if row_pitch == 0 { row } else { row_pitch };
+
+  check_len(len, tex.width(), tex.height(), texel_size, row_pitch)?;
+  Ok((row_pitch, row, pitch_alignment(gpu, texel_size)))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::ptr::null;
+
+  use libplacebo_sys::{
+    pl_gpu_dummy_create, pl_gpu_dummy_destroy, pl_plane_find_fmt, pl_tex_create, pl_tex_destroy,
+    pl_tex_params,
+  };
+
+  use super::*;
+  use crate::log::Log;
+
+  const YUV420P10: PlanarFormat = PlanarFormat {
+    color_family: ColorFamily::Yuv,
+    sample_type: pl_fmt_type::PL_FMT_UNORM,
+    bits_per_sample: 10,
+    bytes_per_sample: 2,
+    sub_sampling_w: 1,
+    sub_sampling_h: 1,
+  };
+
+  const RGBS: PlanarFormat = PlanarFormat {
+    color_family: ColorFamily::Rgb,
+    sample_type: pl_fmt_type::PL_FMT_FLOAT,
+    bits_per_sample: 32,
+    bytes_per_sample: 4,
+    sub_sampling_w: 0,
+    sub_sampling_h: 0,
+  };
+
+  const GRAY8: PlanarFormat = PlanarFormat {
+    color_family: ColorFamily::Gray,
+    sample_type: pl_fmt_type::PL_FMT_UNORM,
+    bits_per_sample: 8,
+    bytes_per_sample: 1,
+    sub_sampling_w: 0,
+    sub_sampling_h: 0,
+  };
+
+  #[test]
+  fn subsampled_plane_sizes_round_up() {
+    assert_eq!(YUV420P10.plane_size(0, 1921, 1081), (1921, 1081));
+    assert_eq!(YUV420P10.plane_size(1, 1921, 1081), (961, 541));
+    assert_eq!(YUV420P10.plane_size(2, 1920, 1080), (960, 540));
+    assert_eq!(RGBS.plane_size(2, 1921, 1081), (1921, 1081));
+  }
+
+  #[test]
+  fn high_bit_depth_is_padded() {
+    for plane in 0..YUV420P10.num_planes() {
+      let (data, bits) = YUV420P10.plane_data(plane, 64, 64).unwrap();
+      assert_eq!(data.pixel_stride, 2);
+      assert_eq!(data.component_size[0], 16);
+      assert_eq!(data.component_map[0], plane);
+      assert_eq!(bits.sample_depth, 16);
+      assert_eq!(bits.color_depth, 10);
+      assert_eq!(bits.bit_shift, 0);
+    }
+  }
+
+  #[test]
+  fn float_and_gray_planes() {
+    let (data, bits) = RGBS.plane_data(1, 8, 8).unwrap();
+    assert_eq!(data.type_, pl_fmt_type::PL_FMT_FLOAT);
+    assert_eq!(data.component_size[0], 32);
+    assert_eq!(data.component_map[0], 1);
+    assert_eq!(bits.color_depth, 32);
+
+    let (data, bits) = GRAY8.plane_data(0, 8, 8).unwrap();
+    assert_eq!(data.component_size[0], 8);
+    assert_eq!(data.component_map[0], 0);
+    assert_eq!(bits.sample_depth, 8);
+  }
+
+  #[test]
+  fn frame_maps_planes_to_components() {
+    let textures = [null(); 3];
+    let frame = YUV420P10
+      .frame(
+        &textures,
+        &pl_color_repr::default(),
+        &pl_color_space::default(),
+      )
+      .unwrap();
+
+    assert_eq!(frame.num_planes, 3);
+    assert_eq!(frame.repr.bits.color_depth, 10);
+    for (i, plane) in (0..).zip(&frame.planes[..3]) {
+      assert_eq!(plane.components, 1);
+      assert_eq!(plane.component_mapping[0], i);
+    }
+
+    assert!(GRAY8
+      .frame(
+        &textures,
+        &pl_color_repr::default(),
+        &pl_color_space::default()
+      )
+      .is_err());
+    assert_eq!(
+      GRAY8
+        .frame(
+          &textures[..1],
+          &pl_color_repr::default(),
+          &pl_color_space::default()
+        )
+        .unwrap()
+        .num_planes,
+      1
+    );
+  }
+
+  #[test]
+  fn plane_data_checks_buffer_length() {
+    let pixels = [0u8; 100];
+    let unorm = pl_fmt_type::PL_FMT_UNORM;
+
+    let data = PlaneData::new(&pixels, unorm, 10, 10, 1, 0).unwrap();
+    assert_eq!(data.row_stride(), 10);
+
+    // The last row doesn't need padding.
+    assert!(PlaneData::new(&pixels[..94], unorm, 4, 10, 1, 10).is_ok());
+    assert!(PlaneData::new(&pixels[..93], unorm, 4, 10, 1, 10).is_err());
+
+    assert!(PlaneData::new(&pixels, unorm, 10, 11, 1, 0).is_err());
+    assert!(PlaneData::new(&pixels, unorm, 10, 5, 2, 16).is_err());
+    assert!(PlaneData::new(&pixels, unorm, 0, 5, 1, 0).is_err());
+    assert!(PlaneData::new(&pixels, unorm, 5, 5, 0, 0).is_err());
+  }
+
+  #[test]
+  fn plane_data_components() {
+    let pixels = [0u8; 64];
+
+    let rgb565 = PlaneData::new(&pixels, pl_fmt_type::PL_FMT_UNORM, 4, 8, 2, 0)
+      .unwrap()
+      .from_mask([0xF800, 0x07E0, 0x001F, 0]);
+    assert_eq!(rgb565.num_components(), 3);
+    assert_eq!(rgb565.component_size(), [5, 6, 5, 0]);
+    assert_eq!(rgb565.component_map()[..3], [2, 1, 0]);
+
+    let mut bgr = PlaneData::new(&pixels, pl_fmt_type::PL_FMT_UNORM, 4, 4, 3, 0)
+      .unwrap()
+      .from_comps([8, 8, 8, 0], [16, 8, 0, 0]);
+    assert_eq!(bgr.num_components(), 3);
+    assert_eq!(bgr.component_map()[..3], [2, 1, 0]);
+    assert_eq!(bgr.align().unwrap().sample_depth, 8);
+
+    let (layout, _) = YUV420P10.plane_data(1, 4, 4).unwrap();
+    let chroma = PlaneData::with_layout(&pixels, &layout, 0).unwrap();
+    assert_eq!(chroma.row_stride(), 8);
+    assert_eq!(chroma.component_size()[0], 16);
+    assert_eq!(chroma.component_map()[0], 1);
+  }
+
+  /// Uploads a pattern with a padded row stride to the dummy GPU and
+  /// downloads it again with a different one, for every combination of
+  /// width, sample format, chroma subsampling and stride.
+  #[test]
+  #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+  fn transfers_handle_arbitrary_strides() {
+    let log = Log::default();
+    let mut gpu_ptr = unsafe { pl_gpu_dummy_create(log.0, null()) };
+    assert!(!gpu_ptr.is_null());
+    let gpu = unsafe { Gpu::new_unchecked(gpu_ptr.cast_mut()) };
+
+    let formats = [
+      (pl_fmt_type::PL_FMT_UNORM, 8, 1),
+      (pl_fmt_type::PL_FMT_UNORM, 10, 2),
+      (pl_fmt_type::PL_FMT_UNORM, 16, 2),
+      (pl_fmt_type::PL_FMT_FLOAT, 32, 4),
+    ];
+
+    for width in [1, 2, 3, 5, 16, 33, 127] {
+      for (sample_type, bits_per_sample, bytes_per_sample) in formats {
+        for (sub_sampling_w, sub_sampling_h) in [(0, 0), (1, 0), (1, 1)] {
+          let format = PlanarFormat {
+            color_family: ColorFamily::Yuv,
+            sample_type,
+            bits_per_sample,
+            bytes_per_sample,
+            sub_sampling_w,
+            sub_sampling_h,
+          };
+
+          for plane in 0..format.num_planes() {
+            let (w, h) = format.plane_size(plane, width, 5);
+            let (layout, _) = format.plane_data(plane, w, h).unwrap();
+            let row = w as usize * bytes_per_sample as usize;
+
+            for (src_pitch, dst_pitch) in [
+              (0, 0),
+              (row + 1, row + 3),
+              (row.next_multiple_of(64), row + 2),
+            ] {
+              let src_len = src_pitch.max(row) * h as usize;
+              let src: Vec<u8> = (0..src_len).map(|i| (i * 7 + 3) as u8).collect();
+              let data = PlaneData::with_layout(&src, &layout, src_pitch).unwrap();
+
+              let fmt =
+                unsafe { pl_plane_find_fmt(gpu.as_ptr(), [0; 4].as_mut_ptr(), data.as_raw()) };
+              assert!(!fmt.is_null(), "no format for {width} {format:?}");
+              let tex_ptr = unsafe {
+                pl_tex_create(
+                  gpu.as_ptr(),
+                  &pl_tex_params {
+                    w,
+                    h,
+                    format: fmt,
+                    sampleable: true,
+                    host_writable: true,
+                    host_readable: true,
+                    ..pl_tex_params::default()
+                  },
+                )
+              };
+              let mut tex = Some(unsafe { Tex::new_unchecked(tex_ptr.cast_mut()) });
+
+              upload_plane(gpu, &mut tex, &data).unwrap();
+              let tex = tex.unwrap();
+
+              let dst_pitch = dst_pitch.max(row);
+              let mut dst = vec![0; dst_pitch * (h as usize - 1) + row];
+              download_tex(gpu, &tex, &mut dst, dst_pitch).unwrap();
+
+              let src_pitch = data.row_stride();
+              for y in 0..h as usize {
+                assert_eq!(
+                  dst[y * dst_pitch..][..row],
+                  src[y * src_pitch..][..row],
+                  "row {y} of plane {plane}, {w}x{h} {format:?}, pitches {src_pitch}/{dst_pitch}"
+                );
+              }
+
+              unsafe { pl_tex_destroy(gpu.as_ptr(), &mut tex.as_ptr()) };
+            }
+          }
+        }
+      }
+    }
+
+    unsafe { pl_gpu_dummy_destroy(&mut gpu_ptr) };
+  }
+
+  #[test]
+  fn misaligned_rows_are_staged() {
+    let src: Vec<u8> = (0..=255).collect();
+    let (staging, pitch) = stage_rows(&src, 10, 6, 4, 4);
+    assert_eq!(pitch, 8);
+    assert_eq!(staging.len(), 32);
+    assert_eq!(staging[8..14], src[10..16]);
+    assert_eq!(staging[24..30], src[30..36]);
+
+    let mut dst = [0u8; 40];
+    copy_rows(&mut dst, 12, &staging, pitch, 6);
+    assert_eq!(dst[12..18], src[10..16]);
+    assert_eq!(dst[18..24], [0; 6]);
+  }
+}
@@ -0,0 +1,58 @@
+//! Version of the libplacebo headers the bindings were generated from.
+//!
+//! Wrappers for features newer than [`MIN_API_VERSION`] are only compiled in
+//! when the headers provide them, see `build.rs`. At runtime, the library
+//! must match the headers exactly: libplacebo exports `pl_log_create` under a
+//! name suffixed with its API version, so an older or newer library fails to
+//! load instead of misbehaving.
+
+use libplacebo_sys::{MIN_API_VER, PL_API_VER, PL_MAJOR_VER};
+use miette::{miette, Result};
+
+/// API version of the headers, `PL_API_VER`.
+pub const API_VERSION: u32 = PL_API_VER;
+
+/// Major version of the headers, `PL_MAJOR_VER`.
+pub const MAJOR_VERSION: u32 = PL_MAJOR_VER;
+
+/// Oldest API version the bindings support.
+pub const MIN_API_VERSION: u32 = MIN_API_VER;
+
+/// Checks that `api_ver`, as requested by a caller of [`crate::log::Log`],
+/// is one libplacebo will accept. It aborts the process when handed any
+/// other version than its own.
+///
+/// # Errors
+///
+/// Will return `Err` if `api_ver` is older than [`MIN_API_VERSION`] or does
+/// not match [`API_VERSION`].
+pub fn check_api_version(api_ver: i32) -> Result<()> {
+  let api_ver = u32::try_from(api_ver).map_err(|_| miette!("Invalid API version {api_ver}."))?;
+
+  if api_ver < MIN_API_VERSION {
+    Err(miette!(
+      "libplacebo API version {api_ver} is older than the oldest supported, {MIN_API_VERSION}."
+    ))
+  } else if api_ver != API_VERSION {
+    Err(miette!(
+      "libplacebo API version {api_ver} was requested, but the bindings were generated for \
+       {API_VERSION}."
+    ))
+  } else {
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  #[allow(clippy::cast_possible_wrap)]
+  fn checks_api_version() {
+    assert!(check_api_version(API_VERSION as i32).is_ok());
+    assert!(check_api_version(MIN_API_VERSION as i32 - 1).is_err());
+    assert!(check_api_version(API_VERSION as i32 + 1).is_err());
+    assert!(check_api_version(-1).is_err());
+  }
+}
@@ -1,12 +1,18 @@
-use std::ptr::NonNull;
+use std::ffi::CStr;
 
 use libplacebo_sys::{
-  pl_fmt_t, pl_gpu, pl_plane, pl_plane_data, pl_plane_find_fmt, pl_tex_create, pl_tex_destroy,
-  pl_tex_download, pl_tex_params, pl_tex_transfer_params, pl_tex_upload, pl_upload_plane,
-  pl_vulkan, pl_vulkan_create, pl_vulkan_destroy, pl_vulkan_params,
+  pl_buf_create, pl_buf_destroy, pl_buf_params, pl_buf_poll, pl_buf_read, pl_buf_write,
+  pl_find_fmt, pl_find_named_fmt, pl_find_vertex_fmt, pl_fmt_caps, pl_fmt_type, pl_gpu, pl_handle,
+  pl_handle_type, pl_plane, pl_plane_data, pl_plane_find_fmt, pl_shared_mem, pl_tex_create,
+  pl_tex_destroy, pl_tex_download, pl_tex_params, pl_tex_transfer_params, pl_tex_upload,
+  pl_timer_create, pl_timer_destroy, pl_timer_query, pl_upload_plane, pl_vulkan, pl_vulkan_create,
+  pl_vulkan_destroy, pl_vulkan_params,
 };
 
-use crate::{gpu::Tex, log::Log};
+use crate::{
+  gpu::{Buf, Format, Gpu, Tex, Timer},
+  log::Log,
+};
 use miette::{miette, Result};
 
 pub struct Vulkan(pub(crate) pl_vulkan);
@@ -26,32 +32,86 @@ impl Vulkan {
     }
   }
 
+  /// Like [`Self::new`], but returns `None` if no device could be created,
+  /// e.g. because there is no Vulkan driver.
+  #[must_use]
+  pub fn try_new(log: &Log, params: &pl_vulkan_params) -> Option<Self> {
+    debug_assert!(!log.0.is_null());
+
+    let ptr = unsafe { pl_vulkan_create(log.0, params) };
+    (!ptr.is_null()).then(|| Self(ptr))
+  }
+
   #[must_use]
   pub fn gpu(&self) -> pl_gpu {
     unsafe { (*self.0).gpu }
   }
 
+  /// Vulkan API version the device was created with, encoded as by
+  /// `VK_MAKE_API_VERSION`.
+  #[must_use]
+  pub fn api_version(&self) -> u32 {
+    unsafe { (*self.0).api_version }
+  }
+
+  /// The GPU of this context, for querying its limits and formats.
+  ///
+  /// # Panics
+  ///
+  /// Will panic if the context has no GPU.
+  #[must_use]
+  pub fn as_gpu(&self) -> Gpu {
+    let gpu = self.gpu();
+    assert!(!gpu.is_null());
+    unsafe { Gpu::new_unchecked(gpu.cast_mut()) }
+  }
+
   /// Helper function to find a suitable `pl_fmt` based on a `pl_plane_data`'s
   /// requirements. This is called internally by `pl_upload_plane`, but it's
   /// exposed to users both as a convenience and so they may preemptively check
   /// if a format would be supported without actually having to attempt the
   /// upload.
   #[must_use]
-  pub fn plane_find_fmt(&self, data: &pl_plane_data) -> Option<NonNull<pl_fmt_t>> {
-    unsafe {
-      let format = pl_plane_find_fmt(self.gpu(), &mut 0, data);
-      if format.is_null() {
-        None
-      } else {
-        // Note that this *must* return the raw pointer, because the precise,
-        // unchanged address is necessary later. libplacebo has a `PL_PRIV`
-        // function that stores public and private structs next to each other in
-        // memory, and it is used during Vulkan texture generation.
-        // Some(format)
+  pub fn plane_find_fmt(&self, data: &pl_plane_data) -> Option<Format> {
+    Format::from_ptr(unsafe { pl_plane_find_fmt(self.gpu(), [0; 4].as_mut_ptr(), data) })
+  }
 
-        Some(NonNull::new_unchecked(format.cast_mut()))
-      }
-    }
+  /// Find the best format with at least `num_components` components of
+  /// `min_depth` bits each, which supports all of `caps`. If `host_bits` is
+  /// nonzero, the format must also be packed in host memory with exactly that
+  /// many bits per component, so that it can be uploaded to directly.
+  #[must_use]
+  pub fn find_fmt(
+    &self,
+    type_: pl_fmt_type,
+    num_components: i32,
+    min_depth: i32,
+    host_bits: i32,
+    caps: pl_fmt_caps,
+  ) -> Option<Format> {
+    Format::from_ptr(unsafe {
+      pl_find_fmt(
+        self.gpu(),
+        type_,
+        num_components,
+        min_depth,
+        host_bits,
+        caps,
+      )
+    })
+  }
+
+  /// Find a format by its name, e.g. `rgba16f`.
+  #[must_use]
+  pub fn find_named_fmt(&self, name: &CStr) -> Option<Format> {
+    Format::from_ptr(unsafe { pl_find_named_fmt(self.gpu(), name.as_ptr()) })
+  }
+
+  /// Find a format usable as a vertex attribute with `num_components`
+  /// components.
+  #[must_use]
+  pub fn find_vertex_fmt(&self, type_: pl_fmt_type, num_components: i32) -> Option<Format> {
+    Format::from_ptr(unsafe { pl_find_vertex_fmt(self.gpu(), type_, num_components) })
   }
 
   /// Create a texture (with undefined contents). This is assumed to be an
@@ -89,7 +149,7 @@ impl Vulkan {
   /// TODO
   pub fn tex_download(&self, params: &pl_tex_transfer_params) -> Result<()> {
     assert!(!self.0.is_null());
-    assert!(!params.ptr.is_null());
+    assert!(!params.ptr.is_null() || !params.buf.is_null());
     assert!(!params.tex.is_null());
 
     unsafe {
@@ -119,6 +179,135 @@ impl Vulkan {
     }
   }
 
+  /// Create a buffer. Buffers created with `host_mapped` stay mapped for their
+  /// whole lifetime, see [`Buf::data`].
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if `pl_buf_create()` fails, e.g. because the requested
+  /// capabilities or import handle are not supported.
+  pub fn buf_create(&self, params: &pl_buf_params) -> Result<Buf> {
+    let buf = unsafe { pl_buf_create(self.gpu(), params) };
+    if buf.is_null() {
+      Err(miette!("Failed to create buffer."))
+    } else {
+      Ok(unsafe { Buf::new_unchecked(buf.cast_mut()) })
+    }
+  }
+
+  pub fn buf_destroy(&self, buf: &Buf) {
+    unsafe {
+      pl_buf_destroy(self.gpu(), &mut buf.as_ptr());
+    }
+  }
+
+  /// Alignment required of host memory imported with
+  /// [`Self::buf_import_host_ptr`], or `None` if the GPU cannot import host
+  /// memory.
+  #[must_use]
+  pub fn host_ptr_alignment(&self) -> Option<usize> {
+    let gpu = self.as_gpu();
+    let supported = gpu.import_caps().buf & pl_handle_type::PL_HANDLE_HOST_PTR as u64 != 0;
+    supported.then(|| gpu.limits().align_host_ptr.max(1))
+  }
+
+  /// Wraps `size` bytes of host memory at `ptr` in a buffer, so that
+  /// transfers read from or write to it directly.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if the import fails, e.g. because `ptr` or `size` are
+  /// not aligned to [`Self::host_ptr_alignment`].
+  ///
+  /// # Safety
+  ///
+  /// The memory must stay valid, and must not be accessed from the host
+  /// while the GPU may be using it, until the buffer is no longer in use
+  /// (see [`Self::buf_poll`]) and has been destroyed.
+  pub unsafe fn buf_import_host_ptr(&self, ptr: *mut u8, size: usize) -> Result<Buf> {
+    self.buf_create(&pl_buf_params {
+      size,
+      import_handle: pl_handle_type::PL_HANDLE_HOST_PTR,
+      shared_mem: pl_shared_mem {
+        handle: pl_handle { ptr: ptr.cast() },
+        size,
+        ..pl_shared_mem::default()
+      },
+      ..pl_buf_params::default()
+    })
+  }
+
+  /// Write `data` to `buf` at `offset`. The buffer must have been created
+  /// with `host_writable`.
+  pub fn buf_write(&self, buf: &Buf, offset: usize, data: &[u8]) {
+    debug_assert!(offset + data.len() <= buf.size());
+
+    unsafe {
+      pl_buf_write(
+        self.gpu(),
+        buf.as_ptr(),
+        offset,
+        data.as_ptr().cast(),
+        data.len(),
+      );
+    }
+  }
+
+  /// Read `dest.len()` bytes of `buf` at `offset`, blocking until the GPU is
+  /// done with it. The buffer must have been created with `host_readable`.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if `pl_buf_read()` is unsuccessful.
+  pub fn buf_read(&self, buf: &Buf, offset: usize, dest: &mut [u8]) -> Result<()> {
+    debug_assert!(offset + dest.len() <= buf.size());
+
+    let success = unsafe {
+      pl_buf_read(
+        self.gpu(),
+        buf.as_ptr(),
+        offset,
+        dest.as_mut_ptr().cast(),
+        dest.len(),
+      )
+    };
+
+    if success {
+      Ok(())
+    } else {
+      Err(miette!("Failed to read buffer."))
+    }
+  }
+
+  /// Returns whether `buf` is still in use by the GPU, waiting up to
+  /// `timeout` nanoseconds for it to become free.
+  #[must_use]
+  pub fn buf_poll(&self, buf: &Buf, timeout: u64) -> bool {
+    unsafe { pl_buf_poll(self.gpu(), buf.as_ptr(), timeout) }
+  }
+
+  /// Create a timer. Returns `None` if the GPU does not support timers.
+  #[must_use]
+  pub fn timer_create(&self) -> Option<Timer> {
+    let timer = unsafe { pl_timer_create(self.gpu()) };
+    (!timer.is_null()).then(|| unsafe { Timer::new_unchecked(timer) })
+  }
+
+  pub fn timer_destroy(&self, timer: &Timer) {
+    unsafe {
+      pl_timer_destroy(self.gpu(), &mut timer.as_ptr());
+    }
+  }
+
+  /// Returns the oldest measurement of `timer` that has not been queried yet,
+  /// in nanoseconds, or 0 if there is none. Measurements become available
+  /// some time after the GPU finishes the timed operation, so this should be
+  /// called repeatedly until it returns 0.
+  #[must_use]
+  pub fn timer_query(&self, timer: &Timer) -> u64 {
+    unsafe { pl_timer_query(self.gpu(), timer.as_ptr()) }
+  }
+
   /// Upload an image plane to a texture. `tex` will be destroyed and
   /// reinitialized if it is incompatible incompatible.
   ///
@@ -163,6 +352,16 @@ mod tests {
   #[test]
   fn can_create_vulkan() {
     let log = Log::default();
-    let _vk = Vulkan::new(&log, &pl_vulkan_params::default());
+    let Some(vk) = Vulkan::try_new(
+      &log,
+      &pl_vulkan_params {
+        allow_software: true,
+        ..pl_vulkan_params::default()
+      },
+    ) else {
+      eprintln!("No Vulkan device available, skipping.");
+      return;
+    };
+    assert!(!vk.gpu().is_null());
   }
 }
@@ -0,0 +1,486 @@
+//! Golden-image harness. Tests render synthetic frames on a Vulkan device,
+//! preferably a software one like lavapipe so that results are reproducible,
+//! and compare them against references in `tests/golden` by PSNR and SSIM.
+//!
+//! Tests are skipped when there is no Vulkan device. A missing reference fails
+//! the test, so that a run never passes without comparing anything; record
+//! references, and re-record them after intended changes, with
+//! `PLACEBO_BLESS=1`.
+
+use std::{
+  cell::RefCell,
+  env, fs,
+  io::{BufRead, BufReader, Read},
+  path::PathBuf,
+};
+
+use libplacebo_rs::{
+  dispatch::Dispatch,
+  gpu::{Gpu, Tex},
+  log::Log,
+  renderer::Renderer,
+  shaders_root::Shader,
+  utils::upload::{download_tex, upload_tex},
+  vulkan::Vulkan,
+};
+use libplacebo_sys::{
+  pl_dispatch_params, pl_fmt_caps, pl_fmt_type, pl_shader_params, pl_tex_params, pl_vulkan_params,
+};
+
+/// A frame in host memory: 16-bit samples with `channels` interleaved
+/// components per pixel.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+  pub width: usize,
+  pub height: usize,
+  pub channels: usize,
+  pub data: Vec<u16>,
+}
+
+impl Image {
+  /// An image whose samples are `f(x, y, channel)`, in 0.0 to 1.0.
+  #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+  pub fn from_fn(
+    width: usize,
+    height: usize,
+    channels: usize,
+    f: impl Fn(usize, usize, usize) -> f64,
+  ) -> Self {
+    let mut data = Vec::with_capacity(width * height * channels);
+    for y in 0..height {
+      for x in 0..width {
+        for c in 0..channels {
+          data.push((f(x, y, c).clamp(0.0, 1.0) * 65535.0).round() as u16);
+        }
+      }
+    }
+
+    Self {
+      width,
+      height,
+      channels,
+      data,
+    }
+  }
+
+  /// Combines single channel images of the same size into one image.
+  pub fn interleave(planes: &[Self]) -> Self {
+    let first = &planes[0];
+    assert!(planes
+      .iter()
+      .all(|p| p.channels == 1 && (p.width, p.height) == (first.width, first.height)));
+
+    Self {
+      width: first.width,
+      height: first.height,
+      channels: planes.len(),
+      data: (0..first.data.len())
+        .flat_map(|i| planes.iter().map(move |p| p.data[i]))
+        .collect(),
+    }
+  }
+
+  pub fn sample(&self, x: usize, y: usize, channel: usize) -> f64 {
+    f64::from(self.data[(y * self.width + x) * self.channels + channel]) / 65535.0
+  }
+
+  /// Mean of all samples, in 0.0 to 1.0.
+  #[allow(clippy::cast_precision_loss)]
+  pub fn mean(&self) -> f64 {
+    self.data.iter().map(|&s| f64::from(s)).sum::<f64>() / self.data.len() as f64 / 65535.0
+  }
+
+  /// Number of distinct sample values, a rough measure of banding.
+  pub fn distinct_values(&self) -> usize {
+    let mut values = self.data.clone();
+    values.sort_unstable();
+    values.dedup();
+    values.len()
+  }
+
+  fn bytes(&self) -> Vec<u8> {
+    self.data.iter().flat_map(|s| s.to_ne_bytes()).collect()
+  }
+
+  /// Encodes the image as a PAM file, with big-endian 16-bit samples.
+  pub fn to_pam(&self) -> Vec<u8> {
+    let tuple_type = match self.channels {
+      1 => "GRAYSCALE",
+      3 => "RGB",
+      4 => "RGB_ALPHA",
+      _ => "UNKNOWN",
+    };
+
+    let mut pam = format!(
+      "P7\nWIDTH {}\nHEIGHT {}\nDEPTH {}\nMAXVAL 65535\nTUPLTYPE {tuple_type}\nENDHDR\n",
+      self.width, self.height, self.channels
+    )
+    .into_bytes();
+    pam.extend(self.data.iter().flat_map(|s| s.to_be_bytes()));
+    pam
+  }
+
+  /// Decodes a PAM file written by [`Self::to_pam`].
+  pub fn from_pam(pam: &[u8]) -> Option<Self> {
+    let mut reader = BufReader::new(pam);
+    let (mut width, mut height, mut channels) = (0, 0, 0);
+
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    if line.trim_end() != "P7" {
+      return None;
+    }
+
+    loop {
+      line.clear();
+      reader.read_line(&mut line).ok()?;
+      match line.trim_end().split_once(' ') {
+        Some(("WIDTH", value)) => width = value.parse().ok()?,
+        Some(("HEIGHT", value)) => height = value.parse().ok()?,
+        Some(("DEPTH", value)) => channels = value.parse().ok()?,
+        Some(("MAXVAL", "65535") | ("TUPLTYPE", _)) => {}
+        None if line.trim_end() == "ENDHDR" => break,
+        _ => return None,
+      }
+    }
+
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes).ok()?;
+    if bytes.len() != width * height * channels * 2 {
+      return None;
+    }
+
+    Some(Self {
+      width,
+      height,
+      channels,
+      data: bytes
+        .chunks_exact(2)
+        .map(|s| u16::from_be_bytes([s[0], s[1]]))
+        .collect(),
+    })
+  }
+}
+
+/// Horizontal and vertical ramp over the whole range.
+#[allow(clippy::cast_precision_loss)]
+pub fn gradient(width: usize, height: usize) -> Image {
+  Image::from_fn(width, height, 1, |x, y, _| {
+    (x + y) as f64 / (width + height - 2) as f64
+  })
+}
+
+/// A dark horizontal ramp quantized to `steps` levels, i.e. with visible
+/// banding for debanding to remove.
+#[allow(clippy::cast_precision_loss)]
+pub fn banded_ramp(width: usize, height: usize, steps: usize) -> Image {
+  Image::from_fn(width, height, 1, |x, _, _| {
+    let step = (x * steps / width) as f64 / (steps - 1) as f64;
+    0.1 + step * 0.05
+  })
+}
+
+/// PQ-encoded ramp reaching 10000 nits at the right edge, with each row
+/// tinted differently so that gamut mapping has something to do.
+#[allow(clippy::cast_precision_loss)]
+pub fn hdr_ramp(width: usize, height: usize) -> Image {
+  Image::from_fn(width, height, 4, |x, y, c| {
+    let signal = x as f64 / (width - 1) as f64;
+    let tint = y as f64 / (height - 1) as f64;
+    match c {
+      0 => signal,
+      1 => signal * (1.0 - tint * 0.5),
+      2 => signal * (0.5 + tint * 0.5),
+      _ => 1.0,
+    }
+  })
+}
+
+/// RGB ramps along each axis, opaque.
+#[allow(clippy::cast_precision_loss)]
+pub fn rgb_gradient(width: usize, height: usize) -> Image {
+  Image::from_fn(width, height, 4, |x, y, c| {
+    let (x, y) = (
+      x as f64 / (width - 1) as f64,
+      y as f64 / (height - 1) as f64,
+    );
+    match c {
+      0 => x,
+      1 => y,
+      2 => (x + 1.0 - y) / 2.0,
+      _ => 1.0,
+    }
+  })
+}
+
+/// Peak signal-to-noise ratio in dB, over all samples. Infinite for
+/// identical images.
+#[allow(clippy::cast_precision_loss)]
+pub fn psnr(a: &Image, b: &Image) -> f64 {
+  assert_eq!(
+    (a.width, a.height, a.channels),
+    (b.width, b.height, b.channels)
+  );
+
+  let mse = a
+    .data
+    .iter()
+    .zip(&b.data)
+    .map(|(&a, &b)| (f64::from(a) - f64::from(b)).powi(2))
+    .sum::<f64>()
+    / a.data.len() as f64;
+
+  10.0 * (65535.0f64.powi(2) / mse).log10()
+}
+
+/// Mean structural similarity over 8x8 windows of each channel, from -1.0
+/// to 1.0 for identical images.
+#[allow(clippy::cast_precision_loss)]
+pub fn ssim(a: &Image, b: &Image) -> f64 {
+  const WINDOW: usize = 8;
+  const C1: f64 = 0.01 * 0.01;
+  const C2: f64 = 0.03 * 0.03;
+
+  assert_eq!(
+    (a.width, a.height, a.channels),
+    (b.width, b.height, b.channels)
+  );
+
+  let (mut total, mut windows) = (0.0, 0);
+  for c in 0..a.channels {
+    for wy in (0..=a.height.saturating_sub(WINDOW)).step_by(WINDOW / 2) {
+      for wx in (0..=a.width.saturating_sub(WINDOW)).step_by(WINDOW / 2) {
+        let samples = (wy..(wy + WINDOW).min(a.height))
+          .flat_map(|y| (wx..(wx + WINDOW).min(a.width)).map(move |x| (x, y)))
+          .map(|(x, y)| (a.sample(x, y, c), b.sample(x, y, c)));
+
+        let (mut n, mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) =
+          (0.0, 0.0, 0.0, 0.0, 0.0, 0.0);
+        for (a, b) in samples {
+          n += 1.0;
+          sum_a += a;
+          sum_b += b;
+          sum_aa += a * a;
+          sum_bb += b * b;
+          sum_ab += a * b;
+        }
+
+        let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+        let var_a = sum_aa / n - mean_a * mean_a;
+        let var_b = sum_bb / n - mean_b * mean_b;
+        let covar = sum_ab / n - mean_a * mean_b;
+
+        total += ((2.0 * mean_a * mean_b + C1) * (2.0 * covar + C2))
+          / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+        windows += 1;
+      }
+    }
+  }
+
+  total / f64::from(windows)
+}
+
+/// How far a result may stray from its reference.
+#[derive(Clone, Copy)]
+pub struct Tolerance {
+  /// Minimum PSNR, in dB.
+  pub psnr: f64,
+
+  /// Minimum SSIM.
+  pub ssim: f64,
+}
+
+impl Default for Tolerance {
+  /// Loose enough for differences between GPUs and drivers in rounding and
+  /// in the precision of intermediate textures.
+  fn default() -> Self {
+    Self {
+      psnr: 45.0,
+      ssim: 0.995,
+    }
+  }
+}
+
+fn reference_path(name: &str) -> PathBuf {
+  PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+    .join("tests/golden")
+    .join(format!("{name}.pam"))
+}
+
+/// Compares `image` against the reference called `name`, or records it as
+/// the reference with `PLACEBO_BLESS=1`.
+///
+/// # Panics
+///
+/// Will panic if the reference is missing, or if the image doesn't match it
+/// within `tolerance`.
+pub fn check(name: &str, image: &Image, tolerance: Tolerance) {
+  let path = reference_path(name);
+  let bless = env::var_os("PLACEBO_BLESS").is_some_and(|v| v != "0");
+
+  let reference = if bless {
+    None
+  } else {
+    fs::read(&path).ok().map(|pam| {
+      Image::from_pam(&pam).unwrap_or_else(|| panic!("{} is not a valid PAM.", path.display()))
+    })
+  };
+
+  let Some(reference) = reference else {
+    assert!(
+      bless,
+      "Missing reference {}. Run with PLACEBO_BLESS=1 to record it.",
+      path.display()
+    );
+
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(&path, image.to_pam()).unwrap();
+    eprintln!("Recorded reference {}.", path.display());
+    return;
+  };
+
+  assert_eq!(
+    (image.width, image.height, image.channels),
+    (reference.width, reference.height, reference.channels),
+    "{name}: size differs from the reference."
+  );
+
+  let (psnr, ssim) = (psnr(image, &reference), ssim(image, &reference));
+  assert!(
+    psnr >= tolerance.psnr && ssim >= tolerance.ssim,
+    "{name}: PSNR {psnr:.2} dB (min {:.2}), SSIM {ssim:.5} (min {:.5}).",
+    tolerance.psnr,
+    tolerance.ssim
+  );
+}
+
+/// A Vulkan device to run tests on, along with the objects most tests need.
+/// Textures created through it are destroyed along with it.
+pub struct Context {
+  pub renderer: Renderer,
+  pub dispatch: Dispatch,
+  textures: RefCell<Vec<Tex>>,
+  pub vulkan: Vulkan,
+  pub log: Log,
+}
+
+impl Context {
+  /// Creates a context, or returns `None` if there is no Vulkan device, in
+  /// which case the calling test should return early.
+  pub fn new() -> Option<Self> {
+    let log = Log::default();
+    let Some(vulkan) = Vulkan::try_new(
+      &log,
+      &pl_vulkan_params {
+        allow_software: true,
+        queue_count: 1,
+        ..pl_vulkan_params::default()
+      },
+    ) else {
+      eprintln!("No Vulkan device available, skipping.");
+      return None;
+    };
+
+    Some(Self {
+      renderer: Renderer::new(&log, &vulkan.gpu()),
+      dispatch: Dispatch::new(&log, &vulkan.gpu()),
+      textures: RefCell::new(Vec::new()),
+      vulkan,
+      log,
+    })
+  }
+
+  pub fn gpu(&self) -> Gpu {
+    self.vulkan.as_gpu()
+  }
+
+  /// A blank `width`x`height` texture with `channels` 16-bit components,
+  /// which can be sampled, rendered to and transferred both ways.
+  #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+  pub fn texture(&self, width: usize, height: usize, channels: usize) -> Tex {
+    let format = self
+      .vulkan
+      .find_fmt(
+        pl_fmt_type::PL_FMT_UNORM,
+        channels as i32,
+        16,
+        16,
+        pl_fmt_caps::PL_FMT_CAP_SAMPLEABLE
+          | pl_fmt_caps::PL_FMT_CAP_LINEAR
+          | pl_fmt_caps::PL_FMT_CAP_RENDERABLE
+          | pl_fmt_caps::PL_FMT_CAP_HOST_READABLE,
+      )
+      .unwrap_or_else(|| panic!("No 16-bit format with {channels} components."));
+
+    let tex = self.vulkan.tex_create(&pl_tex_params {
+      w: width as i32,
+      h: height as i32,
+      format: format.as_ptr(),
+      sampleable: true,
+      renderable: true,
+      host_writable: true,
+      host_readable: true,
+      ..pl_tex_params::default()
+    });
+
+    self.textures.borrow_mut().push(tex.clone());
+    tex
+  }
+
+  /// A texture holding `image`.
+  pub fn upload(&self, image: &Image) -> Tex {
+    let tex = self.texture(image.width, image.height, image.channels);
+    upload_tex(self.gpu(), &tex, &image.bytes(), 0).unwrap();
+    tex
+  }
+
+  /// The contents of `tex`, which must have 16-bit components.
+  #[allow(clippy::cast_sign_loss)]
+  pub fn download(&self, tex: &Tex) -> Image {
+    let (width, height) = (tex.width() as usize, tex.height() as usize);
+    let channels = tex.num_components() as usize;
+
+    let mut bytes = vec![0; width * height * channels * 2];
+    download_tex(self.gpu(), tex, &mut bytes, 0).unwrap();
+
+    Image {
+      width,
+      height,
+      channels,
+      data: bytes
+        .chunks_exact(2)
+        .map(|s| u16::from_ne_bytes([s[0], s[1]]))
+        .collect(),
+    }
+  }
+
+  /// A blank shader, set up the way the filters set up theirs.
+  pub fn begin(&self) -> Shader {
+    let mut shader = self.dispatch.begin();
+    shader.reset(&pl_shader_params {
+      gpu: self.vulkan.gpu(),
+      ..pl_shader_params::default()
+    });
+    shader
+  }
+
+  /// Runs `shader`, rendering into `target`.
+  pub fn finish(&self, shader: &Shader, target: &Tex) {
+    self
+      .dispatch
+      .finish(&pl_dispatch_params {
+        target: target.as_ptr(),
+        shader: &mut shader.as_ptr(),
+        ..pl_dispatch_params::default()
+      })
+      .unwrap();
+  }
+}
+
+impl Drop for Context {
+  fn drop(&mut self) {
+    for tex in self.textures.borrow().iter() {
+      self.vulkan.tex_destroy(tex);
+    }
+  }
+}
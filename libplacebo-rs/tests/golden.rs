@@ -0,0 +1,435 @@
+//! Golden-image tests for the GPU operations behind each vs-placebo filter,
+//! run with the filter's default parameters. See `common` for how references
+//! are recorded and compared.
+
+#![cfg(feature = "vulkan")]
+
+mod common;
+
+use common::{
+  banded_ramp, check, gradient, hdr_ramp, psnr, rgb_gradient, ssim, Context, Image, Tolerance,
+};
+use libplacebo_rs::{
+  filters::find_filter_config,
+  gpu::Tex,
+  shaders::{custom::CustomShader, lut::CustomLut, sampling::SampleSource},
+  shaders_root::ShaderObject,
+  utils::upload::{ColorFamily, PlanarFormat},
+};
+use libplacebo_sys::{
+  pl_color_levels, pl_color_map_args, pl_color_repr, pl_color_space_bt709, pl_color_space_hdr10,
+  pl_color_system, pl_deband_params, pl_deinterlace_default_params, pl_deinterlace_source,
+  pl_field, pl_filter_usage, pl_fmt_type, pl_frame, pl_frame_mix, pl_plane,
+  pl_render_default_params, pl_render_params, pl_shader_sig, pl_tex_address_mode,
+  pl_tex_sample_mode,
+};
+
+/// An RGBA frame held in a single texture.
+fn rgba_frame(tex: &Tex) -> pl_frame {
+  let mut frame = pl_frame {
+    num_planes: 1,
+    color: unsafe { pl_color_space_bt709 },
+    ..pl_frame::default()
+  };
+  frame.planes[0] = pl_plane {
+    texture: tex.as_ptr(),
+    components: 4,
+    component_mapping: [0, 1, 2, 3],
+    ..pl_plane::default()
+  };
+  frame
+}
+
+#[test]
+fn deband() {
+  let Some(ctx) = Context::new() else { return };
+
+  let input = banded_ramp(256, 64, 8);
+  let src = ctx.upload(&input);
+  let dst = ctx.texture(256, 64, 1);
+
+  let mut shader = ctx.begin();
+  shader.deband(
+    &SampleSource::new(&src),
+    &pl_deband_params {
+      iterations: 1,
+      threshold: 3.0,
+      radius: 16.0,
+      // Grain is random, so it would only make the comparison noisier.
+      grain: 0.0,
+      ..pl_deband_params::default()
+    },
+  );
+  ctx.finish(&shader, &dst);
+
+  let output = ctx.download(&dst);
+  assert!(output.distinct_values() > input.distinct_values());
+  // Smoothing the bands must not change the overall brightness.
+  assert!((output.mean() - input.mean()).abs() < 0.005);
+  check("deband", &output, Tolerance::default());
+}
+
+#[test]
+fn color_convert() {
+  let Some(ctx) = Context::new() else { return };
+
+  let src = ctx.upload(&hdr_ramp(128, 32));
+  let dst = ctx.texture(128, 32, 4);
+
+  let (hdr10, bt709) = unsafe { (pl_color_space_hdr10, pl_color_space_bt709) };
+  let mut repr = pl_color_repr::default();
+
+  let mut shader = ctx.begin();
+  shader.sample_direct(&SampleSource::new(&src)).unwrap();
+  shader.decode_color(&mut repr, None);
+  shader.linearize(&hdr10);
+  shader.color_map_ex(
+    None,
+    &pl_color_map_args {
+      src: hdr10,
+      dst: bt709,
+      prelinearized: true,
+      ..pl_color_map_args::default()
+    },
+  );
+  shader.encode_color(&pl_color_repr::default());
+  ctx.finish(&shader, &dst);
+
+  // Black stays black, and tone mapping keeps every row's ramp increasing.
+  let output = ctx.download(&dst);
+  for y in 0..output.height {
+    for c in 0..3 {
+      assert!(
+        output.sample(0, y, c) < 0.01,
+        "row {y}, channel {c}: black is lifted"
+      );
+    }
+    let luma = |x| (0..3).map(|c| output.sample(x, y, c)).sum::<f64>();
+    assert!(
+      (1..output.width).all(|x| luma(x) >= luma(x - 1) - 1e-3),
+      "row {y} is not monotonic"
+    );
+  }
+  check("color_convert", &output, Tolerance::default());
+}
+
+#[test]
+fn convert() {
+  let Some(mut ctx) = Context::new() else {
+    return;
+  };
+
+  let format = PlanarFormat {
+    color_family: ColorFamily::Yuv,
+    sample_type: pl_fmt_type::PL_FMT_UNORM,
+    bits_per_sample: 16,
+    bytes_per_sample: 2,
+    sub_sampling_w: 1,
+    sub_sampling_h: 1,
+  };
+
+  let chroma = (|x: f64| 0.25 + x / 62.0, |y: f64| 0.75 - y / 30.0);
+  let luma_image = gradient(64, 32);
+  let luma = ctx.upload(&luma_image);
+  #[allow(clippy::cast_precision_loss)]
+  let cb = ctx.upload(&Image::from_fn(32, 16, 1, |x, _, _| chroma.0(x as f64)));
+  #[allow(clippy::cast_precision_loss)]
+  let cr = ctx.upload(&Image::from_fn(32, 16, 1, |_, y, _| chroma.1(y as f64)));
+  let src = format
+    .frame(
+      &[luma.as_ptr(), cb.as_ptr(), cr.as_ptr()],
+      &pl_color_repr {
+        sys: pl_color_system::PL_COLOR_SYSTEM_BT_709,
+        levels: pl_color_levels::PL_COLOR_LEVELS_LIMITED,
+        ..pl_color_repr::default()
+      },
+      &unsafe { pl_color_space_bt709 },
+    )
+    .unwrap();
+
+  let rgb = PlanarFormat {
+    color_family: ColorFamily::Rgb,
+    sub_sampling_w: 0,
+    sub_sampling_h: 0,
+    ..format
+  };
+  let planes: Vec<_> = (0..3).map(|_| ctx.texture(64, 32, 1)).collect();
+  let dst = rgb
+    .frame(
+      &planes.iter().map(|tex| tex.as_ptr()).collect::<Vec<_>>(),
+      &pl_color_repr::default(),
+      &unsafe { pl_color_space_bt709 },
+    )
+    .unwrap();
+
+  ctx
+    .renderer
+    .render_image(
+      &src,
+      &dst,
+      &pl_render_params {
+        skip_caching_single_frame: true,
+        ..unsafe { pl_render_default_params }
+      },
+    )
+    .unwrap();
+
+  let output = Image::interleave(
+    &planes
+      .iter()
+      .map(|tex| ctx.download(tex))
+      .collect::<Vec<_>>(),
+  );
+
+  // BT.709 limited range to full range RGB, with the chroma ramps evaluated
+  // at each luma sample's position. The planes have no chroma location set,
+  // so chroma samples are centered between luma samples.
+  #[allow(clippy::cast_precision_loss)]
+  let expected = Image::from_fn(64, 32, 3, |x, y, c| {
+    let y_ = (luma_image.sample(x, y, 0) - 16.0 / 255.0) * 255.0 / 219.0;
+    let cb = (chroma.0((x as f64 - 0.5) / 2.0) - 128.0 / 255.0) * 255.0 / 224.0;
+    let cr = (chroma.1((y as f64 - 0.5) / 2.0) - 128.0 / 255.0) * 255.0 / 224.0;
+    match c {
+      0 => y_ + 1.5748 * cr,
+      1 => y_ - 0.1873 * cb - 0.4681 * cr,
+      _ => y_ + 1.8556 * cb,
+    }
+  });
+  assert!(psnr(&output, &expected) > 35.0);
+  check("convert", &output, Tolerance::default());
+}
+
+#[test]
+fn lut() {
+  let Some(ctx) = Context::new() else { return };
+
+  // Inverts every channel.
+  let mut cube = String::from("LUT_3D_SIZE 2\n");
+  for b in 0..2 {
+    for g in 0..2 {
+      for r in 0..2 {
+        cube += &format!("{} {} {}\n", 1 - r, 1 - g, 1 - b);
+      }
+    }
+  }
+  let lut = CustomLut::parse_cube(Some(&ctx.log), &cube).unwrap();
+  let lut_state = ShaderObject::new();
+
+  let input = rgb_gradient(64, 64);
+  let src = ctx.upload(&input);
+  let dst = ctx.texture(64, 64, 4);
+
+  let mut repr = pl_color_repr::default();
+  let mut shader = ctx.begin();
+  shader.sample_direct(&SampleSource::new(&src)).unwrap();
+  shader.decode_color(&mut repr, None);
+  shader.custom_lut(&lut, &lut_state);
+  shader.encode_color(&pl_color_repr::default());
+  ctx.finish(&shader, &dst);
+
+  let output = ctx.download(&dst);
+  let expected = Image::from_fn(64, 64, 4, |x, y, c| {
+    let sample = input.sample(x, y, c);
+    if c == 3 {
+      sample
+    } else {
+      1.0 - sample
+    }
+  });
+  assert!(psnr(&output, &expected) > 40.0);
+  check("lut", &output, Tolerance::default());
+}
+
+#[test]
+fn deinterlace() {
+  let Some(ctx) = Context::new() else { return };
+
+  // A ramp moving right by a pixel per field, so that the fields of each
+  // frame disagree.
+  #[allow(clippy::cast_precision_loss)]
+  let field_ramp = |frame: usize| {
+    Image::from_fn(64, 32, 1, move |x, y, _| {
+      let shift = frame * 2 + y % 2;
+      ((x + shift) % 64) as f64 / 63.0
+    })
+  };
+  let prev = ctx.upload(&field_ramp(0));
+  let cur_image = field_ramp(1);
+  let cur = ctx.upload(&cur_image);
+  let next = ctx.upload(&field_ramp(2));
+  let dst = ctx.texture(64, 32, 1);
+
+  let mut shader = ctx.begin();
+  shader.deinterlace(
+    &pl_deinterlace_source {
+      prev: prev.as_ptr(),
+      cur: cur.as_ptr(),
+      next: next.as_ptr(),
+      field: pl_field::PL_FIELD_EVEN,
+      first_field: pl_field::PL_FIELD_EVEN,
+      ..pl_deinterlace_source::default()
+    },
+    &unsafe { pl_deinterlace_default_params },
+  );
+  ctx.finish(&shader, &dst);
+
+  // Lines of the current field are kept, only the others are interpolated.
+  let output = ctx.download(&dst);
+  for y in (0..output.height).step_by(2) {
+    for x in 0..output.width {
+      assert!(
+        (output.sample(x, y, 0) - cur_image.sample(x, y, 0)).abs() < 1e-4,
+        "({x}, {y}) of the current field changed"
+      );
+    }
+  }
+  check("deinterlace", &output, Tolerance::default());
+}
+
+#[test]
+fn interpolate() {
+  let Some(mut ctx) = Context::new() else {
+    return;
+  };
+
+  let first = rgb_gradient(64, 64);
+  let second = Image::from_fn(64, 64, 4, |x, y, c| {
+    if c == 3 {
+      1.0
+    } else {
+      1.0 - first.sample(x, y, c)
+    }
+  });
+  let textures = [ctx.upload(&first), ctx.upload(&second)];
+  let dst = ctx.texture(64, 64, 4);
+
+  // A symmetric kernel weighs frames equidistant from the vsync equally.
+  let frames = textures.each_ref().map(rgba_frame);
+  let frame_ptrs = frames.each_ref().map(std::ptr::from_ref);
+  let signatures = [1, 2];
+  let timestamps = [-0.5, 0.5];
+
+  ctx
+    .renderer
+    .render_image_mix(
+      &pl_frame_mix {
+        num_frames: 2,
+        frames: frame_ptrs.as_ptr(),
+        signatures: signatures.as_ptr(),
+        timestamps: timestamps.as_ptr(),
+        vsync_duration: 1.0,
+      },
+      &rgba_frame(&dst),
+      &pl_render_params {
+        frame_mixer: find_filter_config(c"mitchell_clamp", pl_filter_usage::PL_FILTER_FRAME_MIXING)
+          .unwrap(),
+        ..unsafe { pl_render_default_params }
+      },
+    )
+    .unwrap();
+
+  // Whatever space the frames are blended in, each sample lies between the
+  // two frames' and well away from both.
+  let output = ctx.download(&dst);
+  let (mut to_first, mut to_second) = (0.0, 0.0);
+  for y in 0..64 {
+    for x in 0..64 {
+      for c in 0..3 {
+        let (a, b, out) = (
+          first.sample(x, y, c),
+          second.sample(x, y, c),
+          output.sample(x, y, c),
+        );
+        assert!(
+          out >= a.min(b) - 0.01 && out <= a.max(b) + 0.01,
+          "({x}, {y}, {c}): {out} is not between {a} and {b}"
+        );
+        to_first += (out - a).abs();
+        to_second += (out - b).abs();
+      }
+    }
+  }
+  let samples = f64::from(64 * 64 * 3);
+  assert!(to_first / samples > 0.1 && to_second / samples > 0.1);
+  check("interpolate", &output, Tolerance::default());
+}
+
+#[test]
+fn expr() {
+  let Some(ctx) = Context::new() else { return };
+
+  let x = gradient(64, 32);
+  let y = banded_ramp(64, 32, 4);
+  let (src_x, src_y) = (ctx.upload(&x), ctx.upload(&y));
+  let dst = ctx.texture(64, 32, 1);
+
+  let mut shader = ctx.begin();
+  CustomShader::new(
+    "expr",
+    "ivec2 pos = ivec2(gl_FragCoord.xy);\n\
+     float x = texelFetch(src_x, pos, 0).r;\n\
+     float y = texelFetch(src_y, pos, 0).r;\n\
+     color = vec4((x + y) * 0.5, 0.0, 0.0, 1.0);",
+    pl_shader_sig::PL_SHADER_SIG_NONE,
+    pl_shader_sig::PL_SHADER_SIG_COLOR,
+  )
+  .unwrap()
+  .output_size(64, 32)
+  .texture(
+    "src_x",
+    &src_x,
+    pl_tex_sample_mode::PL_TEX_SAMPLE_NEAREST,
+    pl_tex_address_mode::PL_TEX_ADDRESS_CLAMP,
+  )
+  .unwrap()
+  .texture(
+    "src_y",
+    &src_y,
+    pl_tex_sample_mode::PL_TEX_SAMPLE_NEAREST,
+    pl_tex_address_mode::PL_TEX_ADDRESS_CLAMP,
+  )
+  .unwrap()
+  .apply(&mut shader)
+  .unwrap();
+  ctx.finish(&shader, &dst);
+
+  let output = ctx.download(&dst);
+  let expected = Image::from_fn(64, 32, 1, |px, py, _| {
+    (x.sample(px, py, 0) + y.sample(px, py, 0)) / 2.0
+  });
+  assert!(psnr(&output, &expected) > 80.0);
+  check("expr", &output, Tolerance::default());
+}
+
+#[test]
+fn info() {
+  let Some(ctx) = Context::new() else { return };
+
+  let gpu = ctx.gpu();
+  assert!(!gpu.formats().is_empty());
+  assert!(gpu.limits().max_tex_2d_dim >= 4096);
+}
+
+#[test]
+fn metrics() {
+  let image = rgb_gradient(32, 32);
+  assert!(psnr(&image, &image).is_infinite());
+  assert!((ssim(&image, &image) - 1.0).abs() < 1e-9);
+
+  let mut noisy = image.clone();
+  for (i, sample) in noisy.data.iter_mut().enumerate() {
+    *sample = sample.saturating_add(if i % 2 == 0 { 256 } else { 0 });
+  }
+  assert!((30.0..60.0).contains(&psnr(&image, &noisy)));
+  assert!(ssim(&image, &noisy) < 1.0);
+
+  let flat = Image::from_fn(32, 32, 4, |_, _, _| 0.5);
+  assert!(ssim(&image, &flat) < ssim(&image, &noisy));
+}
+
+#[test]
+fn pam_round_trip() {
+  let image = hdr_ramp(7, 5);
+  assert_eq!(Image::from_pam(&image.to_pam()), Some(image));
+  assert_eq!(Image::from_pam(b"P6\n1 1\n255\n\0\0\0"), None);
+}
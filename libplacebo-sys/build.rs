@@ -3,14 +3,26 @@ extern crate dunce;
 use dunce::canonicalize;
 use std::env;
 use std::ffi::OsStr;
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
+#[path = "build/vendored.rs"]
+mod vendored;
+
+/// Oldest libplacebo API version the bindings support, see `PL_API_VER` in
+/// `libplacebo/config.h`. Wrappers for anything newer are gated on the version
+/// found, which is passed on to dependents as `DEP_PLACEBO_API_VER`.
+const MIN_API_VER: u32 = 349;
+
 fn main() {
   let out_path = PathBuf::from(env::var("OUT_DIR").unwrap());
   let build_path = out_path.join("build");
+  let include_paths;
 
-  if cfg!(feature = "vendored") {
+  if cfg!(feature = "vendored") && !cfg!(feature = "meson") {
+    include_paths = vendored::build(&out_path);
+  } else if cfg!(feature = "vendored") {
     println!("cargo::rerun-if-changed=./subprojects");
 
     run_meson(".", &build_path, true);
@@ -64,8 +76,23 @@ fn main() {
     // println!("cargo:rustc-link-lib=kernel32");
     // println!("cargo:rustc-link-lib=dovi");
     // println!("cargo:rustc-link-lib=shaderc_combined");
+
+    let install_path = canonicalize(build_path.join("install")).unwrap();
+    include_paths = vec![canonicalize(install_path.join("include")).unwrap()];
+
+    print_link_flags();
+  } else {
+    // Links libplacebo dynamically, along with whatever pkg-config reports
+    // it depends on.
+    let libraries = system_deps::Config::new()
+      .probe()
+      .unwrap_or_else(|err| panic!("{err}"));
+    include_paths = libraries.all_include_paths().into_iter().cloned().collect();
   }
 
+  let api_ver = check_api_version(&include_paths);
+  println!("cargo::metadata=api_ver={api_ver}");
+
   // #[cfg(target_env = "gnu")]
   // println!("cargo::rustc-link-lib=dylib=stdc++");
   // println!("cargo::rustc-link-lib=static:-bundle=stdc++");
@@ -74,34 +101,111 @@ fn main() {
   // println!("cargo::rustc-link-lib=vulkan-1");
   // println!("cargo::rustc-link-lib=static=placebo");
 
+  // Pre-generated bindings are tagged with the API version of the headers
+  // they were generated from.
+  let pregenerated = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap())
+    .join("src/bindings")
+    .join(format!("api_{api_ver}.rs"));
+  println!(
+    "cargo::rustc-env=PLACEBO_PREGENERATED_BINDINGS={}",
+    pregenerated.display()
+  );
+
+  let bindings_path = out_path.join("bindings.rs");
+  if cfg!(feature = "bindgen") {
+    #[cfg(feature = "bindgen")]
+    generate_bindings(&include_paths, &bindings_path);
+
+    println!("cargo::rerun-if-env-changed=PLACEBO_UPDATE_BINDINGS");
+    if env::var_os("PLACEBO_UPDATE_BINDINGS").is_some() {
+      fs::create_dir_all(pregenerated.parent().unwrap()).unwrap();
+      fs::copy(&bindings_path, &pregenerated).expect("Couldn't update pre-generated bindings!");
+    }
+  } else {
+    println!("cargo::rerun-if-changed={}", pregenerated.display());
+    fs::copy(&pregenerated, &bindings_path).unwrap_or_else(|_| {
+      panic!(
+        "No pre-generated bindings for libplacebo API version {api_ver} at {}, enable the \
+         `bindgen` feature to generate them",
+        pregenerated.display()
+      )
+    });
+  }
+
+  // Functions whose symbols are suffixed with the API version, which C code
+  // calls through macros.
+  fs::write(
+    out_path.join("versioned.rs"),
+    format!(
+      "pub use self::pl_log_create_{api_ver} as pl_log_create;\n\
+       pub const MIN_API_VER: u32 = {MIN_API_VER};\n"
+    ),
+  )
+  .expect("Couldn't write versioned.rs!");
+}
+
+/// Generates bindings for the headers found in `include_paths`.
+#[cfg(feature = "bindgen")]
+fn generate_bindings(include_paths: &[PathBuf], out: &Path) {
   let mut builder = bindgen::Builder::default()
     .clang_arg("--verbose")
     .header("wrapper.h")
     .parse_callbacks(Box::new(bindgen::CargoCallbacks::new()))
     .allowlist_item("PL_.*")
     .allowlist_item("pl_.*")
-    .blocklist_item("pl_shader_obj_t")
+    // Capabilities are combined into masks, which a Rust enum can't hold.
+    .bitfield_enum("pl_fmt_caps")
+    .bitfield_enum("pl_hook_stage")
     .default_enum_style(bindgen::EnumVariation::Rust {
       non_exhaustive: false,
     })
-    .derive_default(true);
-
-  if cfg!(feature = "vendored") {
-    let install_path = canonicalize(build_path.join("install")).unwrap();
-    let libplacebo_headers_path = canonicalize(install_path.join("include")).unwrap();
+    .derive_default(true)
+    // Layout tests hard-code the sizes of the target the bindings were
+    // generated on, which would tie pre-generated bindings to it.
+    .layout_tests(false);
 
+  for path in include_paths {
     builder = builder
       .clang_arg("--include-directory")
-      .clang_arg(libplacebo_headers_path.to_str().unwrap());
+      .clang_arg(path.to_str().unwrap());
   }
 
   let bindings = builder.generate().expect("Unable to generate bindings");
 
   bindings
-    .write_to_file(out_path.join("bindings.rs"))
+    .write_to_file(out)
     .expect("Couldn't write bindings!");
+}
+
+/// Checks that the headers found in `include_paths` are new enough, since
+/// pkg-config versions are not always reliable (e.g. for git snapshots).
+/// Returns their API version.
+fn check_api_version(include_paths: &[PathBuf]) -> u32 {
+  let config = include_paths
+    .iter()
+    .map(|path| path.join("libplacebo/config.h"))
+    .find(|path| path.exists())
+    .unwrap_or_else(|| panic!("libplacebo/config.h not found in {include_paths:?}"));
+  println!("cargo::rerun-if-changed={}", config.display());
+
+  let api_ver = parse_api_version(&config)
+    .unwrap_or_else(|| panic!("PL_API_VER not found in {}", config.display()));
+  assert!(
+    api_ver >= MIN_API_VER,
+    "libplacebo API version {api_ver} is too old, at least {MIN_API_VER} is required"
+  );
+  api_ver
+}
 
-  print_link_flags();
+fn parse_api_version(config: &Path) -> Option<u32> {
+  fs::read_to_string(config).ok()?.lines().find_map(|line| {
+    line
+      .trim()
+      .strip_prefix("#define PL_API_VER")?
+      .trim()
+      .parse()
+      .ok()
+  })
 }
 
 fn run_meson<L, D>(lib: L, dir: D, static_linking: bool)
@@ -136,14 +240,25 @@ where
         // OsStr::new("x86_64-w64-mingw32.meson"),
         OsStr::new("-Dlibplacebo:demos=false"),
         OsStr::new("-Dlibplacebo:tests=false"),
-        OsStr::new("-Dlibplacebo:glslang=disabled"),
-        OsStr::new("-Dlibplacebo:shaderc=enabled"),
+        OsStr::new(&meson_feature("vulkan", cfg!(feature = "vulkan"))),
+        OsStr::new(&meson_feature("opengl", cfg!(feature = "opengl"))),
+        OsStr::new(&meson_feature("glslang", cfg!(feature = "glslang"))),
+        OsStr::new(&meson_feature("shaderc", cfg!(feature = "shaderc"))),
+        OsStr::new(&meson_feature("lcms", cfg!(feature = "lcms"))),
+        OsStr::new(&meson_feature("libdovi", cfg!(feature = "dovi"))),
+        OsStr::new(&meson_feature("xxhash", cfg!(feature = "xxhash"))),
       ],
     );
   }
   run_command(dir, "meson", &[OsStr::new("install")]);
 }
 
+/// `-D` option enabling or disabling a libplacebo feature.
+fn meson_feature(name: &str, enabled: bool) -> String {
+  let state = if enabled { "enabled" } else { "disabled" };
+  format!("-Dlibplacebo:{name}={state}")
+}
+
 fn run_command<D, N>(dir: D, name: N, args: &[&OsStr])
 where
   D: AsRef<OsStr>,
@@ -179,10 +294,26 @@ where
 fn print_link_flags() {
   // libplacebo deps.
   println!("cargo::rustc-link-lib=m");
-  println!("cargo::rustc-link-lib=shaderc_combined");
-  // println!("cargo::rustc-link-lib=static=glslang");
+  if cfg!(feature = "shaderc") {
+    println!("cargo::rustc-link-lib=shaderc_combined");
+  }
+  if cfg!(feature = "glslang") {
+    println!("cargo::rustc-link-lib=glslang");
+  }
   // println!("cargo::rustc-link-lib=static=spirv-cross-c");
-  println!("cargo::rustc-link-lib=vulkan-1");
+  let target_os = env::var("CARGO_CFG_TARGET_OS").unwrap();
+  if cfg!(feature = "vulkan") {
+    vendored::link_vulkan_loader();
+  }
+  if cfg!(feature = "lcms") {
+    println!("cargo::rustc-link-lib=lcms2");
+  }
+  if cfg!(feature = "dovi") {
+    println!("cargo::rustc-link-lib=dovi");
+  }
+  if cfg!(feature = "opengl") && target_os != "windows" {
+    println!("cargo::rustc-link-lib=dl");
+  }
 
   if let Ok(stdlib) = env::var("CXXSTDLIB") {
     if !stdlib.is_empty() {
@@ -201,7 +332,6 @@ fn print_link_flags() {
     }
   }
 
-  let target_os = env::var("CARGO_CFG_TARGET_OS").unwrap();
   if target_os == "windows" {
     println!("cargo::rustc-link-lib=dylib=dbghelp");
     println!("cargo::rustc-link-lib=dylib=winmm");
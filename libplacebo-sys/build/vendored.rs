@@ -0,0 +1,452 @@
+//! Builds the libplacebo submodule with the `cc` crate, without meson.
+//!
+//! libplacebo generates part of its sources with Python scripts: the GLSL
+//! preprocessor turns `#pragma GLSL` blocks into C, and the Vulkan and OpenGL
+//! backends generate helpers from the API registries. These run with the
+//! copies of Jinja and glad in libplacebo's `3rdparty` directory, so only a
+//! Python interpreter is needed. To build without Python, point
+//! `PLACEBO_GENERATED_DIR` at the outputs of a previous build (`generated` in
+//! `OUT_DIR`), which mirror the layout of `src`.
+
+use std::{
+  env, fs,
+  path::{Path, PathBuf},
+  process::Command,
+};
+
+/// Sources that are only compiled along with the optional dependency they
+/// wrap, relative to `src`.
+const OPTIONAL_SOURCES: &[(&str, &str)] = &[
+  ("glsl/spirv_shaderc.c", "shaderc"),
+  ("glsl/spirv_glslang.c", "glslang"),
+  ("glsl/glslang.cc", "glslang"),
+  ("glsl/glslang_resources.c", "glslang"),
+];
+
+/// Directories of `src` holding backends, which are only compiled when
+/// enabled. The dummy backend is part of the core and always available.
+const BACKEND_DIRS: &[(&str, &str)] = &[("vulkan", "vulkan"), ("opengl", "opengl")];
+
+/// Directories of `src` that are never compiled.
+const SKIPPED_DIRS: &[&str] = &["tests", "d3d11"];
+
+/// Features the build was configured with, see `Cargo.toml`.
+fn enabled(feature: &str) -> bool {
+  env::var_os(format!(
+    "CARGO_FEATURE_{}",
+    feature.to_uppercase().replace('-', "_")
+  ))
+  .is_some()
+}
+
+/// Builds libplacebo and returns the include paths of its headers.
+pub fn build(out_path: &Path) -> Vec<PathBuf> {
+  let root = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join("subprojects/libplacebo");
+  let src = root.join("src");
+  assert!(
+    src.join("include/libplacebo").exists(),
+    "libplacebo sources not found in {}, run `git submodule update --init --recursive`",
+    root.display()
+  );
+  println!("cargo::rerun-if-changed={}", src.display());
+  println!("cargo::rerun-if-env-changed=PLACEBO_GENERATED_DIR");
+
+  if enabled("vulkan") && !enabled("shaderc") && !enabled("glslang") {
+    println!("cargo::warning=The Vulkan backend needs `shaderc` or `glslang` to compile shaders.");
+  }
+
+  let gen = out_path.join("generated");
+  let include = out_path.join("include");
+  let pregenerated = env::var_os("PLACEBO_GENERATED_DIR").map(PathBuf::from);
+  let generator = Generator::new(&root, &gen, pregenerated);
+
+  let version = Version::parse(&root.join("meson.build"));
+  let defines = defines();
+  configure(&src, &include, &gen, &version, &defines);
+
+  let mut include_paths = vec![include.clone(), src.join("include")];
+  let mut dep_includes = Vec::new();
+
+  if enabled("vulkan") {
+    include_paths.push(root.join("3rdparty/Vulkan-Headers/include"));
+    generator.run(
+      "vulkan/utils_gen.c",
+      &[
+        src.join("vulkan/utils_gen.py").as_os_str(),
+        root
+          .join("3rdparty/Vulkan-Headers/registry/vk.xml")
+          .as_os_str(),
+        gen.join("vulkan/utils_gen.c").as_os_str(),
+      ],
+    );
+  }
+
+  if enabled("opengl") {
+    generator.glad();
+    include_paths.push(gen.join("include"));
+  }
+
+  for (feature, package) in [
+    ("shaderc", "shaderc"),
+    ("glslang", "glslang"),
+    ("lcms", "lcms2"),
+    ("dovi", "dovi"),
+    ("xxhash", "libxxhash"),
+  ] {
+    if enabled(feature) {
+      let library = pkg_config::probe_library(package)
+        .unwrap_or_else(|err| panic!("`{feature}` is enabled, but {package} was not found: {err}"));
+      dep_includes.extend(library.include_paths);
+    }
+  }
+
+  // Sources with `#pragma GLSL` blocks are compiled from their preprocessed
+  // copies, grouped by directory so that they still find the headers next to
+  // their originals.
+  let mut groups: Vec<(PathBuf, Vec<PathBuf>)> = Vec::new();
+  let mut plain = Vec::new();
+  let mut cpp = Vec::new();
+  for file in sources(&src) {
+    let relative = file.strip_prefix(&src).unwrap().to_path_buf();
+    if file.extension().is_some_and(|ext| ext == "cc") {
+      cpp.push(file);
+    } else if fs::read_to_string(&file).is_ok_and(|code| code.contains("#pragma GLSL")) {
+      let output = gen.join(&relative);
+      generator.run(
+        relative.to_str().unwrap(),
+        &[
+          root.join("tools/glsl_preproc/main.py").as_os_str(),
+          file.as_os_str(),
+          output.as_os_str(),
+        ],
+      );
+
+      let dir = file.parent().unwrap().to_path_buf();
+      match groups.iter_mut().find(|(d, _)| *d == dir) {
+        Some((_, files)) => files.push(output),
+        None => groups.push((dir, vec![output])),
+      }
+    } else {
+      plain.push(file);
+    }
+  }
+  if enabled("vulkan") {
+    groups.push((src.join("vulkan"), vec![gen.join("vulkan/utils_gen.c")]));
+  }
+
+  let base = |cpp: bool| {
+    let mut build = cc::Build::new();
+    build
+      .cpp(cpp)
+      .std(if cpp { "c++20" } else { "c11" })
+      .warnings(false)
+      .includes(&include_paths)
+      .include(&gen)
+      .include(&src)
+      .includes(&dep_includes)
+      .define("_ISOC99_SOURCE", None)
+      .define("_ISOC11_SOURCE", None)
+      .define("_GNU_SOURCE", None)
+      .define("_XOPEN_SOURCE", "700")
+      .define("PL_STATIC", None);
+
+    let fast_float = root.join("3rdparty/fast_float/include");
+    if fast_float.exists() {
+      build.include(fast_float);
+    }
+    build
+  };
+
+  let mut objects = base(false).files(&plain).compile_intermediates();
+  for (dir, files) in &groups {
+    objects.extend(
+      base(false)
+        .include(dir)
+        .files(files)
+        .compile_intermediates(),
+    );
+  }
+
+  // The C++ build links the C++ standard library and archives everything.
+  base(true).files(&cpp).objects(&objects).compile("placebo");
+
+  println!("cargo::rustc-link-lib=m");
+  if enabled("vulkan") {
+    link_vulkan_loader();
+  }
+
+  let target_os = env::var("CARGO_CFG_TARGET_OS").unwrap();
+  if target_os == "windows" {
+    println!("cargo::rustc-link-lib=dylib=dbghelp");
+    println!("cargo::rustc-link-lib=dylib=shlwapi");
+  } else {
+    println!("cargo::rustc-link-lib=pthread");
+    if enabled("opengl") {
+      println!("cargo::rustc-link-lib=dl");
+    }
+  }
+
+  include_paths
+}
+
+/// Links the Vulkan loader, which libplacebo gets `vkGetInstanceProcAddr`
+/// from.
+pub fn link_vulkan_loader() {
+  if env::var("CARGO_CFG_TARGET_OS").unwrap() == "windows" {
+    println!("cargo::rustc-link-lib=vulkan-1");
+  } else {
+    println!("cargo::rustc-link-lib=vulkan");
+  }
+}
+
+/// Every C and C++ source in `src` that this configuration compiles.
+fn sources(src: &Path) -> Vec<PathBuf> {
+  fn walk(dir: &Path, src: &Path, files: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+      let path = entry.unwrap().path();
+      let relative = path
+        .strip_prefix(src)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .replace('\\', "/");
+
+      if path.is_dir() {
+        let skipped = SKIPPED_DIRS.contains(&relative.as_str())
+          || BACKEND_DIRS
+            .iter()
+            .any(|&(dir, feature)| dir == relative && !enabled(feature));
+        if !skipped {
+          walk(&path, src, files);
+        }
+      } else if path
+        .extension()
+        .is_some_and(|ext| ext == "c" || ext == "cc")
+        && OPTIONAL_SOURCES
+          .iter()
+          .all(|&(file, feature)| file != relative || enabled(feature))
+        // Generated from the registry instead.
+        && relative != "vulkan/utils_gen.c"
+      {
+        files.push(path);
+      }
+    }
+  }
+
+  let mut files = Vec::new();
+  walk(src, src, &mut files);
+  files.sort();
+  files
+}
+
+/// Version of the submodule, from the `version` of its meson project: the
+/// major version, then the API version as the number of entries in the list
+/// of API changes, then the fix version.
+struct Version {
+  major: u32,
+  api: u32,
+  fix: u32,
+}
+
+impl Version {
+  fn parse(meson_build: &Path) -> Self {
+    let meson = fs::read_to_string(meson_build).unwrap();
+    let parse = || -> Option<Self> {
+      let (_, rest) = meson.split_once(".format(")?;
+      let mut numbers = rest
+        .lines()
+        .map(|line| line.split('#').next().unwrap().trim())
+        .filter(|line| !line.is_empty());
+
+      let major = numbers.next()?.trim_end_matches(',').parse().ok()?;
+      let mut api = 0;
+      for line in numbers.by_ref() {
+        if line.starts_with("}.keys()") {
+          break;
+        }
+        if line.starts_with('\'') && line.contains("':") {
+          api += 1;
+        }
+      }
+      let fix = numbers.next()?.trim_end_matches([',', ')']).parse().ok()?;
+
+      Some(Self { major, api, fix })
+    };
+
+    parse().unwrap_or_else(|| panic!("Couldn't parse the version in {}", meson_build.display()))
+  }
+}
+
+/// `PL_HAVE_*` definitions for the enabled features and the target.
+fn defines() -> Vec<&'static str> {
+  let mut defines = Vec::new();
+  for (feature, define) in [
+    ("vulkan", "PL_HAVE_VULKAN"),
+    ("vulkan", "PL_HAVE_VK_PROC_ADDR"),
+    ("opengl", "PL_HAVE_OPENGL"),
+    ("opengl", "PL_HAVE_GL_PROC_ADDR"),
+    ("shaderc", "PL_HAVE_SHADERC"),
+    ("shaderc", "PL_HAVE_SHADERC_VK_1_2"),
+    ("shaderc", "PL_HAVE_SHADERC_VK_1_3"),
+    ("glslang", "PL_HAVE_GLSLANG"),
+    ("lcms", "PL_HAVE_LCMS"),
+    ("dovi", "PL_HAVE_LIBDOVI"),
+    ("xxhash", "PL_HAVE_XXHASH"),
+  ] {
+    if enabled(feature) {
+      defines.push(define);
+    }
+  }
+
+  if env::var("CARGO_CFG_TARGET_OS").unwrap() == "windows" {
+    defines.extend(["PL_HAVE_WIN32", "PL_HAVE_DBGHELP"]);
+  } else {
+    defines.push("PL_HAVE_PTHREADS");
+  }
+  defines
+}
+
+/// Writes the headers meson would configure: the public `config.h`, and the
+/// internal `config_internal.h` and `version.h`.
+fn configure(src: &Path, include: &Path, gen: &Path, version: &Version, defines: &[&str]) {
+  let pretty = format!("v{}.{}.{}", version.major, version.api, version.fix);
+  let defs: String = defines
+    .iter()
+    .map(|define| format!("#define {define} 1\n"))
+    .collect();
+  let substitutions = [
+    ("majorver", version.major.to_string()),
+    ("apiver", version.api.to_string()),
+    ("fixver", version.fix.to_string()),
+    ("version", pretty.clone()),
+    ("buildver", pretty),
+    ("extra_defs", defs.clone()),
+  ];
+
+  fs::create_dir_all(include.join("libplacebo")).unwrap();
+  configure_file(
+    &src.join("include/libplacebo/config.h.in"),
+    &include.join("libplacebo/config.h"),
+    &substitutions,
+  );
+
+  fs::create_dir_all(gen).unwrap();
+  fs::write(gen.join("config_internal.h"), defs).unwrap();
+
+  let version_in = src.join("version.h.in");
+  if version_in.exists() {
+    configure_file(&version_in, &gen.join("version.h"), &substitutions);
+  }
+}
+
+/// Replaces `@name@` placeholders in `input` like meson's `configure_file()`.
+fn configure_file(input: &Path, output: &Path, substitutions: &[(&str, String)]) {
+  let mut contents = fs::read_to_string(input).unwrap();
+  for (name, value) in substitutions {
+    contents = contents.replace(&format!("@{name}@"), value);
+  }
+
+  if let Some(start) = contents.find('@') {
+    let placeholder = contents[start..].split_whitespace().next().unwrap();
+    assert!(
+      !placeholder[1..].contains('@'),
+      "Unknown placeholder {placeholder} in {}",
+      input.display()
+    );
+  }
+  fs::write(output, contents).unwrap();
+}
+
+/// Runs libplacebo's generator scripts, or copies their outputs from a
+/// directory of pre-generated sources.
+struct Generator {
+  python: PathBuf,
+  python_path: std::ffi::OsString,
+  gen: PathBuf,
+  pregenerated: Option<PathBuf>,
+}
+
+impl Generator {
+  fn new(root: &Path, gen: &Path, pregenerated: Option<PathBuf>) -> Self {
+    println!("cargo::rerun-if-env-changed=PYTHON");
+    let python = env::var_os("PYTHON").map_or_else(
+      || PathBuf::from(if cfg!(windows) { "python" } else { "python3" }),
+      PathBuf::from,
+    );
+
+    let third_party = root.join("3rdparty");
+    let python_path = env::join_paths([
+      third_party.join("jinja/src"),
+      third_party.join("markupsafe/src"),
+      third_party.join("glad"),
+    ])
+    .unwrap();
+
+    Self {
+      python,
+      python_path,
+      gen: gen.to_path_buf(),
+      pregenerated,
+    }
+  }
+
+  /// Produces `output`, relative to the generated sources, by running the
+  /// Python interpreter with `args`.
+  fn run(&self, output: &str, args: &[&std::ffi::OsStr]) {
+    let target = self.gen.join(output);
+    fs::create_dir_all(target.parent().unwrap()).unwrap();
+
+    if let Some(pregenerated) = &self.pregenerated {
+      let source = pregenerated.join(output);
+      fs::copy(&source, &target)
+        .unwrap_or_else(|err| panic!("Couldn't copy {}: {err}", source.display()));
+      return;
+    }
+
+    let status = Command::new(&self.python)
+      .args(args)
+      .env("PYTHONPATH", &self.python_path)
+      .status()
+      .unwrap_or_else(|err| {
+        panic!(
+          "Couldn't run {} to generate {output}: {err}. Install Python or set \
+           PLACEBO_GENERATED_DIR.",
+          self.python.display()
+        )
+      });
+    assert!(status.success(), "Generating {output} failed");
+  }
+
+  /// Generates the OpenGL loader headers, `glad/gl.h` and `glad/egl.h`.
+  fn glad(&self) {
+    let out_dir = self.gen.join("include");
+    if let Some(pregenerated) = &self.pregenerated {
+      for header in ["glad/gl.h", "glad/egl.h"] {
+        fs::create_dir_all(out_dir.join("glad")).unwrap();
+        fs::copy(
+          pregenerated.join("include").join(header),
+          out_dir.join(header),
+        )
+        .unwrap_or_else(|err| panic!("Couldn't copy {header}: {err}"));
+      }
+      return;
+    }
+
+    let out_path = format!("--out-path={}", out_dir.display());
+    self.run(
+      "include/glad/gl.h",
+      &[
+        "-m".as_ref(),
+        "glad".as_ref(),
+        out_path.as_ref(),
+        "--reproducible".as_ref(),
+        "--merge".as_ref(),
+        "--api=gl:compatibility,gles2,egl".as_ref(),
+        "c".as_ref(),
+        "--header-only".as_ref(),
+        "--mx".as_ref(),
+        "--loader".as_ref(),
+      ],
+    );
+  }
+}
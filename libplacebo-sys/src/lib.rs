@@ -3,26 +3,7 @@
 #![allow(non_upper_case_globals)]
 
 include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
-
-#[repr(u32)]
-#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
-pub enum pl_shader_obj_type {
-  PL_SHADER_OBJ_INVALID = 0,
-  PL_SHADER_OBJ_COLOR_MAP,
-  PL_SHADER_OBJ_SAMPLER,
-  PL_SHADER_OBJ_DITHER,
-  PL_SHADER_OBJ_LUT,
-  PL_SHADER_OBJ_AV1_GRAIN,
-  PL_SHADER_OBJ_FILM_GRAIN,
-  PL_SHADER_OBJ_RESHAPE,
-}
-
-#[repr(C)]
-#[derive(Debug, Copy, Clone)]
-pub struct pl_shader_obj_t {
-  pub gpu: pl_gpu,
-  pub r#type: pl_shader_obj_type,
-}
+include!(concat!(env!("OUT_DIR"), "/versioned.rs"));
 
 #[deny(clippy::all, clippy::pedantic, clippy::nursery, clippy::perf)]
 #[cfg(test)]
@@ -31,8 +12,25 @@ mod tests {
   use std::ptr::null_mut;
 
   #[test]
+  #[allow(clippy::assertions_on_constants)]
   fn can_read_version() {
-    assert_eq!(PL_MAJOR_VER, 7);
+    assert!(PL_API_VER >= MIN_API_VER);
+  }
+
+  /// Fails when the pre-generated bindings are stale. Run with
+  /// `PLACEBO_UPDATE_BINDINGS=1` to update them.
+  #[cfg(feature = "bindgen")]
+  #[test]
+  fn pregenerated_bindings_are_up_to_date() {
+    let path = env!("PLACEBO_PREGENERATED_BINDINGS");
+    let generated = include_str!(concat!(env!("OUT_DIR"), "/bindings.rs"));
+    let pregenerated = std::fs::read_to_string(path).unwrap_or_else(|_| {
+      panic!("{path} is missing. Run with PLACEBO_UPDATE_BINDINGS=1 to generate it.")
+    });
+    assert!(
+      generated == pregenerated,
+      "{path} differs from the generated bindings."
+    );
   }
 
   #[test]
@@ -42,7 +40,7 @@ mod tests {
       log_level: pl_log_level::PL_LOG_DEBUG,
       log_priv: null_mut(),
     };
-    let mut ptr = unsafe { pl_log_create_349(1, &params) };
+    let mut ptr = unsafe { pl_log_create(i32::try_from(PL_API_VER).unwrap(), &params) };
     assert!(!ptr.is_null());
     unsafe { pl_log_destroy(&mut ptr) };
     assert!(ptr.is_null());
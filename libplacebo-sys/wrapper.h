@@ -1,9 +1,23 @@
+#include <libplacebo/config.h>
+#include <libplacebo/shaders/colorspace.h>
+#include <libplacebo/shaders/custom.h>
+#include <libplacebo/shaders/deinterlacing.h>
+#include <libplacebo/shaders/icc.h>
+#include <libplacebo/shaders/lut.h>
 #include <libplacebo/shaders/sampling.h>
+#include <libplacebo/utils/frame_queue.h>
 #include <libplacebo/utils/upload.h>
 #include <libplacebo/colorspace.h>
 #include <libplacebo/dispatch.h>
+#include <libplacebo/dummy.h>
+#include <libplacebo/filters.h>
 #include <libplacebo/gpu.h>
 #include <libplacebo/log.h>
 #include <libplacebo/options.h>
 #include <libplacebo/renderer.h>
+#if defined(PL_HAVE_VULKAN) && PL_HAVE_VULKAN
 #include <libplacebo/vulkan.h>
+#endif
+#if defined(PL_HAVE_OPENGL) && PL_HAVE_OPENGL
+#include <libplacebo/opengl.h>
+#endif
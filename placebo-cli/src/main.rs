@@ -0,0 +1,182 @@
+//! Processes YUV4MPEG2 streams and still images with libplacebo, outside of
+//! VapourSynth.
+//!
+//! ```text
+//! ffmpeg -i in.mkv -f yuv4mpegpipe - \
+//!   | placebo-cli deband:iterations=2 dither:depth=8 \
+//!   | ffmpeg -i - out.mkv
+//!
+//! placebo-cli -i in.exr -o out.png tonemap:transfer=13
+//! ```
+
+mod ops;
+mod pipeline;
+mod still;
+mod y4m;
+
+use std::{
+  env,
+  fs::File,
+  io::{self, BufRead, BufReader, BufWriter, Write},
+};
+
+use libplacebo_rs::colorspace::{
+  levels_to_range, primaries_from_h273, system_from_matrix, transfer_from_h273,
+};
+use libplacebo_sys::{pl_color_levels, pl_color_repr, pl_color_space};
+use miette::{miette, IntoDiagnostic, Result};
+
+use crate::{
+  ops::Op,
+  pipeline::{FrameDesc, Pipeline},
+  y4m::{Reader, Writer},
+};
+
+const USAGE: &str = "\
+Usage: placebo-cli [options] <op>...
+
+Reads a YUV4MPEG2 stream, runs it through each operation in turn and writes
+the result as YUV4MPEG2. PNG, TIFF and EXR images are processed instead when
+the input is one; the output format then follows the output's extension.
+
+Options:
+  -i, --input <file>     Read from <file> instead of stdin
+  -o, --output <file>    Write to <file> instead of stdout
+  --matrix <n>           H.273 matrix coefficients of the input
+  --transfer <n>         H.273 transfer characteristics of the input
+  --primaries <n>        H.273 color primaries of the input
+  --range <full|limited> Range of the input, unless the stream has one
+
+Operations, given as name[:key=value,...]:
+  deband     iterations, threshold, radius, grain
+  dither     depth (8-16), method (blue, ordered, ordered_fixed, white)
+  resample   width, height, filter (e.g. ewa_lanczos)
+  tonemap    transfer, primaries (H.273), function (e.g. bt.2390)
+  shader     file, stage (native, rgb, linear, sigmoid, prekernel,
+             postkernel, scaled, preoutput, output)
+";
+
+#[derive(Default)]
+struct Args {
+  input: Option<String>,
+  output: Option<String>,
+  matrix: Option<i64>,
+  transfer: Option<i64>,
+  primaries: Option<i64>,
+  full_range: Option<bool>,
+  ops: Vec<Op>,
+}
+
+impl Args {
+  fn parse(mut args: impl Iterator<Item = String>) -> Result<Self> {
+    fn code(name: &str, value: Option<String>) -> Result<i64> {
+      let value = value.ok_or_else(|| miette!("{name} needs a value."))?;
+      value
+        .parse()
+        .map_err(|_| miette!("Invalid {name} {value:?}."))
+    }
+
+    let mut parsed = Self::default();
+
+    while let Some(arg) = args.next() {
+      match arg.as_str() {
+        "-h" | "--help" => {
+          print!("{USAGE}");
+          std::process::exit(0);
+        }
+        "-i" | "--input" => parsed.input = args.next(),
+        "-o" | "--output" => parsed.output = args.next(),
+        "--matrix" => parsed.matrix = Some(code("--matrix", args.next())?),
+        "--transfer" => parsed.transfer = Some(code("--transfer", args.next())?),
+        "--primaries" => parsed.primaries = Some(code("--primaries", args.next())?),
+        "--range" => {
+          parsed.full_range = match args.next().as_deref() {
+            Some("full") => Some(true),
+            Some("limited") => Some(false),
+            _ => return Err(miette!("--range must be full or limited.")),
+          }
+        }
+        _ if arg.starts_with('-') && arg != "-" => {
+          return Err(miette!("Unknown option {arg}.\n\n{USAGE}"))
+        }
+        _ => parsed.ops.push(Op::parse(&arg)?),
+      }
+    }
+
+    Ok(parsed)
+  }
+
+  /// Representation and color space of the input, as far as it is given.
+  /// Unset properties are left for libplacebo to guess.
+  fn input_color(&self, full_range: Option<bool>) -> Result<(pl_color_repr, pl_color_space)> {
+    let transfer = match self.transfer {
+      Some(code) => {
+        Some(transfer_from_h273(code).ok_or_else(|| miette!("Unsupported transfer {code}."))?)
+      }
+      None => None,
+    };
+
+    let mut repr = pl_color_repr::default();
+    if let Some(code) = self.matrix {
+      repr.sys =
+        system_from_matrix(code, transfer).ok_or_else(|| miette!("Unsupported matrix {code}."))?;
+    }
+    repr.levels = match full_range.or(self.full_range) {
+      Some(true) => pl_color_levels::PL_COLOR_LEVELS_FULL,
+      Some(false) => pl_color_levels::PL_COLOR_LEVELS_LIMITED,
+      None => pl_color_levels::PL_COLOR_LEVELS_UNKNOWN,
+    };
+
+    let mut color = pl_color_space::default();
+    if let Some(transfer) = transfer {
+      color.transfer = transfer;
+    }
+    if let Some(code) = self.primaries {
+      color.primaries =
+        primaries_from_h273(code).ok_or_else(|| miette!("Unsupported primaries {code}."))?;
+    }
+
+    Ok((repr, color))
+  }
+}
+
+fn main() -> Result<()> {
+  let args = Args::parse(env::args().skip(1))?;
+
+  if let Some(input) = args.input.as_deref().filter(|input| still::is_image(input)) {
+    let output = args
+      .output
+      .as_deref()
+      .ok_or_else(|| miette!("Images need an output file."))?;
+    let (_, color) = args.input_color(None)?;
+    return still::process(input, output, &color, &args.ops);
+  }
+
+  let input: Box<dyn BufRead> = match args.input.as_deref() {
+    None | Some("-") => Box::new(io::stdin().lock()),
+    Some(path) => Box::new(BufReader::new(File::open(path).into_diagnostic()?)),
+  };
+  let output: Box<dyn Write> = match args.output.as_deref() {
+    None | Some("-") => Box::new(io::stdout().lock()),
+    Some(path) => Box::new(File::create(path).into_diagnostic()?),
+  };
+
+  let mut reader = Reader::new(input)?;
+  let (repr, color) = args.input_color(reader.header.full_range)?;
+
+  let mut pipeline = Pipeline::new(FrameDesc::new(&reader.header, repr, color), args.ops)?;
+
+  // Only report the range if it was known going in.
+  let output_desc = pipeline.output();
+  let full_range = levels_to_range(output_desc.repr.levels).map(|range| range == 0);
+  let header = output_desc.header(&reader.header, full_range);
+  let mut writer = Writer::new(BufWriter::new(output), &header)?;
+
+  let (mut planes, mut out) = (Vec::new(), Vec::new());
+  while reader.read_frame(&mut planes)? {
+    pipeline.process(&planes, &mut out)?;
+    writer.write_frame(&out)?;
+  }
+
+  writer.flush()
+}
@@ -0,0 +1,361 @@
+//! The operations a stream can be run through. Each one is a separate
+//! rendering pass, from the previous pass's output, configured through the
+//! same parameters as the VapourSynth filters: H.273 code points for colors,
+//! libplacebo's names for scalers and tone mapping functions.
+
+use std::{ffi::CString, fs, ptr::from_ref};
+
+use libplacebo_rs::{
+  colorspace::{primaries_from_h273, transfer_from_h273},
+  filters::find_filter_config,
+  shaders::{
+    custom::CustomShader,
+    hooks::{Hook, HookOutput, HookParams, HookResult, Hooks},
+  },
+};
+use libplacebo_sys::{
+  pl_color_map_default_params, pl_color_map_params, pl_color_space, pl_deband_params,
+  pl_dither_method, pl_dither_params, pl_filter_config, pl_filter_usage, pl_find_tone_map_function,
+  pl_hook_stage, pl_render_default_params, pl_render_params, pl_shader_sig,
+};
+use miette::{miette, IntoDiagnostic, Result};
+
+use crate::pipeline::FrameDesc;
+
+pub enum Op {
+  /// Debands all planes, like `placebo.Deband`.
+  Deband(pl_deband_params),
+
+  /// Converts to `depth` bits per sample, dithering with `params`.
+  Dither {
+    depth: i32,
+    params: pl_dither_params,
+  },
+
+  /// Scales to `width`x`height`, with `filter` or libplacebo's default
+  /// scalers.
+  Resample {
+    width: Option<i32>,
+    height: Option<i32>,
+    filter: Option<&'static pl_filter_config>,
+  },
+
+  /// Maps to another transfer and/or set of primaries.
+  Tonemap {
+    transfer: Option<i64>,
+    primaries: Option<i64>,
+    params: pl_color_map_params,
+  },
+
+  /// Runs a GLSL snippet that modifies `vec4 color` at a render stage.
+  Shader(Hooks),
+}
+
+/// The `key=value` options of an operation, which are consumed as they are
+/// read so that unknown ones can be reported.
+struct Options<'a> {
+  op: &'a str,
+  values: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> Options<'a> {
+  fn parse(op: &'a str, options: &'a str) -> Result<Self> {
+    let values = options
+      .split(',')
+      .filter(|option| !option.is_empty())
+      .map(|option| {
+        option
+          .split_once('=')
+          .ok_or_else(|| miette!("{op}: expected key=value, got {option:?}."))
+      })
+      .collect::<Result<_>>()?;
+
+    Ok(Self { op, values })
+  }
+
+  fn take_str(&mut self, key: &str) -> Option<&'a str> {
+    let i = self.values.iter().position(|&(k, _)| k == key)?;
+    Some(self.values.remove(i).1)
+  }
+
+  fn take<T: std::str::FromStr>(&mut self, key: &str) -> Result<Option<T>> {
+    self
+      .take_str(key)
+      .map(|value| {
+        value
+          .parse()
+          .map_err(|_| miette!("{}: invalid {key} {value:?}.", self.op))
+      })
+      .transpose()
+  }
+
+  fn finish(self) -> Result<()> {
+    match self.values.first() {
+      Some((key, _)) => Err(miette!("{}: unknown option {key}.", self.op)),
+      None => Ok(()),
+    }
+  }
+}
+
+impl Op {
+  /// Parses an operation given as `name[:key=value,...]`, e.g.
+  /// `deband:iterations=2,threshold=4`.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` on unknown operations or options and invalid values.
+  pub fn parse(spec: &str) -> Result<Self> {
+    let (name, options) = spec.split_once(':').unwrap_or((spec, ""));
+    let mut options = Options::parse(name, options)?;
+
+    let op = match name {
+      "deband" => Self::Deband(pl_deband_params {
+        iterations: options.take("iterations")?.unwrap_or(1),
+        threshold: options.take("threshold")?.unwrap_or(3.0),
+        radius: options.take("radius")?.unwrap_or(16.0),
+        grain: options.take("grain")?.unwrap_or(4.0),
+        ..pl_deband_params::default()
+      }),
+      "dither" => {
+        let depth = options
+          .take("depth")?
+          .ok_or_else(|| miette!("dither: depth is required."))?;
+        if !(8..=16).contains(&depth) {
+          return Err(miette!("dither: depth must be 8-16."));
+        }
+
+        let method = match options.take_str("method").unwrap_or("blue") {
+          "blue" => pl_dither_method::PL_DITHER_BLUE_NOISE,
+          "ordered" => pl_dither_method::PL_DITHER_ORDERED_LUT,
+          "ordered_fixed" => pl_dither_method::PL_DITHER_ORDERED_FIXED,
+          "white" => pl_dither_method::PL_DITHER_WHITE_NOISE,
+          method => return Err(miette!("dither: unknown method {method}.")),
+        };
+
+        Self::Dither {
+          depth,
+          params: pl_dither_params {
+            method,
+            lut_size: 6,
+            ..pl_dither_params::default()
+          },
+        }
+      }
+      "resample" => {
+        let width: Option<i32> = options.take("width")?;
+        let height: Option<i32> = options.take("height")?;
+        if width.is_some_and(|w| w <= 0) || height.is_some_and(|h| h <= 0) {
+          return Err(miette!("resample: width and height must be positive."));
+        }
+
+        let filter = match options.take_str("filter") {
+          Some(name) => {
+            let name = CString::new(name).into_diagnostic()?;
+            Some(
+              find_filter_config(&name, pl_filter_usage::PL_FILTER_UPSCALING)
+                .ok_or_else(|| miette!("resample: unknown filter {}.", name.to_string_lossy()))?,
+            )
+          }
+          None => None,
+        };
+
+        Self::Resample {
+          width,
+          height,
+          filter,
+        }
+      }
+      "tonemap" => {
+        let transfer: Option<i64> = options.take("transfer")?;
+        let primaries: Option<i64> = options.take("primaries")?;
+        if let Some(code) = transfer.filter(|&code| transfer_from_h273(code).is_none()) {
+          return Err(miette!("tonemap: unsupported transfer {code}."));
+        }
+        if let Some(code) = primaries.filter(|&code| primaries_from_h273(code).is_none()) {
+          return Err(miette!("tonemap: unsupported primaries {code}."));
+        }
+
+        let mut params = unsafe { pl_color_map_default_params };
+        if let Some(name) = options.take_str("function") {
+          let name = CString::new(name).into_diagnostic()?;
+          params.tone_mapping_function = unsafe { pl_find_tone_map_function(name.as_ptr()) };
+          if params.tone_mapping_function.is_null() {
+            return Err(miette!(
+              "tonemap: unknown function {}.",
+              name.to_string_lossy()
+            ));
+          }
+        }
+
+        Self::Tonemap {
+          transfer,
+          primaries,
+          params,
+        }
+      }
+      "shader" => {
+        let path = options
+          .take_str("file")
+          .ok_or_else(|| miette!("shader: file is required."))?;
+        let body = fs::read_to_string(path).into_diagnostic()?;
+        let stage = hook_stage(options.take_str("stage").unwrap_or("rgb"))?;
+
+        // Catch syntax errors in the parameters now rather than per frame.
+        ShaderHook::custom_shader(&body)?;
+
+        let mut hooks = Hooks::new();
+        hooks.push(ShaderHook { body, stage });
+        Self::Shader(hooks)
+      }
+      _ => return Err(miette!("Unknown operation {name}.")),
+    };
+
+    options.finish()?;
+    Ok(op)
+  }
+
+  /// Description of the frame this operation renders from `src`.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if the output size can't hold the subsampled planes.
+  pub fn target(&self, src: &FrameDesc) -> Result<FrameDesc> {
+    let (width, height) = self.size(src.width, src.height);
+    let mut dst = FrameDesc {
+      width,
+      height,
+      color: self.color(&src.color),
+      ..*src
+    };
+
+    if let Some(depth) = self.depth() {
+      dst.format.bits_per_sample = depth;
+      dst.format.bytes_per_sample = if depth > 8 { 2 } else { 1 };
+    }
+
+    if width % (1 << dst.format.sub_sampling_w) != 0
+      || height % (1 << dst.format.sub_sampling_h) != 0
+    {
+      return Err(miette!(
+        "resample: {width}x{height} is not divisible by the chroma subsampling."
+      ));
+    }
+
+    Ok(dst)
+  }
+
+  /// Size of the frame this operation renders from a `width`x`height` one.
+  pub fn size(&self, width: i32, height: i32) -> (i32, i32) {
+    match self {
+      Self::Resample {
+        width: new_width,
+        height: new_height,
+        ..
+      } => (new_width.unwrap_or(width), new_height.unwrap_or(height)),
+      _ => (width, height),
+    }
+  }
+
+  /// Color space of the frame this operation renders from one in `color`.
+  pub fn color(&self, color: &pl_color_space) -> pl_color_space {
+    let mut color = *color;
+
+    if let Self::Tonemap {
+      transfer,
+      primaries,
+      ..
+    } = self
+    {
+      if let Some(transfer) = transfer.and_then(transfer_from_h273) {
+        color.transfer = transfer;
+      }
+      if let Some(primaries) = primaries.and_then(primaries_from_h273) {
+        color.primaries = primaries;
+      }
+      color.hdr = Default::default();
+    }
+
+    color
+  }
+
+  /// Bit depth this operation dithers to, if any.
+  pub const fn depth(&self) -> Option<i32> {
+    match self {
+      Self::Dither { depth, .. } => Some(*depth),
+      _ => None,
+    }
+  }
+
+  /// Render parameters for this operation. They point into `self`, which
+  /// must stay in place until rendering is done.
+  pub fn render_params(&self) -> pl_render_params {
+    let mut params = pl_render_params {
+      skip_caching_single_frame: true,
+      ..unsafe { pl_render_default_params }
+    };
+
+    match self {
+      Self::Deband(deband) => params.deband_params = deband,
+      Self::Dither { params: dither, .. } => params.dither_params = dither,
+      Self::Resample { filter, .. } => {
+        if let Some(filter) = filter {
+          params.upscaler = from_ref(*filter);
+          params.downscaler = from_ref(*filter);
+        }
+      }
+      Self::Tonemap {
+        params: color_map, ..
+      } => params.color_map_params = color_map,
+      Self::Shader(hooks) => hooks.attach(&mut params),
+    }
+
+    params
+  }
+}
+
+fn hook_stage(name: &str) -> Result<pl_hook_stage> {
+  Ok(match name {
+    "native" => pl_hook_stage::PL_HOOK_NATIVE,
+    "rgb" => pl_hook_stage::PL_HOOK_RGB,
+    "linear" => pl_hook_stage::PL_HOOK_LINEAR,
+    "sigmoid" => pl_hook_stage::PL_HOOK_SIGMOID,
+    "prekernel" => pl_hook_stage::PL_HOOK_PRE_KERNEL,
+    "postkernel" => pl_hook_stage::PL_HOOK_POST_KERNEL,
+    "scaled" => pl_hook_stage::PL_HOOK_SCALED,
+    "preoutput" => pl_hook_stage::PL_HOOK_PRE_OUTPUT,
+    "output" => pl_hook_stage::PL_HOOK_OUTPUT,
+    _ => return Err(miette!("shader: unknown stage {name}.")),
+  })
+}
+
+/// Runs a GLSL snippet on `vec4 color` at `stage`.
+struct ShaderHook {
+  body: String,
+  stage: pl_hook_stage,
+}
+
+impl ShaderHook {
+  fn custom_shader(body: &str) -> Result<CustomShader<'static>> {
+    CustomShader::new(
+      "placebo-cli shader",
+      body,
+      pl_shader_sig::PL_SHADER_SIG_COLOR,
+      pl_shader_sig::PL_SHADER_SIG_COLOR,
+    )
+  }
+}
+
+impl Hook for ShaderHook {
+  fn stages(&self) -> pl_hook_stage {
+    self.stage
+  }
+
+  fn hook(&mut self, params: &HookParams) -> Result<HookResult> {
+    let mut shader = params
+      .shader()
+      .ok_or_else(|| miette!("shader: stage has no shader to continue."))?;
+    Self::custom_shader(&self.body)?.apply(&mut shader)?;
+
+    Ok(HookOutput::Color(shader).into())
+  }
+}
@@ -0,0 +1,332 @@
+//! Runs frames through a chain of [`Op`]s on the GPU. Every operation
+//! renders into its own set of textures, which are created up front since a
+//! Y4M stream can't change its format midway.
+
+use libplacebo_rs::{
+  gpu::Tex,
+  log::Log,
+  renderer::Renderer,
+  utils::upload::{download_tex, upload_plane, PlanarFormat, PlaneData},
+  vulkan::Vulkan,
+};
+use libplacebo_sys::{
+  pl_chroma_location, pl_color_repr, pl_color_space, pl_fmt_caps, pl_frame,
+  pl_frame_set_chroma_location, pl_tex, pl_tex_params, pl_vk_inst_params, pl_vulkan_params,
+};
+use miette::{miette, Result};
+
+use crate::{ops::Op, y4m::Header};
+
+/// Everything about a frame that an operation may change.
+#[derive(Clone, Copy)]
+pub struct FrameDesc {
+  pub width: i32,
+  pub height: i32,
+  pub format: PlanarFormat,
+  pub repr: pl_color_repr,
+  pub color: pl_color_space,
+  pub chroma_location: pl_chroma_location,
+}
+
+impl FrameDesc {
+  /// Describes the frames of a stream with `header`, in `repr` and `color`.
+  pub fn new(header: &Header, repr: pl_color_repr, color: pl_color_space) -> Self {
+    Self {
+      width: header.width,
+      height: header.height,
+      format: header.format,
+      repr,
+      color,
+      chroma_location: header.chroma_location,
+    }
+  }
+
+  /// Header of a stream of these frames, keeping the other parameters of
+  /// `header`. ffmpeg's `XYSCSS` parameter is dropped, since it repeats the
+  /// input format, which may no longer be the format of these frames.
+  pub fn header(&self, header: &Header, full_range: Option<bool>) -> Header {
+    Header {
+      width: self.width,
+      height: self.height,
+      format: self.format,
+      chroma_location: self.chroma_location,
+      full_range,
+      extra: header
+        .extra
+        .iter()
+        .filter(|param| !param.starts_with("XYSCSS="))
+        .cloned()
+        .collect(),
+    }
+  }
+
+  fn plane_size(&self, plane: i32) -> (i32, i32) {
+    self.format.plane_size(plane, self.width, self.height)
+  }
+
+  /// `textures`, one per plane, as a frame of this description.
+  fn frame(&self, textures: &[Tex]) -> Result<pl_frame> {
+    let textures: Vec<pl_tex> = textures.iter().map(Tex::as_ptr).collect();
+    let mut frame = self.format.frame(&textures, &self.repr, &self.color)?;
+    unsafe { pl_frame_set_chroma_location(&mut frame, self.chroma_location) };
+    Ok(frame)
+  }
+}
+
+/// Creates the GPU everything runs on, set up like the VapourSynth filters'.
+///
+/// # Errors
+///
+/// Will return `Err` if there is no usable Vulkan device.
+pub fn create_vulkan(log: &Log) -> Result<Vulkan> {
+  Vulkan::try_new(
+    log,
+    &pl_vulkan_params {
+      async_compute: true,
+      async_transfer: true,
+      queue_count: 1,
+      instance_params: &pl_vk_inst_params {
+        debug: true,
+        ..pl_vk_inst_params::default()
+      },
+      ..pl_vulkan_params::default()
+    },
+  )
+  .ok_or_else(|| miette!("No Vulkan device available."))
+}
+
+pub struct Pipeline {
+  input: FrameDesc,
+  uploaded: Vec<Option<Tex>>,
+
+  /// The operations, their output descriptions and the textures they render
+  /// into.
+  passes: Vec<(Op, FrameDesc, Vec<Tex>)>,
+
+  renderer: Renderer,
+  vulkan: Vulkan,
+  _log: Log,
+}
+
+impl Pipeline {
+  /// Sets up a GPU to run `ops` on frames described by `input`.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if there is no Vulkan device, if there are no
+  /// operations, if an operation can't produce a valid frame, or if the GPU
+  /// has no renderable format for its planes.
+  pub fn new(input: FrameDesc, ops: Vec<Op>) -> Result<Self> {
+    let log = Log::default();
+    let vulkan = create_vulkan(&log)?;
+    Self::with_vulkan(log, vulkan, input, ops)
+  }
+
+  /// Like [`Pipeline::new`], but runs on `vulkan`, which must have been
+  /// created with `log`.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if there are no operations, if an operation can't
+  /// produce a valid frame, or if the GPU has no renderable format for its
+  /// planes.
+  pub fn with_vulkan(log: Log, vulkan: Vulkan, input: FrameDesc, ops: Vec<Op>) -> Result<Self> {
+    if ops.is_empty() {
+      return Err(miette!("No operations given."));
+    }
+
+    let mut pipeline = Self {
+      input,
+      uploaded: vec![None; input.format.num_planes() as usize],
+      passes: Vec::with_capacity(ops.len()),
+      renderer: Renderer::new(&log, &vulkan.gpu()),
+      vulkan,
+      _log: log,
+    };
+
+    let mut desc = input;
+    for op in ops {
+      desc = op.target(&desc)?;
+      let textures = pipeline.create_textures(&desc)?;
+      pipeline.passes.push((op, desc, textures));
+    }
+
+    Ok(pipeline)
+  }
+
+  /// Description of the frames the last operation produces.
+  pub fn output(&self) -> &FrameDesc {
+    &self.passes[self.passes.len() - 1].1
+  }
+
+  fn create_textures(&self, desc: &FrameDesc) -> Result<Vec<Tex>> {
+    (0..desc.format.num_planes())
+      .map(|plane| {
+        let (width, height) = desc.plane_size(plane);
+        let (data, _) = desc.format.plane_data(plane, width, height)?;
+        let format = self
+          .vulkan
+          .plane_find_fmt(&data)
+          .filter(|format| format.supports(pl_fmt_caps::PL_FMT_CAP_RENDERABLE))
+          .ok_or_else(|| {
+            miette!(
+              "No renderable texture format for {}-bit planes.",
+              desc.format.bits_per_sample
+            )
+          })?;
+
+        Ok(self.vulkan.tex_create(&pl_tex_params {
+          w: width,
+          h: height,
+          format: format.as_ptr(),
+          sampleable: true,
+          renderable: true,
+          host_readable: true,
+          debug_tag: c"placebo-cli".as_ptr(),
+          ..pl_tex_params::default()
+        }))
+      })
+      .collect()
+  }
+
+  /// Runs one frame, whose `planes` are tightly packed, through all
+  /// operations and stores the result in `out`, resizing it as needed.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if uploading, rendering or downloading fails.
+  #[allow(clippy::cast_sign_loss)]
+  pub fn process(&mut self, planes: &[Vec<u8>], out: &mut Vec<Vec<u8>>) -> Result<()> {
+    let gpu = self.vulkan.as_gpu();
+    let input = self.input;
+
+    for ((plane, pixels), tex) in (0..).zip(planes).zip(&mut self.uploaded) {
+      let (width, height) = input.plane_size(plane);
+      let (layout, _) = input.format.plane_data(plane, width, height)?;
+      upload_plane(gpu, tex, &PlaneData::with_layout(pixels, &layout, 0)?)?;
+    }
+
+    let uploaded: Vec<Tex> = self.uploaded.iter().flatten().cloned().collect();
+    let mut src = input.frame(&uploaded)?;
+
+    for (op, desc, textures) in &self.passes {
+      let dst = desc.frame(textures)?;
+      self
+        .renderer
+        .render_image(&src, &dst, &op.render_params())?;
+      src = dst;
+    }
+
+    let (_, desc, textures) = &self.passes[self.passes.len() - 1];
+    out.resize_with(textures.len(), Vec::new);
+    for ((plane, pixels), tex) in (0..).zip(out.iter_mut()).zip(textures) {
+      let (width, height) = desc.plane_size(plane);
+      pixels.resize(
+        width as usize * height as usize * desc.format.bytes_per_sample as usize,
+        0,
+      );
+      download_tex(gpu, tex, pixels, 0)?;
+    }
+
+    Ok(())
+  }
+}
+
+impl Drop for Pipeline {
+  fn drop(&mut self) {
+    for tex in self.uploaded.iter().flatten() {
+      self.vulkan.tex_destroy(tex);
+    }
+    for (_, _, textures) in &self.passes {
+      for tex in textures {
+        self.vulkan.tex_destroy(tex);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::y4m::{Reader, Writer};
+
+  #[test]
+  fn header_drops_input_format() {
+    let header = Header::parse("YUV4MPEG2 W4 H2 C420jpeg XYSCSS=420JPEG F25:1").unwrap();
+    let mut desc = FrameDesc::new(&header, pl_color_repr::default(), pl_color_space::default());
+    desc.format.bits_per_sample = 10;
+    desc.format.bytes_per_sample = 2;
+
+    assert_eq!(
+      desc.header(&header, None).to_line().unwrap(),
+      "YUV4MPEG2 W4 H2 C420p10 F25:1\n"
+    );
+  }
+
+  #[test]
+  fn y4m_stream_runs_through_pipeline() {
+    let log = Log::default();
+    let Some(vulkan) = Vulkan::try_new(
+      &log,
+      &pl_vulkan_params {
+        allow_software: true,
+        queue_count: 1,
+        ..pl_vulkan_params::default()
+      },
+    ) else {
+      eprintln!("No Vulkan device available, skipping.");
+      return;
+    };
+
+    // Two flat gray 4x2 4:4:4 frames.
+    let mut stream = b"YUV4MPEG2 W4 H2 C444 XYSCSS=444 F25:1\n".to_vec();
+    for luma in [60u8, 180] {
+      stream.extend_from_slice(b"FRAME\n");
+      stream.extend([luma; 8]);
+      stream.extend([128; 16]);
+    }
+
+    let mut reader = Reader::new(stream.as_slice()).unwrap();
+    let input = FrameDesc::new(
+      &reader.header,
+      pl_color_repr::default(),
+      pl_color_space::default(),
+    );
+    let ops = vec![
+      Op::parse("resample:width=8,height=4").unwrap(),
+      Op::parse("dither:depth=10").unwrap(),
+    ];
+    let mut pipeline = Pipeline::with_vulkan(log, vulkan, input, ops).unwrap();
+
+    let header = pipeline.output().header(&reader.header, None);
+    let mut out = Vec::new();
+    let mut writer = Writer::new(&mut out, &header).unwrap();
+
+    let (mut planes, mut processed) = (Vec::new(), Vec::new());
+    while reader.read_frame(&mut planes).unwrap() {
+      pipeline.process(&planes, &mut processed).unwrap();
+      writer.write_frame(&processed).unwrap();
+    }
+    drop(writer);
+
+    let mut reader = Reader::new(out.as_slice()).unwrap();
+    assert_eq!(
+      reader.header.to_line().unwrap(),
+      "YUV4MPEG2 W8 H4 C444p10 F25:1\n"
+    );
+
+    // Flat frames stay flat. YUV defaults to limited range, where going from
+    // 8 to 10 bits multiplies values by 4.
+    for luma in [60u16, 180] {
+      assert!(reader.read_frame(&mut planes).unwrap());
+      for (plane, expected) in planes.iter().zip([luma * 4, 512, 512]) {
+        assert_eq!(plane.len(), 8 * 4 * 2);
+        for sample in plane.chunks_exact(2) {
+          let sample = u16::from_le_bytes([sample[0], sample[1]]);
+          assert!(sample.abs_diff(expected) <= 2, "{sample} is not {expected}");
+        }
+      }
+    }
+    assert!(!reader.read_frame(&mut planes).unwrap());
+  }
+}
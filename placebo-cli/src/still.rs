@@ -0,0 +1,136 @@
+//! Runs still images (PNG, TIFF, EXR) through the same operations as
+//! streams. Images keep their pixel type throughout, so `dither` only
+//! lowers the depth within it.
+
+use std::{ffi::OsStr, path::Path};
+
+use image::{DynamicImage, ImageBuffer};
+use libplacebo_rs::{
+  gpu::{Gpu, Tex},
+  log::Log,
+  renderer::Renderer,
+  utils::image::{create_target, download_image, target_frame, upload_image, FramePixel},
+};
+use libplacebo_sys::{pl_color_primaries, pl_color_space, pl_color_transfer, pl_tex_destroy};
+use miette::{miette, IntoDiagnostic, Result};
+
+use crate::{ops::Op, pipeline::create_vulkan};
+
+/// Whether `path` is an image to process with [`process`] rather than a
+/// Y4M stream.
+pub fn is_image(path: &str) -> bool {
+  Path::new(path)
+    .extension()
+    .and_then(OsStr::to_str)
+    .is_some_and(|ext| {
+      matches!(
+        ext.to_ascii_lowercase().as_str(),
+        "png" | "tif" | "tiff" | "exr"
+      )
+    })
+}
+
+/// Destroys the textures it holds when dropped.
+struct Textures {
+  gpu: Gpu,
+  textures: Vec<Tex>,
+}
+
+impl Drop for Textures {
+  fn drop(&mut self) {
+    for tex in &self.textures {
+      unsafe { pl_tex_destroy(self.gpu.as_ptr(), &mut tex.as_ptr()) };
+    }
+  }
+}
+
+/// Reads `input`, runs it through `ops` and writes the result to `output`,
+/// in a format chosen by its extension. Unknown properties of `color` are
+/// assumed to be BT.709 primaries with the sRGB transfer, or linear light
+/// for floating point images.
+///
+/// # Errors
+///
+/// Will return `Err` if there are no operations, or if reading, processing
+/// or writing fails.
+pub fn process(input: &str, output: &str, color: &pl_color_space, ops: &[Op]) -> Result<()> {
+  if ops.is_empty() {
+    return Err(miette!("No operations given."));
+  }
+
+  let image = image::open(input).into_diagnostic()?;
+
+  let mut color = *color;
+  if color.primaries == pl_color_primaries::PL_COLOR_PRIM_UNKNOWN {
+    color.primaries = pl_color_primaries::PL_COLOR_PRIM_BT_709;
+  }
+  if color.transfer == pl_color_transfer::PL_COLOR_TRC_UNKNOWN {
+    color.transfer = match image {
+      DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => {
+        pl_color_transfer::PL_COLOR_TRC_LINEAR
+      }
+      _ => pl_color_transfer::PL_COLOR_TRC_SRGB,
+    };
+  }
+
+  let log = Log::default();
+  let vulkan = create_vulkan(&log)?;
+  let mut renderer = Renderer::new(&log, &vulkan.gpu());
+  let gpu = vulkan.as_gpu();
+
+  let output_image: DynamicImage = match image {
+    DynamicImage::ImageLuma8(image) => run(&mut renderer, gpu, &image, color, ops)?.into(),
+    DynamicImage::ImageLumaA8(image) => run(&mut renderer, gpu, &image, color, ops)?.into(),
+    DynamicImage::ImageRgb8(image) => run(&mut renderer, gpu, &image, color, ops)?.into(),
+    DynamicImage::ImageRgba8(image) => run(&mut renderer, gpu, &image, color, ops)?.into(),
+    DynamicImage::ImageLuma16(image) => run(&mut renderer, gpu, &image, color, ops)?.into(),
+    DynamicImage::ImageLumaA16(image) => run(&mut renderer, gpu, &image, color, ops)?.into(),
+    DynamicImage::ImageRgb16(image) => run(&mut renderer, gpu, &image, color, ops)?.into(),
+    DynamicImage::ImageRgba16(image) => run(&mut renderer, gpu, &image, color, ops)?.into(),
+    DynamicImage::ImageRgb32F(image) => run(&mut renderer, gpu, &image, color, ops)?.into(),
+    image => run(&mut renderer, gpu, &image.to_rgba32f(), color, ops)?.into(),
+  };
+
+  output_image.save(output).into_diagnostic()
+}
+
+/// Renders `image` through each of `ops` in turn.
+#[allow(clippy::cast_possible_wrap)]
+fn run<P: FramePixel>(
+  renderer: &mut Renderer,
+  gpu: Gpu,
+  image: &ImageBuffer<P, Vec<P::Subpixel>>,
+  color: pl_color_space,
+  ops: &[Op],
+) -> Result<ImageBuffer<P, Vec<P::Subpixel>>> {
+  let mut textures = Textures {
+    gpu,
+    textures: Vec::with_capacity(ops.len() + 1),
+  };
+
+  let mut uploaded = None;
+  let src = upload_image(gpu, &mut uploaded, image, &color);
+  textures.textures.extend(uploaded);
+
+  let mut src = src?;
+  let (mut width, mut height) = (image.width() as i32, image.height() as i32);
+
+  for op in ops {
+    (width, height) = op.size(width, height);
+    let tex = create_target::<P>(gpu, width, height)?;
+    textures.textures.push(tex.clone());
+
+    let mut dst = target_frame::<P>(&tex, &op.color(&src.color));
+    if let Some(depth) = op.depth() {
+      let sample_depth = tex.format().component_depth()[0];
+      dst.repr.bits.sample_depth = sample_depth;
+      dst.repr.bits.color_depth = depth.min(sample_depth);
+    }
+
+    renderer.render_image(&src, &dst, &op.render_params())?;
+    src = dst;
+  }
+
+  let last = &textures.textures[textures.textures.len() - 1];
+  download_image(gpu, last)
+}
@@ -0,0 +1,407 @@
+//! Reading and writing YUV4MPEG2 streams. Only planar 8-16 bit formats
+//! without alpha are supported, which covers what ffmpeg writes for
+//! `gray*`, `yuv420p*`, `yuv422p*` and `yuv444p*`.
+
+use std::io::{BufRead, Read, Write};
+
+use libplacebo_rs::utils::upload::{ColorFamily, PlanarFormat};
+use libplacebo_sys::{pl_chroma_location, pl_fmt_type};
+use miette::{miette, IntoDiagnostic, Result};
+
+const MAGIC: &str = "YUV4MPEG2";
+
+/// Stream parameters of a Y4M stream.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Header {
+  pub width: i32,
+  pub height: i32,
+  pub format: PlanarFormat,
+  pub chroma_location: pl_chroma_location,
+
+  /// `XCOLORRANGE`, if given: `true` for full range, `false` for limited.
+  pub full_range: Option<bool>,
+
+  /// Other parameters (frame rate, aspect ratio, interlacing, ...), passed
+  /// through unchanged.
+  pub extra: Vec<String>,
+}
+
+impl Header {
+  /// Parses a stream header line, without the trailing newline.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if the line isn't a Y4M header, lacks the frame size
+  /// or has an unsupported colorspace.
+  pub fn parse(line: &str) -> Result<Self> {
+    let mut params = line.split(' ');
+    if params.next() != Some(MAGIC) {
+      return Err(miette!("Input is not a YUV4MPEG2 stream."));
+    }
+
+    let (mut width, mut height) = (None, None);
+    let mut colorspace = "420jpeg";
+    let mut full_range = None;
+    let mut extra = Vec::new();
+
+    for param in params.filter(|p| !p.is_empty()) {
+      let mut chars = param.chars();
+      let tag = chars.next();
+      let value = chars.as_str();
+
+      match tag {
+        Some('W') => width = value.parse().ok(),
+        Some('H') => height = value.parse().ok(),
+        Some('C') => colorspace = value,
+        _ => match param {
+          "XCOLORRANGE=FULL" => full_range = Some(true),
+          "XCOLORRANGE=LIMITED" => full_range = Some(false),
+          _ => extra.push(param.to_owned()),
+        },
+      }
+    }
+
+    let (Some(width), Some(height)) = (width, height) else {
+      return Err(miette!("Y4M header has no valid frame size."));
+    };
+    if width <= 0 || height <= 0 {
+      return Err(miette!("Y4M frame size {width}x{height} is not positive."));
+    }
+    let (format, chroma_location) = parse_colorspace(colorspace)?;
+
+    Ok(Self {
+      width,
+      height,
+      format,
+      chroma_location,
+      full_range,
+      extra,
+    })
+  }
+
+  /// The stream header line, including the trailing newline.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if the format can't be stored in a Y4M stream.
+  pub fn to_line(&self) -> Result<String> {
+    let mut line = format!(
+      "{MAGIC} W{} H{} C{}",
+      self.width,
+      self.height,
+      colorspace_tag(&self.format, self.chroma_location)?
+    );
+
+    for param in &self.extra {
+      line.push(' ');
+      line.push_str(param);
+    }
+
+    match self.full_range {
+      Some(true) => line.push_str(" XCOLORRANGE=FULL"),
+      Some(false) => line.push_str(" XCOLORRANGE=LIMITED"),
+      None => {}
+    }
+
+    line.push('\n');
+    Ok(line)
+  }
+
+  /// Number of bytes of each plane of a frame.
+  #[allow(clippy::cast_sign_loss)]
+  pub fn plane_lens(&self) -> Vec<usize> {
+    (0..self.format.num_planes())
+      .map(|plane| {
+        let (width, height) = self.format.plane_size(plane, self.width, self.height);
+        width as usize * height as usize * self.format.bytes_per_sample as usize
+      })
+      .collect()
+  }
+}
+
+/// Format and chroma location of a `C` parameter.
+fn parse_colorspace(tag: &str) -> Result<(PlanarFormat, pl_chroma_location)> {
+  let unsupported = || miette!("Unsupported Y4M colorspace {tag}.");
+
+  // The depth is either appended to `mono` or follows a `p`, e.g. `420p10`.
+  let (layout, depth) = match tag.strip_prefix("mono") {
+    Some("") => ("mono", "8"),
+    Some(depth) => ("mono", depth),
+    None => match tag.split_once('p') {
+      Some((layout, depth)) if !depth.is_empty() && depth.bytes().all(|b| b.is_ascii_digit()) => {
+        (layout, depth)
+      }
+      _ => (tag, "8"),
+    },
+  };
+  let depth: i32 = depth.parse().map_err(|_| unsupported())?;
+
+  let (color_family, sub_sampling_w, sub_sampling_h, chroma_location) = match layout {
+    "mono" => (
+      ColorFamily::Gray,
+      0,
+      0,
+      pl_chroma_location::PL_CHROMA_UNKNOWN,
+    ),
+    "420" | "420jpeg" => (ColorFamily::Yuv, 1, 1, pl_chroma_location::PL_CHROMA_CENTER),
+    "420mpeg2" => (ColorFamily::Yuv, 1, 1, pl_chroma_location::PL_CHROMA_LEFT),
+    "420paldv" => (
+      ColorFamily::Yuv,
+      1,
+      1,
+      pl_chroma_location::PL_CHROMA_TOP_LEFT,
+    ),
+    "422" => (ColorFamily::Yuv, 1, 0, pl_chroma_location::PL_CHROMA_LEFT),
+    "444" => (
+      ColorFamily::Yuv,
+      0,
+      0,
+      pl_chroma_location::PL_CHROMA_UNKNOWN,
+    ),
+    _ => return Err(unsupported()),
+  };
+
+  if !(8..=16).contains(&depth) {
+    return Err(unsupported());
+  }
+
+  Ok((
+    PlanarFormat {
+      color_family,
+      sample_type: pl_fmt_type::PL_FMT_UNORM,
+      bits_per_sample: depth,
+      bytes_per_sample: if depth > 8 { 2 } else { 1 },
+      sub_sampling_w,
+      sub_sampling_h,
+    },
+    chroma_location,
+  ))
+}
+
+/// `C` parameter describing `format`. Only 8-bit 4:2:0 can carry the
+/// chroma location.
+fn colorspace_tag(format: &PlanarFormat, chroma_location: pl_chroma_location) -> Result<String> {
+  let depth = format.bits_per_sample;
+  if format.sample_type != pl_fmt_type::PL_FMT_UNORM || !(8..=16).contains(&depth) {
+    return Err(miette!(
+      "Y4M streams can't hold {depth}-bit {:?} samples.",
+      format.sample_type
+    ));
+  }
+
+  let layout = match (
+    format.color_family,
+    format.sub_sampling_w,
+    format.sub_sampling_h,
+  ) {
+    (ColorFamily::Gray, ..) => {
+      return Ok(if depth == 8 {
+        "mono".to_owned()
+      } else {
+        format!("mono{depth}")
+      });
+    }
+    (ColorFamily::Yuv, 1, 1) => "420",
+    (ColorFamily::Yuv, 1, 0) => "422",
+    (ColorFamily::Yuv, 0, 0) => "444",
+    _ => return Err(miette!("Y4M streams can't hold {format:?}.")),
+  };
+
+  Ok(match (layout, depth, chroma_location) {
+    ("420", 8, pl_chroma_location::PL_CHROMA_LEFT) => "420mpeg2".to_owned(),
+    ("420", 8, pl_chroma_location::PL_CHROMA_TOP_LEFT) => "420paldv".to_owned(),
+    ("420", 8, _) => "420jpeg".to_owned(),
+    (_, 8, _) => layout.to_owned(),
+    _ => format!("{layout}p{depth}"),
+  })
+}
+
+pub struct Reader<R> {
+  inner: R,
+  pub header: Header,
+}
+
+impl<R: BufRead> Reader<R> {
+  /// Starts reading a stream, by reading its header.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if the header can't be read or parsed.
+  pub fn new(mut inner: R) -> Result<Self> {
+    let mut line = String::new();
+    inner.read_line(&mut line).into_diagnostic()?;
+    let header = Header::parse(line.trim_end_matches('\n'))?;
+
+    Ok(Self { inner, header })
+  }
+
+  /// Reads the next frame into `planes`, one buffer per plane. Returns
+  /// `false` at the end of the stream.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` on I/O errors or if the stream is malformed or ends
+  /// in the middle of a frame.
+  pub fn read_frame(&mut self, planes: &mut Vec<Vec<u8>>) -> Result<bool> {
+    let mut line = String::new();
+    if self.inner.read_line(&mut line).into_diagnostic()? == 0 {
+      return Ok(false);
+    }
+
+    if !line.starts_with("FRAME") {
+      return Err(miette!(
+        "Expected a Y4M frame header, got {:?}.",
+        line.trim_end()
+      ));
+    }
+
+    let lens = self.header.plane_lens();
+    planes.resize_with(lens.len(), Vec::new);
+    for (plane, len) in planes.iter_mut().zip(lens) {
+      plane.resize(len, 0);
+      self
+        .inner
+        .read_exact(plane)
+        .map_err(|_| miette!("Y4M stream ends in the middle of a frame."))?;
+    }
+
+    Ok(true)
+  }
+}
+
+pub struct Writer<W> {
+  inner: W,
+  plane_lens: Vec<usize>,
+}
+
+impl<W: Write> Writer<W> {
+  /// Starts writing a stream, by writing its header.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if the header can't be written.
+  pub fn new(mut inner: W, header: &Header) -> Result<Self> {
+    inner
+      .write_all(header.to_line()?.as_bytes())
+      .into_diagnostic()?;
+
+    Ok(Self {
+      inner,
+      plane_lens: header.plane_lens(),
+    })
+  }
+
+  /// Writes a frame, one buffer per plane.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if the planes don't match the header or can't be
+  /// written.
+  pub fn write_frame(&mut self, planes: &[Vec<u8>]) -> Result<()> {
+    let lens: Vec<usize> = planes.iter().map(Vec::len).collect();
+    if lens != self.plane_lens {
+      return Err(miette!(
+        "Frame planes of {lens:?} bytes don't match the stream's {:?}.",
+        self.plane_lens
+      ));
+    }
+
+    self.inner.write_all(b"FRAME\n").into_diagnostic()?;
+    for plane in planes {
+      self.inner.write_all(plane).into_diagnostic()?;
+    }
+
+    Ok(())
+  }
+
+  /// Flushes the underlying writer.
+  ///
+  /// # Errors
+  ///
+  /// Will return `Err` if flushing fails.
+  pub fn flush(&mut self) -> Result<()> {
+    self.inner.flush().into_diagnostic()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn headers_round_trip() {
+    for line in [
+      "YUV4MPEG2 W1920 H1080 C420mpeg2 F24000:1001 Ip A1:1",
+      "YUV4MPEG2 W64 H32 C444p16 F25:1 XCOLORRANGE=FULL",
+      "YUV4MPEG2 W3 H3 Cmono10",
+      "YUV4MPEG2 W4 H2 C422p10 F30:1",
+    ] {
+      let header = Header::parse(line).unwrap();
+      assert_eq!(header.to_line().unwrap(), format!("{line}\n"));
+    }
+  }
+
+  #[test]
+  fn headers_are_parsed() {
+    let header = Header::parse("YUV4MPEG2 W6 H4 F25:1").unwrap();
+    assert_eq!((header.width, header.height), (6, 4));
+    assert_eq!(header.format.color_family, ColorFamily::Yuv);
+    assert_eq!(
+      (header.format.sub_sampling_w, header.format.sub_sampling_h),
+      (1, 1)
+    );
+    assert_eq!(header.chroma_location, pl_chroma_location::PL_CHROMA_CENTER);
+    assert_eq!(header.plane_lens(), [24, 6, 6]);
+    assert_eq!(header.extra, ["F25:1"]);
+
+    let header = Header::parse("YUV4MPEG2 W6 H4 C420p10").unwrap();
+    assert_eq!(header.format.bytes_per_sample, 2);
+    assert_eq!(header.plane_lens(), [48, 12, 12]);
+
+    // Subsampled planes of odd sizes are rounded up.
+    let header = Header::parse("YUV4MPEG2 W5 H3 C420paldv").unwrap();
+    assert_eq!(
+      header.chroma_location,
+      pl_chroma_location::PL_CHROMA_TOP_LEFT
+    );
+    assert_eq!(header.plane_lens(), [15, 6, 6]);
+
+    assert!(Header::parse("YUV4MPEG W6 H4").is_err());
+    assert!(Header::parse("YUV4MPEG2 W6").is_err());
+    assert!(Header::parse("YUV4MPEG2 W0 H4").is_err());
+    assert!(Header::parse("YUV4MPEG2 W6 H-4").is_err());
+    assert!(Header::parse("YUV4MPEG2 W6 H4 Cmonochrome").is_err());
+    assert!(Header::parse("YUV4MPEG2 W6 H4 C444alpha").is_err());
+    assert!(Header::parse("YUV4MPEG2 W6 H4 C420p18").is_err());
+  }
+
+  #[test]
+  fn frames_round_trip() {
+    let mut stream = Vec::new();
+    stream.extend_from_slice(b"YUV4MPEG2 W2 H2 C444\n");
+    for frame in 0..2u8 {
+      stream.extend_from_slice(b"FRAME\n");
+      stream.extend((0..12).map(|i| frame * 12 + i));
+    }
+
+    let mut reader = Reader::new(stream.as_slice()).unwrap();
+    let mut out = Vec::new();
+    let mut writer = Writer::new(&mut out, &reader.header).unwrap();
+
+    let mut planes = Vec::new();
+    let mut frames = 0;
+    while reader.read_frame(&mut planes).unwrap() {
+      assert_eq!(planes.len(), 3);
+      writer.write_frame(&planes).unwrap();
+      frames += 1;
+    }
+
+    assert_eq!(frames, 2);
+    assert_eq!(out, stream);
+
+    let truncated = &stream[..stream.len() - 1];
+    let mut reader = Reader::new(truncated).unwrap();
+    assert!(reader.read_frame(&mut planes).unwrap());
+    assert!(reader.read_frame(&mut planes).is_err());
+  }
+}
@@ -0,0 +1,352 @@
+//! Color descriptions of VapourSynth frames, read from and written to the
+//! `_Matrix`, `_Transfer`, `_Primaries`, `_ColorRange` and `_ChromaLocation`
+//! frame properties. Their ITU-T H.273 values are mapped to libplacebo's by
+//! [`libplacebo_rs::colorspace`].
+
+use libplacebo_rs::colorspace::{
+  chroma_location_from_h273, levels_from_range, levels_to_range, matrix_from_system,
+  primaries_from_h273, primaries_to_h273, system_from_matrix, transfer_from_h273, transfer_to_h273,
+};
+use libplacebo_sys::{
+  pl_chroma_location, pl_color_levels, pl_color_primaries, pl_color_repr, pl_color_space,
+  pl_color_system, pl_color_transfer,
+};
+use miette::{miette, Result};
+use vapoursynth4_rs::{
+  ffi::VSColorFamily,
+  frame::{VideoFormat, VideoFrame},
+  key,
+  map::{AppendMode, MapRef},
+};
+
+use crate::planes::bit_encoding;
+
+/// User overrides for a color description, in H.273 values. Unset fields fall
+/// back to the frame properties.
+#[derive(Clone, Copy, Default)]
+pub struct ColorArgs {
+  pub matrix: Option<i64>,
+  pub transfer: Option<i64>,
+  pub primaries: Option<i64>,
+  pub range: Option<i64>,
+}
+
+/// Resolved color description of a frame.
+#[derive(Clone, Copy)]
+pub struct Colorimetry {
+  pub repr: pl_color_repr,
+  pub color: pl_color_space,
+}
+
+/// Reads an integer frame property, treating "unspecified" (2) as missing.
+pub fn unspecified_to_none<E>(value: Result<i64, E>) -> Option<i64> {
+  value.ok().filter(|&v| v != 2)
+}
+
+/// Resolves the color description of the source frame, preferring the user
+/// supplied arguments over the frame properties.
+pub fn resolve_source(
+  args: &ColorArgs,
+  format: &VideoFormat,
+  props: Option<&MapRef>,
+) -> Result<Colorimetry> {
+  let is_rgb = format.color_family == VSColorFamily::RGB;
+
+  let prop_matrix = props.and_then(|p| unspecified_to_none(p.get_int(key!("_Matrix"), 0)));
+  let prop_transfer = props.and_then(|p| unspecified_to_none(p.get_int(key!("_Transfer"), 0)));
+  let prop_primaries = props.and_then(|p| unspecified_to_none(p.get_int(key!("_Primaries"), 0)));
+  let prop_range = props.and_then(|p| p.get_int(key!("_ColorRange"), 0).ok());
+
+  let transfer = args
+    .transfer
+    .or(prop_transfer)
+    .map_or(Ok(pl_color_transfer::PL_COLOR_TRC_BT_1886), |t| {
+      transfer_from_h273(t).ok_or_else(|| miette!("unsupported source transfer {t}."))
+    })?;
+
+  let sys = match args.matrix.or(prop_matrix) {
+    Some(m) => system_from_matrix(m, Some(transfer))
+      .ok_or_else(|| miette!("unsupported source matrix {m}."))?,
+    None if is_rgb => pl_color_system::PL_COLOR_SYSTEM_RGB,
+    None => pl_color_system::PL_COLOR_SYSTEM_BT_709,
+  };
+
+  let primaries = args
+    .primaries
+    .or(prop_primaries)
+    .map_or(Ok(pl_color_primaries::PL_COLOR_PRIM_BT_709), |p| {
+      primaries_from_h273(p).ok_or_else(|| miette!("unsupported source primaries {p}."))
+    })?;
+
+  let levels = match args.range.or(prop_range) {
+    Some(r) => levels_from_range(r).ok_or_else(|| miette!("unsupported source range {r}."))?,
+    None if is_rgb || sys == pl_color_system::PL_COLOR_SYSTEM_XYZ => {
+      pl_color_levels::PL_COLOR_LEVELS_FULL
+    }
+    None => pl_color_levels::PL_COLOR_LEVELS_LIMITED,
+  };
+
+  Ok(Colorimetry {
+    repr: pl_color_repr {
+      sys,
+      levels,
+      bits: bit_encoding(format),
+      ..pl_color_repr::default()
+    },
+    color: pl_color_space {
+      primaries,
+      transfer,
+      ..pl_color_space::default()
+    },
+  })
+}
+
+/// Resolves the color description of the output frame. Anything left unset by
+/// the user is carried over from the source.
+pub fn resolve_target(
+  args: &ColorArgs,
+  format: &VideoFormat,
+  src: &Colorimetry,
+) -> Result<Colorimetry> {
+  let is_rgb = format.color_family == VSColorFamily::RGB;
+
+  let transfer = args.transfer.map_or(Ok(src.color.transfer), |t| {
+    transfer_from_h273(t).ok_or_else(|| miette!("unsupported transfer {t}."))
+  })?;
+
+  let sys = match args.matrix {
+    Some(m) => {
+      system_from_matrix(m, Some(transfer)).ok_or_else(|| miette!("unsupported matrix {m}."))?
+    }
+    None if is_rgb => pl_color_system::PL_COLOR_SYSTEM_RGB,
+    None if src.repr.sys == pl_color_system::PL_COLOR_SYSTEM_RGB => {
+      pl_color_system::PL_COLOR_SYSTEM_BT_709
+    }
+    None => src.repr.sys,
+  };
+
+  let primaries = args.primaries.map_or(Ok(src.color.primaries), |p| {
+    primaries_from_h273(p).ok_or_else(|| miette!("unsupported primaries {p}."))
+  })?;
+
+  let levels = match args.range {
+    Some(r) => levels_from_range(r).ok_or_else(|| miette!("unsupported range {r}."))?,
+    None if is_rgb || sys == pl_color_system::PL_COLOR_SYSTEM_XYZ => {
+      pl_color_levels::PL_COLOR_LEVELS_FULL
+    }
+    None if src.repr.sys == pl_color_system::PL_COLOR_SYSTEM_RGB => {
+      pl_color_levels::PL_COLOR_LEVELS_LIMITED
+    }
+    None => src.repr.levels,
+  };
+
+  Ok(Colorimetry {
+    repr: pl_color_repr {
+      sys,
+      levels,
+      bits: bit_encoding(format),
+      ..pl_color_repr::default()
+    },
+    color: pl_color_space {
+      primaries,
+      transfer,
+      ..pl_color_space::default()
+    },
+  })
+}
+
+/// Reads the color arguments of a filter, either the output ones (`matrix`,
+/// ...) or the source overrides (`matrix_in`, ...).
+pub fn get_color_args(input: &MapRef, suffix_in: bool) -> ColorArgs {
+  if suffix_in {
+    ColorArgs {
+      matrix: input.get_int(key!("matrix_in"), 0).ok(),
+      transfer: input.get_int(key!("transfer_in"), 0).ok(),
+      primaries: input.get_int(key!("primaries_in"), 0).ok(),
+      range: input.get_int(key!("range_in"), 0).ok(),
+    }
+  } else {
+    ColorArgs {
+      matrix: input.get_int(key!("matrix"), 0).ok(),
+      transfer: input.get_int(key!("transfer"), 0).ok(),
+      primaries: input.get_int(key!("primaries"), 0).ok(),
+      range: input.get_int(key!("range"), 0).ok(),
+    }
+  }
+}
+
+/// Reads the chroma location from the `_ChromaLocation` property, defaulting
+/// to left like most video.
+pub fn chroma_location(props: Option<&MapRef>) -> pl_chroma_location {
+  props
+    .and_then(|p| p.get_int(key!("_ChromaLocation"), 0).ok())
+    .and_then(chroma_location_from_h273)
+    .unwrap_or(pl_chroma_location::PL_CHROMA_LEFT)
+}
+
+/// Writes `colorimetry` to the color frame properties of `dst`. Values without
+/// an H.273 equivalent remove the property, except for XYZ, which is written as
+/// [`libplacebo_rs::colorspace::MATRIX_XYZ`].
+pub fn set_props(dst: &mut VideoFrame, colorimetry: &Colorimetry) {
+  let Some(mut props) = dst.properties_mut() else {
+    return;
+  };
+
+  let mut set = |key, value: Option<i64>| match value {
+    Some(v) => {
+      let _ = props.set_int(key, v, AppendMode::Replace);
+    }
+    None => {
+      props.delete_key(key);
+    }
+  };
+
+  set(key!("_Matrix"), matrix_from_system(colorimetry.repr.sys));
+  set(
+    key!("_Transfer"),
+    transfer_to_h273(colorimetry.color.transfer),
+  );
+  set(
+    key!("_Primaries"),
+    primaries_to_h273(colorimetry.color.primaries),
+  );
+  set(
+    key!("_ColorRange"),
+    levels_to_range(colorimetry.repr.levels),
+  );
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::format;
+  use libplacebo_rs::colorspace::MATRIX_XYZ;
+
+  #[test]
+  fn source_defaults_depend_on_color_family() {
+    let rgb = resolve_source(
+      &ColorArgs::default(),
+      &format(VSColorFamily::RGB, 8, (0, 0)),
+      None,
+    )
+    .unwrap();
+    assert_eq!(rgb.repr.sys, pl_color_system::PL_COLOR_SYSTEM_RGB);
+    assert_eq!(rgb.repr.levels, pl_color_levels::PL_COLOR_LEVELS_FULL);
+
+    let yuv = resolve_source(
+      &ColorArgs::default(),
+      &format(VSColorFamily::YUV, 10, (1, 1)),
+      None,
+    )
+    .unwrap();
+    assert_eq!(yuv.repr.sys, pl_color_system::PL_COLOR_SYSTEM_BT_709);
+    assert_eq!(yuv.repr.levels, pl_color_levels::PL_COLOR_LEVELS_LIMITED);
+    assert_eq!(yuv.repr.bits.color_depth, 10);
+    assert_eq!(
+      yuv.color.primaries,
+      pl_color_primaries::PL_COLOR_PRIM_BT_709
+    );
+    assert_eq!(yuv.color.transfer, pl_color_transfer::PL_COLOR_TRC_BT_1886);
+  }
+
+  #[test]
+  fn source_arguments_are_validated() {
+    let yuv = format(VSColorFamily::YUV, 10, (1, 1));
+    let hdr = resolve_source(
+      &ColorArgs {
+        matrix: Some(9),
+        transfer: Some(16),
+        primaries: Some(9),
+        range: Some(0),
+      },
+      &yuv,
+      None,
+    )
+    .unwrap();
+    assert_eq!(hdr.repr.sys, pl_color_system::PL_COLOR_SYSTEM_BT_2020_NC);
+    assert_eq!(hdr.color.transfer, pl_color_transfer::PL_COLOR_TRC_PQ);
+    assert_eq!(
+      hdr.color.primaries,
+      pl_color_primaries::PL_COLOR_PRIM_BT_2020
+    );
+    assert_eq!(hdr.repr.levels, pl_color_levels::PL_COLOR_LEVELS_FULL);
+
+    for args in [
+      ColorArgs {
+        matrix: Some(99),
+        ..ColorArgs::default()
+      },
+      ColorArgs {
+        transfer: Some(100),
+        ..ColorArgs::default()
+      },
+      ColorArgs {
+        primaries: Some(100),
+        ..ColorArgs::default()
+      },
+      ColorArgs {
+        range: Some(5),
+        ..ColorArgs::default()
+      },
+    ] {
+      assert!(resolve_source(&args, &yuv, None).is_err());
+    }
+  }
+
+  #[test]
+  fn target_carries_over_source() {
+    let src = resolve_source(
+      &ColorArgs {
+        transfer: Some(16),
+        ..ColorArgs::default()
+      },
+      &format(VSColorFamily::RGB, 16, (0, 0)),
+      None,
+    )
+    .unwrap();
+
+    // RGB to YUV picks the usual YUV defaults instead of RGB's.
+    let yuv = resolve_target(
+      &ColorArgs::default(),
+      &format(VSColorFamily::YUV, 8, (1, 1)),
+      &src,
+    )
+    .unwrap();
+    assert_eq!(yuv.repr.sys, pl_color_system::PL_COLOR_SYSTEM_BT_709);
+    assert_eq!(yuv.repr.levels, pl_color_levels::PL_COLOR_LEVELS_LIMITED);
+    assert_eq!(yuv.repr.bits.color_depth, 8);
+    assert_eq!(yuv.color.transfer, pl_color_transfer::PL_COLOR_TRC_PQ);
+
+    let bt709 = resolve_target(
+      &ColorArgs {
+        transfer: Some(1),
+        ..ColorArgs::default()
+      },
+      &format(VSColorFamily::YUV, 8, (1, 1)),
+      &src,
+    )
+    .unwrap();
+    assert_eq!(
+      bt709.color.transfer,
+      pl_color_transfer::PL_COLOR_TRC_BT_1886
+    );
+  }
+
+  #[test]
+  fn xyz_is_full_range() {
+    let rgb = format(VSColorFamily::RGB, 16, (0, 0));
+    let xyz_args = ColorArgs {
+      matrix: Some(MATRIX_XYZ),
+      ..ColorArgs::default()
+    };
+
+    let src = resolve_source(&ColorArgs::default(), &rgb, None).unwrap();
+    let xyz = resolve_target(&xyz_args, &format(VSColorFamily::YUV, 16, (0, 0)), &src).unwrap();
+    assert_eq!(xyz.repr.sys, pl_color_system::PL_COLOR_SYSTEM_XYZ);
+    assert_eq!(xyz.repr.levels, pl_color_levels::PL_COLOR_LEVELS_FULL);
+
+    let xyz = resolve_source(&xyz_args, &format(VSColorFamily::YUV, 16, (0, 0)), None).unwrap();
+    assert_eq!(xyz.repr.sys, pl_color_system::PL_COLOR_SYSTEM_XYZ);
+    assert_eq!(xyz.repr.levels, pl_color_levels::PL_COLOR_LEVELS_FULL);
+  }
+}
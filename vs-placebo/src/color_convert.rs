@@ -0,0 +1,419 @@
+use const_str::cstr;
+use libplacebo_rs::colorspace::chroma_location_to_h273;
+use libplacebo_rs::gpu::Tex;
+use libplacebo_rs::renderer::Renderer;
+use libplacebo_rs::shaders::icc::Icc;
+use libplacebo_rs::shaders_root::ShaderInfo;
+use libplacebo_rs::{log::Log, vulkan::Vulkan};
+use libplacebo_sys::{
+  pl_chroma_location, pl_color_system, pl_render_default_params, pl_render_info, pl_render_params,
+};
+use miette::{miette, Report, Result};
+use std::ffi::CString;
+use std::ptr::from_mut;
+use std::{
+  ffi::{c_void, CStr},
+  sync::{Arc, Mutex},
+};
+use vapoursynth4_rs::{
+  core::CoreRef,
+  frame::{FrameContext, VideoFormat, VideoFrame},
+  key,
+  map::{AppendMode, MapMut, MapRef},
+  node::{
+    ActivationReason, Dependencies, Filter as VsFilter, FilterDependency, Node, RequestPattern,
+    VideoNode,
+  },
+};
+
+use crate::color::{
+  chroma_location, get_color_args, resolve_source, resolve_target, set_props, ColorArgs,
+  Colorimetry,
+};
+use crate::planes::{
+  check_gpu_format, check_render_format, create_output, create_vulkan, destroy_textures,
+  download_planes, frame_from_planes, upload_plane, Imports, TexUsage,
+};
+use crate::shader_dump::ShaderDump;
+use crate::stats::{FrameStats, StatsTimer};
+
+/// Renderer state a frame is converted with.
+struct State {
+  renderer: Renderer,
+
+  /// Profile read from the `ICCProfile` property of the last frame converted
+  /// with this state.
+  icc_prop: Option<Icc>,
+}
+
+impl State {
+  /// Tracks the `ICCProfile` frame property. Updating an unchanged profile is
+  /// cheap, so this is done for every frame.
+  fn update_icc_prop(&mut self, log: &Log, data: Option<&[u8]>) -> Result<()> {
+    match (data, &mut self.icc_prop) {
+      (None, icc) => *icc = None,
+      (Some(data), Some(icc)) => icc.update(log, data, None)?,
+      (Some(data), icc) => *icc = Some(Icc::open(log, data, None)?),
+    }
+
+    Ok(())
+  }
+}
+
+/// Receives the passes the renderer runs for a frame.
+struct PassObserver<'a, 'b> {
+  stats: Option<&'a mut FrameStats<'b>>,
+  shader_dump: Option<&'a ShaderDump>,
+
+  /// First error writing a shader, returned once rendering is done.
+  error: Option<Report>,
+}
+
+unsafe extern "C" fn observe_pass(priv_: *mut c_void, info: *const pl_render_info) {
+  let observer = &mut *priv_.cast::<PassObserver>();
+  let pass = &*(*info).pass;
+
+  if let Some(stats) = observer.stats.as_deref_mut() {
+    stats.record_render_pass(pass);
+  }
+
+  if let (Some(dump), None) = (observer.shader_dump, &observer.error) {
+    if let Err(error) = dump.write_info("color_convert", &ShaderInfo::from_ref(pass.shader)) {
+      observer.error = Some(error);
+    }
+  }
+}
+
+pub struct Filter {
+  node: VideoNode,
+  out_format: VideoFormat,
+
+  /// Overrides for the source color description.
+  src_args: ColorArgs,
+
+  /// Requested output color description.
+  dst_args: ColorArgs,
+
+  /// Source profile given through `icc_in`. Takes precedence over the
+  /// `ICCProfile` frame property.
+  icc_in: Option<Icc>,
+
+  /// Output profile given through `icc_out`.
+  icc_out: Option<Icc>,
+
+  /// Attach GPU statistics to output frames.
+  stats: Option<StatsTimer>,
+
+  /// Where to write generated shaders, if anywhere.
+  shader_dump: Option<ShaderDump>,
+
+  /// Idle renderer states. Renderers are not thread-safe, so each frame in
+  /// flight takes one, creating it if there is none.
+  states: Mutex<Vec<State>>,
+
+  vulkan: Vulkan,
+  pl_log: Arc<Log>,
+}
+
+impl Filter {
+  /// Uploads `src`, renders it to `dst` in one go through the renderer, which
+  /// also handles subsampled chroma, and returns the output colorimetry and
+  /// chroma location, and whether an ICC profile was applied.
+  fn convert_frame(
+    &self,
+    src: &VideoFrame,
+    dst: &mut VideoFrame,
+    stats: Option<&mut FrameStats>,
+  ) -> Result<(Colorimetry, pl_chroma_location, bool)> {
+    let src_format = *src.get_video_format();
+    let src_props = src.properties();
+
+    let src_colorimetry = resolve_source(&self.src_args, &src_format, src_props.as_ref())?;
+    let dst_colorimetry = resolve_target(&self.dst_args, &self.out_format, &src_colorimetry)?;
+
+    // Only the colors are converted, so chroma stays where it was.
+    let location = chroma_location(src_props.as_ref());
+
+    let icc_prop = src_props
+      .as_ref()
+      .and_then(|props| props.get_binary(key!("ICCProfile"), 0).ok());
+
+    let mut observer = PassObserver {
+      stats,
+      shader_dump: self.shader_dump.as_ref(),
+      error: None,
+    };
+    let mut params = pl_render_params {
+      skip_caching_single_frame: true,
+      ..unsafe { pl_render_default_params }
+    };
+    if observer.stats.is_some() || observer.shader_dump.is_some() {
+      params.info_callback = Some(observe_pass);
+      params.info_priv = from_mut(&mut observer).cast();
+    }
+
+    let mut state = self
+      .states
+      .lock()
+      .map_err(|_| miette!("renderer pool is poisoned."))?
+      .pop()
+      .unwrap_or_else(|| State {
+        renderer: Renderer::new(&self.pl_log, &self.vulkan.gpu()),
+        icc_prop: None,
+      });
+
+    let mut texes_in: Vec<Tex> = Vec::new();
+    let mut texes_out: Vec<Tex> = Vec::new();
+    let mut imports = Imports::default();
+
+    let mut process = || -> Result<bool> {
+      for plane in 0..src_format.num_planes {
+        texes_in.push(upload_plane(&self.vulkan, src, plane, &mut imports)?);
+      }
+
+      for plane in 0..self.out_format.num_planes {
+        texes_out.push(create_output(
+          &self.vulkan,
+          &self.out_format,
+          dst.frame_width(plane),
+          dst.frame_height(plane),
+          plane,
+        )?);
+      }
+
+      state.update_icc_prop(&self.pl_log, icc_prop)?;
+
+      let icc_in = self.icc_in.as_ref().or(state.icc_prop.as_ref());
+      let image = frame_from_planes(&src_format, &texes_in, &src_colorimetry, location, icc_in)?;
+      let target = frame_from_planes(
+        &self.out_format,
+        &texes_out,
+        &dst_colorimetry,
+        location,
+        self.icc_out.as_ref(),
+      )?;
+
+      state.renderer.render_image(&image, &target, &params)?;
+      let used_icc = icc_in.is_some() || self.icc_out.is_some();
+
+      download_planes(&self.vulkan, &texes_out, dst)?;
+      Ok(used_icc)
+    };
+
+    let result = process();
+
+    imports.release(&self.vulkan);
+    destroy_textures(&self.vulkan, &texes_in);
+    destroy_textures(&self.vulkan, &texes_out);
+
+    if let Ok(mut states) = self.states.lock() {
+      states.push(state);
+    }
+
+    match observer.error {
+      Some(error) => Err(error),
+      None => result.map(|used_icc| (dst_colorimetry, location, used_icc)),
+    }
+  }
+}
+
+impl VsFilter for Filter {
+  type Error = CString;
+  type FrameType = VideoFrame;
+  type FilterData = ();
+
+  #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+  fn create<'b>(
+    input: MapRef<'_>,
+    output: MapMut<'_>,
+    _data: Option<Box<Self::FilterData>>,
+    mut core: CoreRef,
+  ) -> Result<(), Self::Error> {
+    let Ok(node) = input.get_video_node(key!("clip"), 0) else {
+      return Err(CString::new("Failed to get clip").unwrap());
+    };
+
+    let n = node.clone();
+    let vi = n.info();
+
+    if let Err(error) = check_render_format(&vi.format) {
+      return Err(CString::new(format!("placebo.ColorConvert: input {error}")).unwrap());
+    }
+
+    let out_format = match input.get_int(key!("format"), 0) {
+      Ok(id) => core.get_video_format_by_id(id as u32),
+      Err(_) => vi.format,
+    };
+
+    if let Err(error) = check_render_format(&out_format) {
+      return Err(CString::new(format!("placebo.ColorConvert: output {error}")).unwrap());
+    }
+
+    if vi.width % (1 << out_format.sub_sampling_w) != 0
+      || vi.height % (1 << out_format.sub_sampling_h) != 0
+    {
+      return Err(
+        CString::new(
+          "placebo.ColorConvert: clip dimensions must be divisible by the output subsampling.",
+        )
+        .unwrap(),
+      );
+    }
+
+    let mut vi_out = vi.clone();
+    vi_out.format = out_format;
+
+    let shader_dump = match input.get_utf8(key!("debug_shader_dir"), 0) {
+      Ok(dir) => Some(
+        ShaderDump::new(dir)
+          .map_err(|error| CString::new(format!("placebo.ColorConvert: {error:?}")).unwrap())?,
+      ),
+      Err(_) => None,
+    };
+
+    // libplacebo setup.
+
+    // Log references are held by `Renderer` and `Vulkan`.
+    let pl_log = Arc::new(Log::default());
+
+    let vulkan = create_vulkan(&pl_log)
+      .map_err(|error| CString::new(format!("placebo.ColorConvert: {error}")).unwrap())?;
+
+    let open_icc = |name: &str, path: Result<&str, _>| match path {
+      Ok(path) => Icc::from_file(&pl_log, path, None)
+        .map(Some)
+        .map_err(|error| CString::new(format!("placebo.ColorConvert: {name}: {error}")).unwrap()),
+      Err(_) => Ok(None),
+    };
+
+    let icc_in = open_icc("icc_in", input.get_utf8(key!("icc_in"), 0))?;
+    let icc_out = open_icc("icc_out", input.get_utf8(key!("icc_out"), 0))?;
+
+    for (usage, format, kind) in [
+      (TexUsage::Input, &vi.format, "input"),
+      (TexUsage::Output, &out_format, "output"),
+    ] {
+      if let Err(error) = check_gpu_format(&vulkan, format, usage) {
+        return Err(CString::new(format!("placebo.ColorConvert: {kind} {error}")).unwrap());
+      }
+    }
+
+    let mut filter = Self {
+      node,
+      out_format,
+      src_args: get_color_args(&input, true),
+      dst_args: get_color_args(&input, false),
+      icc_in,
+      icc_out,
+      stats: (input.get_int(key!("stats"), 0).unwrap_or(0) != 0).then(|| StatsTimer::new(&vulkan)),
+      shader_dump,
+      states: Mutex::default(),
+      pl_log,
+      vulkan,
+    };
+
+    let deps = [FilterDependency {
+      source: filter.node.as_mut_ptr(),
+      request_pattern: RequestPattern::StrictSpatial,
+    }];
+
+    core.create_video_filter(
+      output,
+      cstr!("ColorConvert"),
+      &vi_out,
+      Box::new(filter),
+      Dependencies::new(&deps).unwrap(),
+    );
+
+    Ok(())
+  }
+
+  fn get_frame(
+    &self,
+    n: i32,
+    activation_reason: ActivationReason,
+    _frame_data: *mut *mut c_void,
+    mut ctx: FrameContext,
+    core: CoreRef,
+  ) -> Result<Option<VideoFrame>, Self::Error> {
+    match activation_reason {
+      ActivationReason::Initial => {
+        ctx.request_frame_filter(n, &self.node);
+      }
+      ActivationReason::AllFramesReady => {
+        let src = self.node.get_frame_filter(n, &mut ctx);
+
+        let mut dst = core.new_video_frame(
+          &self.out_format,
+          src.frame_width(0),
+          src.frame_height(0),
+          Some(&src),
+        );
+
+        let mut stats = self
+          .stats
+          .as_ref()
+          .map(|timer| FrameStats::new(&self.vulkan, timer));
+
+        let result = self.convert_frame(&src, &mut dst, stats.as_mut());
+        if let Some(stats) = &mut stats {
+          stats.finish();
+        }
+
+        match result {
+          Ok((dst_colorimetry, location, used_icc)) => {
+            set_props(&mut dst, &dst_colorimetry);
+            if let Some(stats) = &stats {
+              stats.set_props(&mut dst);
+            }
+
+            if let Some(mut props) = dst.properties_mut() {
+              match chroma_location_to_h273(location) {
+                Some(location)
+                  if dst_colorimetry.repr.sys != pl_color_system::PL_COLOR_SYSTEM_RGB =>
+                {
+                  let _ = props.set_int(key!("_ChromaLocation"), location, AppendMode::Replace);
+                }
+                _ => {
+                  props.delete_key(key!("_ChromaLocation"));
+                }
+              }
+
+              // The source profile no longer describes the output.
+              if used_icc {
+                props.delete_key(key!("ICCProfile"));
+              }
+            }
+          }
+          Err(error) => {
+            return Err(CString::new(format!("placebo.ColorConvert: {error:?}")).unwrap())
+          }
+        }
+
+        return Ok(Some(dst));
+      }
+      ActivationReason::Error => {}
+    }
+
+    Ok(None)
+  }
+
+  const NAME: &'static CStr = cstr!("ColorConvert");
+  const ARGS: &'static CStr = cstr!(
+    "clip:vnode;\
+    format:int:opt;\
+    matrix:int:opt;\
+    transfer:int:opt;\
+    primaries:int:opt;\
+    range:int:opt;\
+    matrix_in:int:opt;\
+    transfer_in:int:opt;\
+    primaries_in:int:opt;\
+    range_in:int:opt;\
+    icc_in:data:opt;\
+    icc_out:data:opt;\
+    stats:int:opt;\
+    debug_shader_dir:data:opt;"
+  );
+  const RETURN_TYPE: &'static CStr = cstr!("clip:vnode;");
+}
@@ -0,0 +1,444 @@
+use const_str::cstr;
+use libplacebo_rs::colorspace::{chroma_location_from_h273, chroma_location_to_h273};
+use libplacebo_rs::filters::find_filter_config;
+use libplacebo_rs::gpu::Tex;
+use libplacebo_rs::renderer::Renderer;
+use libplacebo_rs::shaders::icc::Icc;
+use libplacebo_rs::{log::Log, vulkan::Vulkan};
+use libplacebo_sys::{
+  pl_chroma_location, pl_color_system, pl_filter_config, pl_filter_usage, pl_render_default_params,
+  pl_render_params,
+};
+use miette::{miette, Result};
+use std::ffi::CString;
+use std::ptr::{from_ref, null};
+use std::sync::Mutex;
+use std::{
+  ffi::{c_void, CStr},
+  sync::Arc,
+};
+use vapoursynth4_rs::{
+  core::CoreRef,
+  frame::{FrameContext, VideoFormat, VideoFrame},
+  key,
+  map::{AppendMode, MapMut, MapRef},
+  node::{
+    ActivationReason, Dependencies, Filter as VsFilter, FilterDependency, Node, RequestPattern,
+    VideoNode,
+  },
+};
+
+use crate::color::{
+  chroma_location, get_color_args, resolve_source, resolve_target, set_props, ColorArgs,
+  Colorimetry,
+};
+use crate::planes::{
+  check_gpu_format, check_render_format, create_output, create_vulkan, destroy_textures,
+  download_planes, frame_from_planes, upload_plane, Imports, TexUsage,
+};
+use crate::stats::{FrameStats, StatsTimer};
+
+/// Looks up a chroma scaler by name. `None` leaves the choice to libplacebo.
+fn find_scaler(name: Option<&CStr>, usage: pl_filter_usage) -> Result<*const pl_filter_config> {
+  name.map_or(Ok(null()), |name| {
+    find_filter_config(name, usage)
+      .map(from_ref)
+      .ok_or_else(|| miette!("unknown scaler {}.", name.to_string_lossy()))
+  })
+}
+
+/// Renderer state that frames are converted under, one at a time.
+struct State {
+  renderer: Renderer,
+
+  /// Profile read from the `ICCProfile` property of the current frame.
+  icc_prop: Option<Icc>,
+}
+
+impl State {
+  /// Tracks the `ICCProfile` frame property. Updating an unchanged profile is
+  /// cheap, so this is done for every frame.
+  fn update_icc_prop(&mut self, log: &Log, data: Option<&[u8]>) -> Result<()> {
+    match (data, &mut self.icc_prop) {
+      (None, icc) => *icc = None,
+      (Some(data), Some(icc)) => icc.update(log, data, None)?,
+      (Some(data), icc) => *icc = Some(Icc::open(log, data, None)?),
+    }
+
+    Ok(())
+  }
+}
+
+pub struct Filter {
+  node: VideoNode,
+  out_format: VideoFormat,
+
+  /// Overrides for the source color description.
+  src_args: ColorArgs,
+
+  /// Requested output color description.
+  dst_args: ColorArgs,
+
+  /// Override for the source chroma location.
+  chroma_location_in: Option<pl_chroma_location>,
+
+  /// Output chroma location. `None` keeps the source one.
+  chroma_location: Option<pl_chroma_location>,
+
+  /// Names of the `pl_filter_config`s used to upscale and downscale chroma.
+  chroma_upscaler: Option<CString>,
+  chroma_downscaler: Option<CString>,
+
+  /// Source profile given through `icc_in`. Takes precedence over the
+  /// `ICCProfile` frame property.
+  icc_in: Option<Icc>,
+
+  /// Output profile given through `icc_out`.
+  icc_out: Option<Icc>,
+
+  /// Attach GPU statistics to output frames.
+  stats: Option<StatsTimer>,
+
+  state: Mutex<State>,
+
+  vulkan: Vulkan,
+  pl_log: Arc<Log>,
+}
+
+impl Filter {
+  fn render_params(&self) -> Result<pl_render_params> {
+    Ok(pl_render_params {
+      plane_upscaler: find_scaler(
+        self.chroma_upscaler.as_deref(),
+        pl_filter_usage::PL_FILTER_UPSCALING,
+      )?,
+      plane_downscaler: find_scaler(
+        self.chroma_downscaler.as_deref(),
+        pl_filter_usage::PL_FILTER_DOWNSCALING,
+      )?,
+      skip_caching_single_frame: true,
+      ..unsafe { pl_render_default_params }
+    })
+  }
+
+  /// Uploads `src`, renders it to `dst` and returns the output colorimetry
+  /// and chroma location, and whether an ICC profile was applied.
+  fn convert_frame(
+    &self,
+    src: &VideoFrame,
+    dst: &mut VideoFrame,
+    stats: Option<&mut FrameStats>,
+  ) -> Result<(Colorimetry, pl_chroma_location, bool)> {
+    let src_format = *src.get_video_format();
+    let src_props = src.properties();
+
+    let src_colorimetry = resolve_source(&self.src_args, &src_format, src_props.as_ref())?;
+    let dst_colorimetry = resolve_target(&self.dst_args, &self.out_format, &src_colorimetry)?;
+
+    let src_location = self
+      .chroma_location_in
+      .unwrap_or_else(|| chroma_location(src_props.as_ref()));
+    let dst_location = self.chroma_location.unwrap_or(src_location);
+
+    let icc_prop = src_props
+      .as_ref()
+      .and_then(|props| props.get_binary(key!("ICCProfile"), 0).ok());
+
+    let mut params = self.render_params()?;
+    if let Some(stats) = stats {
+      stats.attach(&mut params);
+    }
+
+    let mut texes_in: Vec<Tex> = Vec::new();
+    let mut texes_out: Vec<Tex> = Vec::new();
+    let mut imports = Imports::default();
+
+    let mut process = || -> Result<bool> {
+      for plane in 0..src_format.num_planes {
+        texes_in.push(upload_plane(&self.vulkan, src, plane, &mut imports)?);
+      }
+
+      for plane in 0..self.out_format.num_planes {
+        texes_out.push(create_output(
+          &self.vulkan,
+          &self.out_format,
+          dst.frame_width(plane),
+          dst.frame_height(plane),
+          plane,
+        )?);
+      }
+
+      let mut state = self
+        .state
+        .lock()
+        .map_err(|_| miette!("renderer is poisoned."))?;
+      state.update_icc_prop(&self.pl_log, icc_prop)?;
+
+      let icc_in = self.icc_in.as_ref().or(state.icc_prop.as_ref());
+      let image = frame_from_planes(
+        &src_format,
+        &texes_in,
+        &src_colorimetry,
+        src_location,
+        icc_in,
+      )?;
+      let target = frame_from_planes(
+        &self.out_format,
+        &texes_out,
+        &dst_colorimetry,
+        dst_location,
+        self.icc_out.as_ref(),
+      )?;
+
+      state.renderer.render_image(&image, &target, &params)?;
+      let used_icc = icc_in.is_some() || self.icc_out.is_some();
+      drop(state);
+
+      download_planes(&self.vulkan, &texes_out, dst)?;
+      Ok(used_icc)
+    };
+
+    let result = process();
+
+    imports.release(&self.vulkan);
+    destroy_textures(&self.vulkan, &texes_in);
+    destroy_textures(&self.vulkan, &texes_out);
+
+    result.map(|used_icc| (dst_colorimetry, dst_location, used_icc))
+  }
+}
+
+impl VsFilter for Filter {
+  type Error = CString;
+  type FrameType = VideoFrame;
+  type FilterData = ();
+
+  #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+  fn create<'b>(
+    input: MapRef<'_>,
+    output: MapMut<'_>,
+    _data: Option<Box<Self::FilterData>>,
+    mut core: CoreRef,
+  ) -> Result<(), Self::Error> {
+    let Ok(node) = input.get_video_node(key!("clip"), 0) else {
+      return Err(CString::new("Failed to get clip").unwrap());
+    };
+
+    let n = node.clone();
+    let vi = n.info();
+
+    if let Err(error) = check_render_format(&vi.format) {
+      return Err(CString::new(format!("placebo.Convert: input {error}")).unwrap());
+    }
+
+    let out_format = match input.get_int(key!("format"), 0) {
+      Ok(id) => core.get_video_format_by_id(id as u32),
+      Err(_) => vi.format,
+    };
+
+    if let Err(error) = check_render_format(&out_format) {
+      return Err(CString::new(format!("placebo.Convert: output {error}")).unwrap());
+    }
+
+    if vi.width % (1 << out_format.sub_sampling_w) != 0
+      || vi.height % (1 << out_format.sub_sampling_h) != 0
+    {
+      return Err(
+        CString::new(
+          "placebo.Convert: clip dimensions must be divisible by the output subsampling.",
+        )
+        .unwrap(),
+      );
+    }
+
+    let get_location = |key| match input.get_int(key, 0) {
+      Ok(location) => chroma_location_from_h273(location)
+        .map(Some)
+        .ok_or_else(|| {
+          CString::new(format!(
+            "placebo.Convert: unsupported chroma location {location}."
+          ))
+          .unwrap()
+        }),
+      Err(_) => Ok(None),
+    };
+
+    let chroma_location_in = get_location(key!("chroma_location_in"))?;
+    let chroma_location = get_location(key!("chroma_location"))?;
+
+    let get_scaler = |key, usage| match input.get_utf8(key, 0) {
+      Ok(name) => {
+        let name = CString::new(name).unwrap();
+        match find_filter_config(&name, usage) {
+          Some(_) => Ok(Some(name)),
+          None => Err(
+            CString::new(format!(
+              "placebo.Convert: unknown scaler {}.",
+              name.to_string_lossy()
+            ))
+            .unwrap(),
+          ),
+        }
+      }
+      Err(_) => Ok(None),
+    };
+
+    let chroma_upscaler = get_scaler(
+      key!("chroma_upscaler"),
+      pl_filter_usage::PL_FILTER_UPSCALING,
+    )?;
+    let chroma_downscaler = get_scaler(
+      key!("chroma_downscaler"),
+      pl_filter_usage::PL_FILTER_DOWNSCALING,
+    )?;
+
+    let mut vi_out = vi.clone();
+    vi_out.format = out_format;
+
+    // libplacebo setup.
+
+    // Log references are held by `Renderer` and `Vulkan`.
+    let pl_log = Arc::new(Log::default());
+
+    let vulkan = create_vulkan(&pl_log)
+      .map_err(|error| CString::new(format!("placebo.Convert: {error}")).unwrap())?;
+
+    for (usage, format, kind) in [
+      (TexUsage::Input, &vi.format, "input"),
+      (TexUsage::Output, &out_format, "output"),
+    ] {
+      if let Err(error) = check_gpu_format(&vulkan, format, usage) {
+        return Err(CString::new(format!("placebo.Convert: {kind} {error}")).unwrap());
+      }
+    }
+
+    let open_icc = |name: &str, path: Result<&str, _>| match path {
+      Ok(path) => Icc::from_file(&pl_log, path, None)
+        .map(Some)
+        .map_err(|error| CString::new(format!("placebo.Convert: {name}: {error}")).unwrap()),
+      Err(_) => Ok(None),
+    };
+
+    let icc_in = open_icc("icc_in", input.get_utf8(key!("icc_in"), 0))?;
+    let icc_out = open_icc("icc_out", input.get_utf8(key!("icc_out"), 0))?;
+
+    let mut filter = Self {
+      node,
+      out_format,
+      src_args: get_color_args(&input, true),
+      dst_args: get_color_args(&input, false),
+      chroma_location_in,
+      chroma_location,
+      chroma_upscaler,
+      chroma_downscaler,
+      icc_in,
+      icc_out,
+      stats: (input.get_int(key!("stats"), 0).unwrap_or(0) != 0).then(|| StatsTimer::new(&vulkan)),
+      state: Mutex::new(State {
+        renderer: Renderer::new(&pl_log, &vulkan.gpu()),
+        icc_prop: None,
+      }),
+      pl_log,
+      vulkan,
+    };
+
+    let deps = [FilterDependency {
+      source: filter.node.as_mut_ptr(),
+      request_pattern: RequestPattern::StrictSpatial,
+    }];
+
+    core.create_video_filter(
+      output,
+      cstr!("Convert"),
+      &vi_out,
+      Box::new(filter),
+      Dependencies::new(&deps).unwrap(),
+    );
+
+    Ok(())
+  }
+
+  fn get_frame(
+    &self,
+    n: i32,
+    activation_reason: ActivationReason,
+    _frame_data: *mut *mut c_void,
+    mut ctx: FrameContext,
+    core: CoreRef,
+  ) -> Result<Option<VideoFrame>, Self::Error> {
+    match activation_reason {
+      ActivationReason::Initial => {
+        ctx.request_frame_filter(n, &self.node);
+      }
+      ActivationReason::AllFramesReady => {
+        let src = self.node.get_frame_filter(n, &mut ctx);
+
+        let mut dst = core.new_video_frame(
+          &self.out_format,
+          src.frame_width(0),
+          src.frame_height(0),
+          Some(&src),
+        );
+
+        let mut stats = self
+          .stats
+          .as_ref()
+          .map(|timer| FrameStats::new(&self.vulkan, timer));
+
+        match self.convert_frame(&src, &mut dst, stats.as_mut()) {
+          Ok((colorimetry, location, used_icc)) => {
+            set_props(&mut dst, &colorimetry);
+            if let Some(stats) = &mut stats {
+              stats.finish();
+              stats.set_props(&mut dst);
+            }
+
+            if let Some(mut props) = dst.properties_mut() {
+              match chroma_location_to_h273(location) {
+                Some(location) if colorimetry.repr.sys != pl_color_system::PL_COLOR_SYSTEM_RGB => {
+                  let _ = props.set_int(key!("_ChromaLocation"), location, AppendMode::Replace);
+                }
+                _ => {
+                  props.delete_key(key!("_ChromaLocation"));
+                }
+              }
+
+              // The source profile no longer describes the output.
+              if used_icc {
+                props.delete_key(key!("ICCProfile"));
+              }
+            }
+          }
+          Err(error) => return Err(CString::new(format!("placebo.Convert: {error:?}")).unwrap()),
+        }
+
+        return Ok(Some(dst));
+      }
+      ActivationReason::Error => {}
+    }
+
+    Ok(None)
+  }
+
+  const NAME: &'static CStr = cstr!("Convert");
+  const ARGS: &'static CStr = cstr!(
+    "clip:vnode;\
+    format:int:opt;\
+    matrix:int:opt;\
+    transfer:int:opt;\
+    primaries:int:opt;\
+    range:int:opt;\
+    chroma_location:int:opt;\
+    matrix_in:int:opt;\
+    transfer_in:int:opt;\
+    primaries_in:int:opt;\
+    range_in:int:opt;\
+    chroma_location_in:int:opt;\
+    chroma_upscaler:data:opt;\
+    chroma_downscaler:data:opt;\
+    icc_in:data:opt;\
+    icc_out:data:opt;\
+    stats:int:opt;"
+  );
+  const RETURN_TYPE: &'static CStr = cstr!("clip:vnode;");
+}
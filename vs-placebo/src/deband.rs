@@ -1,14 +1,13 @@
 use const_str::cstr;
 use foreign_types::ForeignType;
 use libplacebo_rs::gpu::Tex;
+use libplacebo_rs::shaders::sampling::SampleSource;
 use libplacebo_rs::shaders_root::ShaderObject;
 use libplacebo_rs::{dispatch::Dispatch, log::Log, vulkan::Vulkan};
 use libplacebo_sys::{
   pl_bit_encoding, pl_color_primaries, pl_color_repr, pl_color_space, pl_color_system,
   pl_color_transfer, pl_deband_params, pl_dispatch_params, pl_dither_method, pl_dither_params,
-  pl_fmt_type, pl_frame, pl_hdr_metadata, pl_plane, pl_plane_data, pl_sample_src,
-  pl_shader_obj_type, pl_shader_params, pl_tex_params, pl_tex_transfer_params, pl_vk_inst_params,
-  pl_vulkan_params, PL_MAX_PLANES,
+  pl_frame, pl_hdr_metadata, pl_plane, pl_shader_params, PL_MAX_PLANES,
 };
 use miette::Result;
 use std::ffi::CString;
@@ -18,7 +17,6 @@ use std::{
 };
 use vapoursynth4_rs::{
   core::CoreRef,
-  ffi::VSSampleType,
   frame::{FrameContext, VideoFrame},
   key,
   map::{MapMut, MapRef},
@@ -29,6 +27,13 @@ use vapoursynth4_rs::{
   utils::bitblt,
 };
 
+use crate::planes::{
+  check_gpu_format, create_output, create_vulkan, destroy_textures, download_plane, upload_plane,
+  Imports, TexUsage,
+};
+use crate::shader_dump::ShaderDump;
+use crate::stats::{finish_pass, FrameStats, StatsTimer};
+
 #[allow(clippy::cast_sign_loss)]
 fn get_planes_arg(planes: MapRef) -> Result<Vec<bool>, String> {
   let m = planes.num_elements(key!("planes")).unwrap_or(-1);
@@ -62,6 +67,12 @@ pub struct Filter {
   /// Indicates whether or not the plane at index `i` should be processed.
   process_planes: Vec<bool>,
 
+  /// Attach GPU statistics to output frames.
+  stats: Option<StatsTimer>,
+
+  /// Where to write generated shaders, if anywhere.
+  shader_dump: Option<ShaderDump>,
+
   dispatch: Dispatch,
   dither_state: ShaderObject,
 
@@ -70,36 +81,6 @@ pub struct Filter {
 }
 
 impl Filter {
-  fn create_textures(&self, data: &pl_plane_data, dst: &VideoFrame) -> (Tex, Tex) {
-    let format = self
-      .vulkan
-      .plane_find_fmt(data)
-      .expect("Failed to find a suitable texture format.");
-
-    let tex_in = self.vulkan.tex_create(&pl_tex_params {
-      w: data.width,
-      h: data.height,
-      format: format.as_ptr(),
-      sampleable: true,
-      host_writable: true,
-      debug_tag: "tex_in".as_ptr().cast(),
-      ..pl_tex_params::default()
-    });
-
-    let plane = data.component_map[0];
-    let tex_out = self.vulkan.tex_create(&pl_tex_params {
-      w: dst.frame_width(plane),
-      h: dst.frame_height(plane),
-      format: format.as_ptr(),
-      renderable: true,
-      host_readable: true,
-      debug_tag: "tex_out".as_ptr().cast(),
-      ..pl_tex_params::default()
-    });
-
-    (tex_in, tex_out)
-  }
-
   #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
   fn deband_frame(
     &self,
@@ -107,9 +88,9 @@ impl Filter {
     src_img: &pl_frame,
     texes_in: &[Tex],
     texes_out: &[Tex],
+    mut stats: Option<&mut FrameStats>,
   ) -> Result<()> {
-    let mut dither_state =
-      ShaderObject::new(self.vulkan.gpu(), pl_shader_obj_type::PL_SHADER_OBJ_DITHER);
+    let mut dither_state = ShaderObject::new();
     let dither_params = pl_dither_params {
       method: pl_dither_method::PL_DITHER_BLUE_NOISE,
       lut_size: 6,
@@ -125,25 +106,28 @@ impl Filter {
         ..pl_shader_params::default()
       });
 
-      let sample_src = pl_sample_src {
-        tex: texes_in[i].as_ptr(),
-        ..pl_sample_src::default()
-      };
-
-      shader.deband(&sample_src, &self.deband_params);
+      shader.deband(&SampleSource::new(&texes_in[i]), &self.deband_params);
 
       // shader.dither(
-      //   texes_out[i].format().component_depth[i],
+      //   texes_out[i].format().component_depth()[0],
       //   // &self.dither_state,
       //   &mut dither_state,
       //   &dither_params,
       // );
 
-      let dispatch_result = self.dispatch.finish(&pl_dispatch_params {
-        target: texes_out[i].as_ptr(),
-        shader: &mut shader.as_ptr(),
-        ..pl_dispatch_params::default()
-      });
+      if let Some(dump) = &self.shader_dump {
+        dump.write("deband", &mut shader)?;
+      }
+
+      let dispatch_result = finish_pass(
+        &self.dispatch,
+        &pl_dispatch_params {
+          target: texes_out[i].as_ptr(),
+          shader: &mut shader.as_ptr(),
+          ..pl_dispatch_params::default()
+        },
+        stats.as_deref_mut(),
+      );
 
       match dispatch_result {
         Ok(()) => {}
@@ -154,37 +138,30 @@ impl Filter {
     Ok(())
   }
 
-  #[allow(clippy::cast_possible_wrap, clippy::cast_sign_loss)]
+  /// Downloads `texes_out` into the planes of `vs_dst` they were rendered
+  /// for.
   fn download_planes(
     &self,
     dst_image: &pl_frame,
     texes_out: &[Tex],
     vs_dst: &mut VideoFrame,
-    plane_data: &[pl_plane_data],
   ) -> Result<()> {
-    for i in 0..dst_image.num_planes as usize {
-      let target_plane = &dst_image.planes[i];
-      let vs_plane = target_plane.component_mapping[0];
-      let out_format = texes_out[i].format();
-      let dst_ptr = vs_dst.plane_mut(vs_plane);
-      let dst_row_pitch: usize = (vs_dst.stride(vs_plane) / plane_data[i].pixel_stride as isize)
-        as usize
-        * out_format.texel_size;
-
-      let download_result = self.vulkan.tex_download(&pl_tex_transfer_params {
-        tex: texes_out[i].as_ptr(),
-        row_pitch: dst_row_pitch,
-        ptr: dst_ptr.cast(),
-        ..pl_tex_transfer_params::default()
+    let mut imports = Imports::default();
+    let result = dst_image.planes[..texes_out.len()]
+      .iter()
+      .zip(texes_out)
+      .try_for_each(|(plane, tex)| {
+        download_plane(
+          &self.vulkan,
+          tex,
+          vs_dst,
+          plane.component_mapping[0],
+          &mut imports,
+        )
       });
 
-      match download_result {
-        Ok(()) => {}
-        Err(error) => return Err(error),
-      }
-    }
-
-    Ok(())
+    imports.release(&self.vulkan);
+    result
   }
 }
 
@@ -218,6 +195,7 @@ impl VsFilter for Filter {
     let threshold = input.get_float(key!("threshold"), 0).unwrap_or(3.0) as f32;
     let radius = input.get_float(key!("radius"), 0).unwrap_or(16.0) as f32;
     let grain = input.get_float(key!("grain"), 0).unwrap_or(4.0) as f32;
+    let stats = input.get_int(key!("stats"), 0).unwrap_or(0) != 0;
 
     let deband_params = pl_deband_params {
       iterations,
@@ -227,6 +205,14 @@ impl VsFilter for Filter {
       ..pl_deband_params::default()
     };
 
+    let shader_dump = match input.get_utf8(key!("debug_shader_dir"), 0) {
+      Ok(dir) => Some(
+        ShaderDump::new(dir)
+          .map_err(|error| CString::new(format!("placebo.Deband: {error:?}")).unwrap())?,
+      ),
+      Err(_) => None,
+    };
+
     let process_planes = get_planes_arg(input).expect("Failed to determine places to process.");
 
     // libplacebo setup.
@@ -234,26 +220,26 @@ impl VsFilter for Filter {
     // Log references are held by `Dispatch` and `Vulkan`.
     let pl_log = Arc::new(Log::default());
 
-    let vulkan = Vulkan::new(
-      &pl_log,
-      &pl_vulkan_params {
-        async_compute: true,
-        async_transfer: true,
-        queue_count: 1,
-        instance_params: &pl_vk_inst_params {
-          debug: true,
-          ..pl_vk_inst_params::default()
-        },
-        ..pl_vulkan_params::default()
-      },
-    );
+    let vulkan = create_vulkan(&pl_log)
+      .map_err(|error| CString::new(format!("placebo.Deband: {error}")).unwrap())?;
+
+    for (usage, format, kind) in [
+      (TexUsage::Input, &vi.format, "input"),
+      (TexUsage::Output, &vi.format, "output"),
+    ] {
+      if let Err(error) = check_gpu_format(&vulkan, format, usage) {
+        return Err(CString::new(format!("placebo.Deband: {kind} {error}")).unwrap());
+      }
+    }
 
     let mut filter = Self {
       node,
       deband_params,
       process_planes,
+      stats: stats.then(|| StatsTimer::new(&vulkan)),
+      shader_dump,
       dispatch: Dispatch::new(&pl_log, &vulkan.gpu()),
-      dither_state: ShaderObject::new(vulkan.gpu(), pl_shader_obj_type::PL_SHADER_OBJ_DITHER),
+      dither_state: ShaderObject::new(),
       // renderer: Renderer::new(&pl_log, &gpu),
       pl_log,
       vulkan,
@@ -318,89 +304,74 @@ impl VsFilter for Filter {
         };
         let mut dst_img = src_img;
 
-        let mut planes_data: Vec<pl_plane_data> = Vec::with_capacity(3);
-        let mut proc_plane_idx: usize = 0;
         let mut texes_in: Vec<Tex> = Vec::with_capacity(PL_MAX_PLANES as usize);
         let mut texes_out: Vec<Tex> = Vec::with_capacity(PL_MAX_PLANES as usize);
+        let mut imports = Imports::default();
+
+        let mut stats = self
+          .stats
+          .as_ref()
+          .map(|timer| FrameStats::new(&self.vulkan, timer));
+
+        let mut process = || -> Result<()> {
+          for plane in 0..format.num_planes {
+            // Skip planes that weren't asked to be processed.
+            if !self.process_planes[plane as usize] {
+              unsafe {
+                // Copy source plane to destination plane.
+                bitblt(
+                  dst.plane_mut(plane).cast(),
+                  dst.stride(plane),
+                  src.plane(plane).cast(),
+                  src.stride(plane),
+                  (dst.frame_width(plane) * format.bytes_per_sample) as usize,
+                  dst.frame_height(plane) as _,
+                );
+              }
+              continue;
+            }
 
-        for plane in 0..format.num_planes {
-          // Skip planes that weren't asked to be processed.
-          if !self.process_planes[plane as usize] {
-            unsafe {
-              // Copy source plane to destination plane.
-              bitblt(
-                dst.plane_mut(plane).cast(),
-                dst.stride(plane),
-                src.plane(plane).cast(),
-                src.stride(plane),
-                (dst.frame_width(plane) * format.bytes_per_sample) as usize,
-                dst.frame_height(plane) as _,
-              );
+            let tex_in = upload_plane(&self.vulkan, &src, plane, &mut imports)?;
+            texes_in.push(tex_in.clone());
+            let tex_out = create_output(
+              &self.vulkan,
+              format,
+              dst.frame_width(plane),
+              dst.frame_height(plane),
+              plane,
+            )?;
+            texes_out.push(tex_out.clone());
+
+            // Add plane to the libplacebo frames.
+            let i = src_img.num_planes as usize;
+            for (img, tex) in [(&mut src_img, &tex_in), (&mut dst_img, &tex_out)] {
+              img.planes[i] = pl_plane {
+                texture: tex.as_ptr(),
+                components: tex.num_components(),
+                component_mapping: [plane, 0, 0, 0],
+                ..pl_plane::default()
+              };
+              img.num_planes += 1;
             }
-            continue;
           }
 
-          // Add plane to the libplacebo frame.
-          src_img.num_planes += 1;
-
-          let data = pl_plane_data {
-            type_: if format.sample_type == VSSampleType::Integer {
-              pl_fmt_type::PL_FMT_UNORM
-            } else {
-              pl_fmt_type::PL_FMT_FLOAT
-            },
-            width: src.frame_width(plane),
-            height: src.frame_height(plane),
-            pixel_stride: format.bytes_per_sample as usize,
-            row_stride: src.stride(plane) as usize,
-            pixels: src.plane(plane).cast(),
-            component_size: [format.bits_per_sample, 0, 0, 0],
-            component_pad: [0; 4],
-            component_map: [plane, 0, 0, 0],
-            ..pl_plane_data::default()
-          };
-
-          planes_data.insert(proc_plane_idx, data);
-
-          let (mut tex_in, tex_out) = self.create_textures(&data, &dst);
-
-          src_img.planes[proc_plane_idx] = self
-            .vulkan
-            .upload_plane(&mut tex_in, &data)
-            .expect("Failed to upload plane.");
-
-          // HACK: `upload_plane()` may have changed the texture pointer.
-          tex_in = unsafe { Tex::new_unchecked(src_img.planes[proc_plane_idx].texture.cast_mut()) };
-
-          dst_img.planes[proc_plane_idx] = pl_plane {
-            texture: tex_out.as_ptr(),
-            components: tex_out.num_components(),
-            component_mapping: [plane, 0, 0, 0],
-            ..pl_plane::default()
-          };
-
-          texes_in.push(tex_in);
-          texes_out.push(tex_out);
-
-          proc_plane_idx += 1;
-        }
+          self.deband_frame(n, &src_img, &texes_in, &texes_out, stats.as_mut())?;
+          self.download_planes(&dst_img, &texes_out, &mut dst)
+        };
 
-        dst_img.num_planes = src_img.num_planes;
+        let result = process();
 
-        let deband_result = self.deband_frame(n, &src_img, &texes_in, &texes_out);
-        match deband_result {
-          Ok(()) => {}
-          Err(error) => return Err(CString::new(format!("{error:?}")).unwrap()),
-        }
+        imports.release(&self.vulkan);
+        destroy_textures(&self.vulkan, &texes_in);
+        destroy_textures(&self.vulkan, &texes_out);
 
-        let download_result = self.download_planes(&dst_img, &texes_out, &mut dst, &planes_data);
-        match download_result {
-          Ok(()) => {}
-          Err(error) => return Err(CString::new(format!("{error:?}")).unwrap()),
+        if let Err(error) = result {
+          return Err(CString::new(format!("{error:?}")).unwrap());
         }
 
-        for tex in texes_in.into_iter().chain(texes_out.into_iter()) {
-          self.vulkan.tex_destroy(&tex);
+        if let Some(stats) = &mut stats {
+          stats.finish();
+          stats.set_props(&mut dst);
         }
 
         return Ok(Some(dst));
@@ -414,7 +385,9 @@ impl VsFilter for Filter {
   const NAME: &'static CStr = cstr!("Deband");
   const ARGS: &'static CStr = cstr!(
     "clip:vnode;\
-    planes:int[]:opt;"
+    planes:int[]:opt;\
+    stats:int:opt;\
+    debug_shader_dir:data:opt;"
   );
   const RETURN_TYPE: &'static CStr = cstr!("clip:vnode;");
 }
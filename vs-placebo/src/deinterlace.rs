@@ -0,0 +1,464 @@
+use const_str::cstr;
+use libplacebo_rs::gpu::{Gpu, Tex};
+use libplacebo_rs::{dispatch::Dispatch, log::Log, vulkan::Vulkan};
+use libplacebo_sys::{
+  pl_deinterlace_algorithm, pl_deinterlace_params, pl_deinterlace_source, pl_dispatch_params,
+  pl_field, pl_shader_params, pl_tex_destroy,
+};
+use miette::{miette, Result};
+use std::ffi::CString;
+use std::{
+  collections::VecDeque,
+  ffi::{c_void, CStr},
+  sync::{Arc, Mutex},
+};
+use vapoursynth4_rs::{
+  core::CoreRef,
+  ffi::VSSampleType,
+  frame::{FrameContext, VideoFrame},
+  key,
+  map::{AppendMode, MapMut, MapRef},
+  node::{
+    ActivationReason, Dependencies, Filter as VsFilter, FilterDependency, Node, RequestPattern,
+    VideoNode,
+  },
+};
+
+use crate::planes::{
+  check_gpu_format, create_output, create_vulkan, destroy_textures, download_planes, upload_plane,
+  Imports, TexUsage,
+};
+use crate::shader_dump::ShaderDump;
+use crate::stats::{finish_pass, FrameStats, StatsTimer};
+
+/// Flips a field parity.
+const fn other_field(field: pl_field) -> pl_field {
+  match field {
+    pl_field::PL_FIELD_EVEN => pl_field::PL_FIELD_ODD,
+    pl_field::PL_FIELD_ODD => pl_field::PL_FIELD_EVEN,
+    other => other,
+  }
+}
+
+/// The planes of a source frame, uploaded once and shared by every output
+/// frame made from it. Destroyed when the last of them is done.
+struct SourceTextures {
+  gpu: Gpu,
+  planes: Vec<Tex>,
+}
+
+unsafe impl Send for SourceTextures {}
+unsafe impl Sync for SourceTextures {}
+
+impl Drop for SourceTextures {
+  fn drop(&mut self) {
+    for tex in &self.planes {
+      unsafe { pl_tex_destroy(self.gpu.as_ptr(), &mut tex.as_ptr()) };
+    }
+  }
+}
+
+/// Recently uploaded source frames. Every source frame is used by up to three
+/// output frames, or six in double-rate mode, which are usually requested
+/// close together.
+#[derive(Default)]
+struct UploadCache(VecDeque<(i32, Arc<SourceTextures>)>);
+
+impl UploadCache {
+  /// Number of source frames kept.
+  const SIZE: usize = 8;
+
+  fn get(&self, number: i32) -> Option<Arc<SourceTextures>> {
+    self
+      .0
+      .iter()
+      .find(|(cached, _)| *cached == number)
+      .map(|(_, texes)| Arc::clone(texes))
+  }
+
+  /// Adds `texes`, evicting the oldest frame if full. A frame uploaded by two
+  /// output frames at once is only kept once.
+  fn insert(&mut self, number: i32, texes: &Arc<SourceTextures>) {
+    if self.get(number).is_some() {
+      return;
+    }
+
+    if self.0.len() == Self::SIZE {
+      self.0.pop_front();
+    }
+    self.0.push_back((number, Arc::clone(texes)));
+  }
+}
+
+pub struct Filter {
+  node: VideoNode,
+
+  /// Number of frames in the source clip.
+  num_frames: i32,
+
+  /// Output both fields of every frame as separate frames.
+  double_rate: bool,
+
+  /// Field order override. `None` reads `_FieldBased` from every frame.
+  first_field: Option<pl_field>,
+
+  deinterlace_params: pl_deinterlace_params,
+
+  /// Attach GPU statistics to output frames.
+  stats: Option<StatsTimer>,
+
+  /// Where to write generated shaders, if anywhere.
+  shader_dump: Option<ShaderDump>,
+
+  /// Must be declared before `vulkan`, which its textures are destroyed
+  /// with.
+  uploads: Mutex<UploadCache>,
+
+  dispatch: Dispatch,
+  vulkan: Vulkan,
+  pl_log: Arc<Log>,
+}
+
+impl Filter {
+  /// Index of the source frame output frame `n` is made from.
+  const fn source_frame(&self, n: i32) -> i32 {
+    if self.double_rate {
+      n / 2
+    } else {
+      n
+    }
+  }
+
+  /// Previous, current and next source frame for output frame `n`, clamped to
+  /// the clip.
+  fn source_frames(&self, n: i32) -> [i32; 3] {
+    let cur = self.source_frame(n);
+    [(cur - 1).max(0), cur, (cur + 1).min(self.num_frames - 1)]
+  }
+
+  /// The first field of `frame`, or `PL_FIELD_NONE` for progressive frames.
+  fn first_field(&self, frame: &VideoFrame) -> pl_field {
+    if let Some(field) = self.first_field {
+      return field;
+    }
+
+    let field_based = frame
+      .properties()
+      .and_then(|props| props.get_int(key!("_FieldBased"), 0).ok());
+
+    match field_based {
+      Some(0) => pl_field::PL_FIELD_NONE,
+      Some(1) => pl_field::PL_FIELD_ODD,
+      _ => pl_field::PL_FIELD_EVEN,
+    }
+  }
+
+  /// Textures of source frame `number`, uploaded from `frame` unless they
+  /// are cached.
+  fn source_textures(
+    &self,
+    number: i32,
+    frame: &VideoFrame,
+    imports: &mut Imports,
+  ) -> Result<Arc<SourceTextures>> {
+    let lock = || {
+      self
+        .uploads
+        .lock()
+        .map_err(|_| miette!("upload cache is poisoned."))
+    };
+
+    if let Some(texes) = lock()?.get(number) {
+      return Ok(texes);
+    }
+
+    let mut texes = SourceTextures {
+      gpu: self.vulkan.as_gpu(),
+      planes: Vec::new(),
+    };
+    for plane in 0..frame.get_video_format().num_planes {
+      texes
+        .planes
+        .push(upload_plane(&self.vulkan, frame, plane, imports)?);
+    }
+
+    let texes = Arc::new(texes);
+    lock()?.insert(number, &texes);
+    Ok(texes)
+  }
+
+  #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+  fn deinterlace_frame(
+    &self,
+    n: i32,
+    sources: [(i32, &VideoFrame); 3],
+    dst: &mut VideoFrame,
+    mut stats: Option<&mut FrameStats>,
+  ) -> Result<()> {
+    let cur = sources[1].1;
+    let first_field = self.first_field(cur);
+    let field = if self.double_rate && n % 2 == 1 {
+      other_field(first_field)
+    } else {
+      first_field
+    };
+
+    let mut texes_out: Vec<Tex> = Vec::new();
+    let mut imports = Imports::default();
+
+    let mut process = || -> Result<()> {
+      let mut texes = Vec::with_capacity(sources.len());
+      for (number, frame) in sources {
+        texes.push(self.source_textures(number, frame, &mut imports)?);
+      }
+      let [prev, cur, next] = [&texes[0], &texes[1], &texes[2]];
+      let planes = prev.planes.iter().zip(&cur.planes).zip(&next.planes);
+
+      for (plane, ((prev, cur), next)) in (0..).zip(planes) {
+        let tex_out = create_output(
+          &self.vulkan,
+          dst.get_video_format(),
+          dst.frame_width(plane),
+          dst.frame_height(plane),
+          plane,
+        )?;
+        texes_out.push(tex_out.clone());
+
+        let mut shader = self.dispatch.begin();
+        shader.reset(&pl_shader_params {
+          gpu: self.vulkan.gpu(),
+          index: n as u8,
+          ..pl_shader_params::default()
+        });
+
+        shader.deinterlace(
+          &pl_deinterlace_source {
+            prev: prev.as_ptr(),
+            cur: cur.as_ptr(),
+            next: next.as_ptr(),
+            field,
+            first_field,
+            ..pl_deinterlace_source::default()
+          },
+          &self.deinterlace_params,
+        );
+
+        if let Some(dump) = &self.shader_dump {
+          dump.write("deinterlace", &mut shader)?;
+        }
+
+        finish_pass(
+          &self.dispatch,
+          &pl_dispatch_params {
+            target: tex_out.as_ptr(),
+            shader: &mut shader.as_ptr(),
+            ..pl_dispatch_params::default()
+          },
+          stats.as_deref_mut(),
+        )?;
+      }
+
+      download_planes(&self.vulkan, &texes_out, dst)
+    };
+
+    let result = process();
+
+    imports.release(&self.vulkan);
+    destroy_textures(&self.vulkan, &texes_out);
+
+    result
+  }
+}
+
+impl VsFilter for Filter {
+  type Error = CString;
+  type FrameType = VideoFrame;
+  type FilterData = ();
+
+  #[allow(clippy::cast_possible_truncation)]
+  fn create<'b>(
+    input: MapRef<'_>,
+    output: MapMut<'_>,
+    _data: Option<Box<Self::FilterData>>,
+    mut core: CoreRef,
+  ) -> Result<(), Self::Error> {
+    let Ok(node) = input.get_video_node(key!("clip"), 0) else {
+      return Err(CString::new("Failed to get clip").unwrap());
+    };
+
+    let n = node.clone();
+    let vi = n.info();
+
+    match (vi.format.sample_type, vi.format.bits_per_sample) {
+      (VSSampleType::Integer, 8..=16) | (VSSampleType::Float, 32) => {}
+      _ => {
+        return Err(
+          CString::new("placebo.Deinterlace: input bit depth must be 8-16 or 32 (float).").unwrap(),
+        )
+      }
+    }
+
+    let algo = match input.get_int(key!("algo"), 0).unwrap_or(2) {
+      0 => pl_deinterlace_algorithm::PL_DEINTERLACE_WEAVE,
+      1 => pl_deinterlace_algorithm::PL_DEINTERLACE_BOB,
+      2 => pl_deinterlace_algorithm::PL_DEINTERLACE_YADIF,
+      3 => pl_deinterlace_algorithm::PL_DEINTERLACE_BWDIF,
+      _ => {
+        return Err(
+          CString::new(
+            "placebo.Deinterlace: algo must be 0 (weave), 1 (bob), 2 (yadif) or 3 (bwdif).",
+          )
+          .unwrap(),
+        )
+      }
+    };
+
+    let first_field = match input.get_int(key!("tff"), 0) {
+      Ok(0) => Some(pl_field::PL_FIELD_ODD),
+      Ok(_) => Some(pl_field::PL_FIELD_EVEN),
+      Err(_) => None,
+    };
+
+    let double_rate = input.get_int(key!("double_rate"), 0).unwrap_or(0) != 0;
+    let skip_spatial_check = input.get_int(key!("skip_spatial_check"), 0).unwrap_or(0) != 0;
+
+    let mut vi_out = vi.clone();
+    if double_rate {
+      vi_out.num_frames *= 2;
+      vi_out.fps_num *= 2;
+    }
+
+    let shader_dump = match input.get_utf8(key!("debug_shader_dir"), 0) {
+      Ok(dir) => Some(
+        ShaderDump::new(dir)
+          .map_err(|error| CString::new(format!("placebo.Deinterlace: {error:?}")).unwrap())?,
+      ),
+      Err(_) => None,
+    };
+
+    // libplacebo setup.
+
+    // Log references are held by `Dispatch` and `Vulkan`.
+    let pl_log = Arc::new(Log::default());
+
+    let vulkan = create_vulkan(&pl_log)
+      .map_err(|error| CString::new(format!("placebo.Deinterlace: {error}")).unwrap())?;
+
+    for (usage, format, kind) in [
+      (TexUsage::Input, &vi.format, "input"),
+      (TexUsage::Output, &vi.format, "output"),
+    ] {
+      if let Err(error) = check_gpu_format(&vulkan, format, usage) {
+        return Err(CString::new(format!("placebo.Deinterlace: {kind} {error}")).unwrap());
+      }
+    }
+
+    let mut filter = Self {
+      node,
+      num_frames: vi.num_frames,
+      double_rate,
+      first_field,
+      deinterlace_params: pl_deinterlace_params {
+        algo,
+        skip_spatial_check,
+      },
+      stats: (input.get_int(key!("stats"), 0).unwrap_or(0) != 0).then(|| StatsTimer::new(&vulkan)),
+      shader_dump,
+      uploads: Mutex::default(),
+      dispatch: Dispatch::new(&pl_log, &vulkan.gpu()),
+      pl_log,
+      vulkan,
+    };
+
+    let deps = [FilterDependency {
+      source: filter.node.as_mut_ptr(),
+      request_pattern: RequestPattern::General,
+    }];
+
+    core.create_video_filter(
+      output,
+      cstr!("Deinterlace"),
+      &vi_out,
+      Box::new(filter),
+      Dependencies::new(&deps).unwrap(),
+    );
+
+    Ok(())
+  }
+
+  fn get_frame(
+    &self,
+    n: i32,
+    activation_reason: ActivationReason,
+    _frame_data: *mut *mut c_void,
+    mut ctx: FrameContext,
+    core: CoreRef,
+  ) -> Result<Option<VideoFrame>, Self::Error> {
+    match activation_reason {
+      ActivationReason::Initial => {
+        let [prev, cur, next] = self.source_frames(n);
+        ctx.request_frame_filter(cur, &self.node);
+        if prev != cur {
+          ctx.request_frame_filter(prev, &self.node);
+        }
+        if next != cur {
+          ctx.request_frame_filter(next, &self.node);
+        }
+      }
+      ActivationReason::AllFramesReady => {
+        let numbers = self.source_frames(n);
+        let [prev, cur, next] = numbers.map(|i| self.node.get_frame_filter(i, &mut ctx));
+
+        let format = cur.get_video_format();
+        let mut dst =
+          core.new_video_frame(format, cur.frame_width(0), cur.frame_height(0), Some(&cur));
+
+        let mut stats = self
+          .stats
+          .as_ref()
+          .map(|timer| FrameStats::new(&self.vulkan, timer));
+
+        if let Err(error) = self.deinterlace_frame(
+          n,
+          [(numbers[0], &prev), (numbers[1], &cur), (numbers[2], &next)],
+          &mut dst,
+          stats.as_mut(),
+        ) {
+          return Err(CString::new(format!("placebo.Deinterlace: {error:?}")).unwrap());
+        }
+
+        if let Some(stats) = &mut stats {
+          stats.finish();
+          stats.set_props(&mut dst);
+        }
+
+        if let Some(mut props) = dst.properties_mut() {
+          let _ = props.set_int(key!("_FieldBased"), 0, AppendMode::Replace);
+
+          if self.double_rate {
+            if let Ok(den) = props.get_int(key!("_DurationDen"), 0) {
+              let _ = props.set_int(key!("_DurationDen"), den * 2, AppendMode::Replace);
+            }
+          }
+        }
+
+        return Ok(Some(dst));
+      }
+      ActivationReason::Error => {}
+    }
+
+    Ok(None)
+  }
+
+  const NAME: &'static CStr = cstr!("Deinterlace");
+  const ARGS: &'static CStr = cstr!(
+    "clip:vnode;\
+    algo:int:opt;\
+    tff:int:opt;\
+    double_rate:int:opt;\
+    skip_spatial_check:int:opt;\
+    stats:int:opt;\
+    debug_shader_dir:data:opt;"
+  );
+  const RETURN_TYPE: &'static CStr = cstr!("clip:vnode;");
+}
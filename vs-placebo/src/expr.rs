@@ -0,0 +1,391 @@
+use const_str::cstr;
+use libplacebo_rs::gpu::Tex;
+use libplacebo_rs::shaders::custom::CustomShader;
+use libplacebo_rs::{dispatch::Dispatch, log::Log, vulkan::Vulkan};
+use libplacebo_sys::{
+  pl_dispatch_params, pl_fmt_type, pl_shader_params, pl_shader_sig, pl_tex_address_mode,
+  pl_tex_sample_mode,
+};
+use miette::Result;
+use std::ffi::CString;
+use std::fmt::Write as _;
+use std::{
+  ffi::{c_void, CStr},
+  sync::Arc,
+};
+use vapoursynth4_rs::{
+  core::CoreRef,
+  frame::{FrameContext, VideoFormat, VideoFrame},
+  key,
+  map::{MapMut, MapRef},
+  node::{
+    ActivationReason, Dependencies, Filter as VsFilter, FilterDependency, Node, RequestPattern,
+    VideoNode,
+  },
+};
+
+use crate::planes::{
+  check_gpu_format, check_render_format, create_output, create_vulkan, destroy_textures,
+  download_planes, planar_format, upload_plane, Imports, TexUsage,
+};
+use crate::shader_dump::ShaderDump;
+use crate::stats::{finish_pass, FrameStats, StatsTimer};
+
+/// Names the input clips go by in expressions, in order.
+const CLIP_NAMES: &str = "xyzabcdefghijklmnopqrstuvw";
+
+/// Factor that maps the texels of a `format` plane to `[0, 1]`. Integer
+/// samples are padded to whole bytes on upload, so e.g. 10-bit samples end up
+/// scaled as if they were 16-bit.
+#[allow(clippy::cast_possible_truncation)]
+fn unorm_scale(format: &VideoFormat) -> Result<f32> {
+  let planar = planar_format(format);
+  if planar.sample_type == pl_fmt_type::PL_FMT_FLOAT {
+    return Ok(1.0);
+  }
+
+  let (_, bits) = planar.plane_data(0, 1, 1)?;
+  Ok(((2f64.powi(bits.sample_depth) - 1.0) / (2f64.powi(bits.color_depth) - 1.0)) as f32)
+}
+
+/// Shader body evaluating `expr` over `num_clips` clips.
+fn expr_body(expr: &str, num_clips: usize) -> String {
+  let mut body = String::from("ivec2 pos = ivec2(gl_FragCoord.xy);\n");
+  for name in CLIP_NAMES.chars().take(num_clips) {
+    let _ = writeln!(
+      body,
+      "float {name} = texelFetch(src_{name}, pos, 0).r * scale_{name};"
+    );
+  }
+  let _ = writeln!(
+    body,
+    "color = vec4(float({expr}) / scale_out, 0.0, 0.0, 1.0);"
+  );
+  body
+}
+
+pub struct Filter {
+  nodes: Vec<VideoNode>,
+  out_format: VideoFormat,
+
+  /// Shader body for every output plane.
+  bodies: Vec<String>,
+
+  /// Texel scale of every input clip, see [`unorm_scale`].
+  scales: Vec<f32>,
+  scale_out: f32,
+
+  /// Attach GPU statistics to output frames.
+  stats: Option<StatsTimer>,
+
+  /// Where to write generated shaders, if anywhere.
+  shader_dump: Option<ShaderDump>,
+
+  dispatch: Dispatch,
+  vulkan: Vulkan,
+  pl_log: Arc<Log>,
+}
+
+impl Filter {
+  /// Renders `plane` of the output from that plane of every clip.
+  #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+  fn expr_plane(
+    &self,
+    n: i32,
+    plane: i32,
+    texes_in: &[Tex],
+    tex_out: &Tex,
+    stats: Option<&mut FrameStats>,
+  ) -> Result<()> {
+    let params = tex_out.params();
+    let mut custom = CustomShader::new(
+      "expr",
+      &self.bodies[plane as usize],
+      pl_shader_sig::PL_SHADER_SIG_NONE,
+      pl_shader_sig::PL_SHADER_SIG_COLOR,
+    )?
+    .output_size(params.w, params.h)
+    .uniform("N", n, true)?
+    .constant("scale_out", self.scale_out, false)?;
+
+    for ((name, tex), &scale) in CLIP_NAMES.chars().zip(texes_in).zip(&self.scales) {
+      custom = custom
+        .texture(
+          &format!("src_{name}"),
+          tex,
+          pl_tex_sample_mode::PL_TEX_SAMPLE_NEAREST,
+          pl_tex_address_mode::PL_TEX_ADDRESS_CLAMP,
+        )?
+        .constant(&format!("scale_{name}"), scale, false)?;
+    }
+
+    let mut shader = self.dispatch.begin();
+    shader.reset(&pl_shader_params {
+      gpu: self.vulkan.gpu(),
+      index: n as u8,
+      ..pl_shader_params::default()
+    });
+
+    custom.apply(&mut shader)?;
+
+    if let Some(dump) = &self.shader_dump {
+      dump.write("expr", &mut shader)?;
+    }
+
+    finish_pass(
+      &self.dispatch,
+      &pl_dispatch_params {
+        target: tex_out.as_ptr(),
+        shader: &mut shader.as_ptr(),
+        ..pl_dispatch_params::default()
+      },
+      stats,
+    )
+  }
+
+  fn expr_frame(
+    &self,
+    n: i32,
+    frames: &[VideoFrame],
+    dst: &mut VideoFrame,
+    mut stats: Option<&mut FrameStats>,
+  ) -> Result<()> {
+    let mut texes_in: Vec<Tex> = Vec::new();
+    let mut texes_out: Vec<Tex> = Vec::new();
+    let mut imports = Imports::default();
+
+    let mut process = || -> Result<()> {
+      for plane in 0..self.out_format.num_planes {
+        let first = texes_in.len();
+        for frame in frames {
+          texes_in.push(upload_plane(&self.vulkan, frame, plane, &mut imports)?);
+        }
+
+        let tex_out = create_output(
+          &self.vulkan,
+          &self.out_format,
+          dst.frame_width(plane),
+          dst.frame_height(plane),
+          plane,
+        )?;
+        texes_out.push(tex_out.clone());
+
+        self.expr_plane(n, plane, &texes_in[first..], &tex_out, stats.as_deref_mut())?;
+      }
+
+      download_planes(&self.vulkan, &texes_out, dst)
+    };
+
+    let result = process();
+
+    imports.release(&self.vulkan);
+    destroy_textures(&self.vulkan, &texes_in);
+    destroy_textures(&self.vulkan, &texes_out);
+
+    result
+  }
+}
+
+impl VsFilter for Filter {
+  type Error = CString;
+  type FrameType = VideoFrame;
+  type FilterData = ();
+
+  #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+  fn create<'b>(
+    input: MapRef<'_>,
+    output: MapMut<'_>,
+    _data: Option<Box<Self::FilterData>>,
+    mut core: CoreRef,
+  ) -> Result<(), Self::Error> {
+    let error = |message: &str| CString::new(format!("placebo.Expr: {message}")).unwrap();
+
+    let num_clips = input.num_elements(key!("clips")).unwrap_or(0);
+    if num_clips < 1 || num_clips as usize > CLIP_NAMES.len() {
+      return Err(error(&format!(
+        "between 1 and {} clips are supported.",
+        CLIP_NAMES.len()
+      )));
+    }
+
+    let nodes: Vec<VideoNode> = (0..num_clips)
+      .map(|i| input.get_video_node(key!("clips"), i))
+      .try_collect()
+      .map_err(|_| error("failed to get clips."))?;
+
+    let vi = nodes[0].info().clone();
+
+    let out_format = match input.get_int(key!("format"), 0) {
+      Ok(id) => core.get_video_format_by_id(id as u32),
+      Err(_) => vi.format,
+    };
+
+    if let Err(message) = check_render_format(&out_format) {
+      return Err(error(&format!("output {message}")));
+    }
+
+    for (name, node) in CLIP_NAMES.chars().zip(&nodes) {
+      let format = &node.info().format;
+      if let Err(message) = check_render_format(format) {
+        return Err(error(&format!("clip {name}: {message}")));
+      }
+
+      if node.info().width != vi.width
+        || node.info().height != vi.height
+        || format.num_planes != out_format.num_planes
+        || format.sub_sampling_w != out_format.sub_sampling_w
+        || format.sub_sampling_h != out_format.sub_sampling_h
+      {
+        return Err(error(&format!(
+          "clip {name} must have the same dimensions, number of planes and subsampling as the output."
+        )));
+      }
+    }
+
+    let num_exprs = input.num_elements(key!("expr")).unwrap_or(0);
+    if num_exprs < 1 {
+      return Err(error("at least one expression is required."));
+    }
+
+    // Planes without an expression reuse the last one, and empty expressions
+    // copy the first clip.
+    let bodies: Vec<String> = (0..out_format.num_planes)
+      .map(|plane| {
+        let expr = input
+          .get_utf8(key!("expr"), plane.min(num_exprs - 1))
+          .unwrap_or_default()
+          .trim();
+        expr_body(if expr.is_empty() { "x" } else { expr }, nodes.len())
+      })
+      .collect();
+
+    let scales: Vec<f32> = nodes
+      .iter()
+      .map(|node| unorm_scale(&node.info().format))
+      .try_collect()
+      .map_err(|message| error(&format!("{message:?}")))?;
+    let scale_out = unorm_scale(&out_format).map_err(|message| error(&format!("{message:?}")))?;
+
+    let stats = input.get_int(key!("stats"), 0).unwrap_or(0) != 0;
+
+    let shader_dump = match input.get_utf8(key!("debug_shader_dir"), 0) {
+      Ok(dir) => Some(ShaderDump::new(dir).map_err(|message| error(&format!("{message:?}")))?),
+      Err(_) => None,
+    };
+
+    let mut vi_out = vi.clone();
+    vi_out.format = out_format;
+
+    // libplacebo setup.
+
+    // Log references are held by `Dispatch` and `Vulkan`.
+    let pl_log = Arc::new(Log::default());
+
+    let vulkan = create_vulkan(&pl_log).map_err(|message| error(&message.to_string()))?;
+
+    for (name, node) in CLIP_NAMES.chars().zip(&nodes) {
+      if let Err(message) = check_gpu_format(&vulkan, &node.info().format, TexUsage::Input) {
+        return Err(error(&format!("clip {name}: {message}")));
+      }
+    }
+
+    if let Err(message) = check_gpu_format(&vulkan, &out_format, TexUsage::Output) {
+      return Err(error(&format!("output {message}")));
+    }
+
+    let mut filter = Self {
+      nodes,
+      out_format,
+      bodies,
+      scales,
+      scale_out,
+      stats: stats.then(|| StatsTimer::new(&vulkan)),
+      shader_dump,
+      dispatch: Dispatch::new(&pl_log, &vulkan.gpu()),
+      pl_log,
+      vulkan,
+    };
+
+    let deps: Vec<FilterDependency> = filter
+      .nodes
+      .iter_mut()
+      .map(|node| FilterDependency {
+        source: node.as_mut_ptr(),
+        request_pattern: RequestPattern::StrictSpatial,
+      })
+      .collect();
+
+    core.create_video_filter(
+      output,
+      cstr!("Expr"),
+      &vi_out,
+      Box::new(filter),
+      Dependencies::new(&deps).unwrap(),
+    );
+
+    Ok(())
+  }
+
+  fn get_frame(
+    &self,
+    n: i32,
+    activation_reason: ActivationReason,
+    _frame_data: *mut *mut c_void,
+    mut ctx: FrameContext,
+    core: CoreRef,
+  ) -> Result<Option<VideoFrame>, Self::Error> {
+    // Shorter clips repeat their last frame.
+    let frame_number = |node: &VideoNode| n.min(node.info().num_frames - 1);
+
+    match activation_reason {
+      ActivationReason::Initial => {
+        for node in &self.nodes {
+          ctx.request_frame_filter(frame_number(node), node);
+        }
+      }
+      ActivationReason::AllFramesReady => {
+        let frames: Vec<VideoFrame> = self
+          .nodes
+          .iter()
+          .map(|node| node.get_frame_filter(frame_number(node), &mut ctx))
+          .collect();
+
+        let mut dst = core.new_video_frame(
+          &self.out_format,
+          frames[0].frame_width(0),
+          frames[0].frame_height(0),
+          Some(&frames[0]),
+        );
+
+        let mut stats = self
+          .stats
+          .as_ref()
+          .map(|timer| FrameStats::new(&self.vulkan, timer));
+
+        if let Err(error) = self.expr_frame(n, &frames, &mut dst, stats.as_mut()) {
+          return Err(CString::new(format!("placebo.Expr: {error:?}")).unwrap());
+        }
+
+        if let Some(stats) = &mut stats {
+          stats.finish();
+          stats.set_props(&mut dst);
+        }
+
+        return Ok(Some(dst));
+      }
+      ActivationReason::Error => {}
+    }
+
+    Ok(None)
+  }
+
+  const NAME: &'static CStr = cstr!("Expr");
+  const ARGS: &'static CStr = cstr!(
+    "clips:vnode[];\
+    expr:data[];\
+    format:int:opt;\
+    stats:int:opt;\
+    debug_shader_dir:data:opt;"
+  );
+  const RETURN_TYPE: &'static CStr = cstr!("clip:vnode;");
+}
@@ -0,0 +1,255 @@
+use const_str::cstr;
+use libplacebo_rs::gpu::Gpu;
+use libplacebo_rs::{log::Log, vulkan::Vulkan};
+use libplacebo_sys::{pl_fmt_caps, pl_gpu_handle_caps, pl_handle_caps, pl_handle_type, PL_API_VER};
+use std::ffi::CString;
+use std::{
+  ffi::{c_void, CStr},
+  sync::Arc,
+};
+use vapoursynth4_rs::{
+  core::CoreRef,
+  frame::{FrameContext, VideoFrame},
+  key,
+  map::{AppendMode, KeyStr, MapMut, MapRef},
+  node::{ActivationReason, Filter as VsFilter},
+};
+
+use crate::planes::create_vulkan;
+
+const FORMAT_CAPS: [(pl_fmt_caps, &str); 11] = [
+  (pl_fmt_caps::PL_FMT_CAP_SAMPLEABLE, "sampleable"),
+  (pl_fmt_caps::PL_FMT_CAP_STORABLE, "storable"),
+  (pl_fmt_caps::PL_FMT_CAP_LINEAR, "linear"),
+  (pl_fmt_caps::PL_FMT_CAP_RENDERABLE, "renderable"),
+  (pl_fmt_caps::PL_FMT_CAP_BLENDABLE, "blendable"),
+  (pl_fmt_caps::PL_FMT_CAP_BLITTABLE, "blittable"),
+  (pl_fmt_caps::PL_FMT_CAP_VERTEX, "vertex"),
+  (pl_fmt_caps::PL_FMT_CAP_TEXEL_UNIFORM, "texel_uniform"),
+  (pl_fmt_caps::PL_FMT_CAP_TEXEL_STORAGE, "texel_storage"),
+  (pl_fmt_caps::PL_FMT_CAP_HOST_READABLE, "host_readable"),
+  (pl_fmt_caps::PL_FMT_CAP_READWRITE, "read_write"),
+];
+
+const HANDLE_TYPES: [(pl_handle_type, &str); 7] = [
+  (pl_handle_type::PL_HANDLE_FD, "fd"),
+  (pl_handle_type::PL_HANDLE_WIN32, "win32"),
+  (pl_handle_type::PL_HANDLE_WIN32_KMT, "win32_kmt"),
+  (pl_handle_type::PL_HANDLE_DMA_BUF, "dma_buf"),
+  (pl_handle_type::PL_HANDLE_HOST_PTR, "host_ptr"),
+  (pl_handle_type::PL_HANDLE_MTL_TEX, "mtl_tex"),
+  (pl_handle_type::PL_HANDLE_IOSURFACE, "iosurface"),
+];
+
+/// Names of the handle types in `caps`, comma-separated.
+fn handle_names(caps: pl_handle_caps) -> String {
+  HANDLE_TYPES
+    .iter()
+    .filter(|(handle, _)| caps & *handle as u64 != 0)
+    .map(|(_, name)| *name)
+    .collect::<Vec<_>>()
+    .join(",")
+}
+
+/// Splits a `VK_MAKE_API_VERSION` version into its major, minor and patch parts.
+const fn vulkan_version(version: u32) -> (u32, u32, u32) {
+  (
+    (version >> 22) & 0x7f,
+    (version >> 12) & 0x3ff,
+    version & 0xfff,
+  )
+}
+
+fn set_int(output: &mut MapMut<'_>, key: &KeyStr, value: i64) {
+  let _ = output.set_int(key, value, AppendMode::Replace);
+}
+
+fn set_ints(output: &mut MapMut<'_>, key: &KeyStr, values: &[i64]) {
+  output.delete_key(key);
+  for &value in values {
+    let _ = output.set_int(key, value, AppendMode::Append);
+  }
+}
+
+fn set_handle_caps(output: &mut MapMut<'_>, keys: [&KeyStr; 3], caps: pl_gpu_handle_caps) {
+  for (key, caps) in keys.into_iter().zip([caps.buf, caps.tex, caps.sync]) {
+    let _ = output.set_utf8(key, &handle_names(caps), AppendMode::Replace);
+  }
+}
+
+/// Reports what the GPU supports, so that problems can be triaged without
+/// access to the machine they happen on.
+#[allow(clippy::cast_possible_wrap)]
+fn report(vulkan: &Vulkan, gpu: Gpu, output: &mut MapMut<'_>) {
+  let (major, minor, patch) = vulkan_version(vulkan.api_version());
+  let _ = output.set_utf8(
+    key!("vulkan_api_version"),
+    &format!("{major}.{minor}.{patch}"),
+    AppendMode::Replace,
+  );
+  set_int(output, key!("api_version"), i64::from(PL_API_VER));
+
+  let glsl = gpu.glsl();
+  set_int(output, key!("glsl_version"), i64::from(glsl.version));
+  set_int(output, key!("glsl_gles"), i64::from(glsl.gles));
+  set_int(output, key!("glsl_vulkan"), i64::from(glsl.vulkan));
+  set_int(output, key!("glsl_compute"), i64::from(glsl.compute));
+  set_int(output, key!("max_shmem_size"), glsl.max_shmem_size as i64);
+  set_int(
+    output,
+    key!("max_group_threads"),
+    i64::from(glsl.max_group_threads),
+  );
+  set_ints(
+    output,
+    key!("max_group_size"),
+    &glsl.max_group_size.map(i64::from),
+  );
+  set_int(output, key!("subgroup_size"), i64::from(glsl.subgroup_size));
+
+  let limits = gpu.limits();
+  set_int(output, key!("thread_safe"), i64::from(limits.thread_safe));
+  set_int(output, key!("callbacks"), i64::from(limits.callbacks));
+  set_int(output, key!("max_buf_size"), limits.max_buf_size as i64);
+  set_int(output, key!("max_ubo_size"), limits.max_ubo_size as i64);
+  set_int(output, key!("max_ssbo_size"), limits.max_ssbo_size as i64);
+  set_int(
+    output,
+    key!("max_mapped_size"),
+    limits.max_mapped_size as i64,
+  );
+  set_int(output, key!("align_host_ptr"), limits.align_host_ptr as i64);
+  set_int(output, key!("host_cached"), i64::from(limits.host_cached));
+  set_int(
+    output,
+    key!("max_tex_1d_dim"),
+    i64::from(limits.max_tex_1d_dim),
+  );
+  set_int(
+    output,
+    key!("max_tex_2d_dim"),
+    i64::from(limits.max_tex_2d_dim),
+  );
+  set_int(
+    output,
+    key!("max_tex_3d_dim"),
+    i64::from(limits.max_tex_3d_dim),
+  );
+  set_int(output, key!("buf_transfer"), i64::from(limits.buf_transfer));
+  set_int(output, key!("max_pushc_size"), limits.max_pushc_size as i64);
+  set_ints(
+    output,
+    key!("max_dispatch"),
+    &limits.max_dispatch.map(i64::from),
+  );
+  set_int(
+    output,
+    key!("fragment_queues"),
+    i64::from(limits.fragment_queues),
+  );
+  set_int(
+    output,
+    key!("compute_queues"),
+    i64::from(limits.compute_queues),
+  );
+
+  set_handle_caps(
+    output,
+    [key!("import_buf"), key!("import_tex"), key!("import_sync")],
+    gpu.import_caps(),
+  );
+  set_handle_caps(
+    output,
+    [key!("export_buf"), key!("export_tex"), key!("export_sync")],
+    gpu.export_caps(),
+  );
+
+  let formats = gpu.formats();
+
+  output.delete_key(key!("formats"));
+  output.delete_key(key!("format_caps"));
+  for format in &formats {
+    let caps = FORMAT_CAPS
+      .iter()
+      .filter(|(cap, _)| format.supports(*cap))
+      .map(|(_, name)| *name)
+      .collect::<Vec<_>>()
+      .join(",");
+
+    let _ = output.set_utf8(
+      key!("formats"),
+      &format.name().to_string_lossy(),
+      AppendMode::Append,
+    );
+    let _ = output.set_utf8(key!("format_caps"), &caps, AppendMode::Append);
+  }
+
+  // Summaries of the above that filters commonly depend on.
+  set_int(output, key!("compute"), i64::from(glsl.compute));
+  set_int(
+    output,
+    key!("storage_images"),
+    i64::from(
+      formats
+        .iter()
+        .any(|format| format.supports(pl_fmt_caps::PL_FMT_CAP_STORABLE)),
+    ),
+  );
+  set_int(
+    output,
+    key!("parallel_compute"),
+    i64::from(glsl.compute && limits.compute_queues > 1),
+  );
+  set_int(
+    output,
+    key!("host_import"),
+    i64::from(gpu.import_caps().buf & pl_handle_type::PL_HANDLE_HOST_PTR as u64 != 0),
+  );
+  set_int(
+    output,
+    key!("host_export"),
+    i64::from(gpu.export_caps().buf & pl_handle_type::PL_HANDLE_HOST_PTR as u64 != 0),
+  );
+}
+
+/// `placebors.Info()`, which returns the limits and capabilities of the GPU the
+/// filters would run on. It never creates a clip, so `get_frame` is unused.
+pub struct Filter;
+
+impl VsFilter for Filter {
+  type Error = CString;
+  type FrameType = VideoFrame;
+  type FilterData = ();
+
+  fn create<'b>(
+    _input: MapRef<'_>,
+    mut output: MapMut<'_>,
+    _data: Option<Box<Self::FilterData>>,
+    _core: CoreRef,
+  ) -> Result<(), Self::Error> {
+    // Dropped in reverse order, so `pl_log` outlives `vulkan`.
+    let pl_log = Arc::new(Log::default());
+
+    let vulkan = create_vulkan(&pl_log)
+      .map_err(|error| CString::new(format!("placebo.Info: {error}")).unwrap())?;
+
+    report(&vulkan, vulkan.as_gpu(), &mut output);
+
+    Ok(())
+  }
+
+  fn get_frame(
+    &self,
+    _n: i32,
+    _activation_reason: ActivationReason,
+    _frame_data: *mut *mut c_void,
+    _ctx: FrameContext,
+    _core: CoreRef,
+  ) -> Result<Option<VideoFrame>, Self::Error> {
+    Ok(None)
+  }
+
+  const NAME: &'static CStr = cstr!("Info");
+  const ARGS: &'static CStr = cstr!("");
+  const RETURN_TYPE: &'static CStr = cstr!("any");
+}
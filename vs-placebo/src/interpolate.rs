@@ -0,0 +1,673 @@
+use const_str::cstr;
+use libplacebo_rs::filters::find_filter_config;
+use libplacebo_rs::gpu::{Gpu, Tex};
+use libplacebo_rs::renderer::{frame_mix_radius, Renderer};
+use libplacebo_rs::utils::frame_queue::Queue;
+use libplacebo_rs::utils::upload::upload_plane;
+use libplacebo_rs::{log::Log, vulkan::Vulkan};
+use libplacebo_sys::{
+  pl_chroma_location, pl_filter_usage, pl_frame, pl_gpu, pl_queue_params, pl_render_default_params,
+  pl_render_params, pl_source_frame, pl_tex,
+};
+use miette::{miette, Result};
+use std::ffi::CString;
+use std::ptr::null;
+use std::sync::Mutex;
+use std::{
+  ffi::{c_void, CStr},
+  sync::Arc,
+};
+use vapoursynth4_rs::{
+  core::CoreRef,
+  frame::{FrameContext, VideoFrame},
+  key,
+  map::{AppendMode, MapMut, MapRef},
+  node::{
+    ActivationReason, Dependencies, Filter as VsFilter, FilterDependency, Node, RequestPattern,
+    VideoNode,
+  },
+};
+
+use crate::color::{chroma_location, resolve_source, ColorArgs, Colorimetry};
+use crate::planes::{
+  check_gpu_format, check_render_format, create_output, create_vulkan, destroy_textures,
+  download_planes, frame_from_planes, frame_plane_data, set_chroma_location, TexUsage,
+};
+use crate::stats::{FrameStats, StatsTimer};
+
+const fn gcd(a: i64, b: i64) -> i64 {
+  if b == 0 {
+    a
+  } else {
+    gcd(b, a % b)
+  }
+}
+
+/// Reduces a frame rate to lowest terms.
+const fn reduce(num: i64, den: i64) -> (i64, i64) {
+  let d = gcd(num, den);
+  (num / d, den / d)
+}
+
+/// Duration of `frame` in seconds, from its `_DurationNum`/`_DurationDen`
+/// properties.
+#[allow(clippy::cast_precision_loss)]
+fn frame_duration(frame: &VideoFrame) -> Option<f64> {
+  let props = frame.properties()?;
+  let num = props.get_int(key!("_DurationNum"), 0).ok()?;
+  let den = props.get_int(key!("_DurationDen"), 0).ok()?;
+  (num > 0 && den > 0).then(|| num as f64 / den as f64)
+}
+
+/// A VapourSynth frame handed to the queue through `pl_source_frame.frame_data`.
+/// The queue owns it from then on: it is freed once the frame has been
+/// uploaded by [`map_frame`], or by [`discard_frame`] if it never is.
+struct SourceFrame {
+  frame: VideoFrame,
+  colorimetry: Colorimetry,
+  chroma_location: pl_chroma_location,
+}
+
+/// `pl_source_frame.map` callback, uploading a [`SourceFrame`] into the
+/// textures owned by the queue.
+#[allow(clippy::cast_sign_loss)]
+unsafe extern "C" fn map_frame(
+  gpu: pl_gpu,
+  tex: *mut pl_tex,
+  src: *const pl_source_frame,
+  out_frame: *mut pl_frame,
+) -> bool {
+  // The planes are copied into the queue's textures, so the frame is not
+  // needed past this call.
+  let source = unsafe { Box::from_raw((*src).frame_data.cast::<SourceFrame>()) };
+  let out_frame = unsafe { &mut *out_frame };
+  let num_planes = source.frame.get_video_format().num_planes;
+
+  *out_frame = pl_frame {
+    num_planes,
+    repr: source.colorimetry.repr,
+    color: source.colorimetry.color,
+    ..pl_frame::default()
+  };
+
+  let gpu = unsafe { Gpu::new_unchecked(gpu.cast_mut()) };
+  let uploaded = (0..num_planes).all(|plane| {
+    // The queue owns the texture slots, which are reused between frames.
+    let slot = unsafe { &mut *tex.add(plane as usize) };
+    let mut plane_tex = (!slot.is_null()).then(|| unsafe { Tex::new_unchecked(slot.cast_mut()) });
+
+    let result = frame_plane_data(&source.frame, plane)
+      .and_then(|data| upload_plane(gpu, &mut plane_tex, &data));
+    *slot = plane_tex.map_or(null(), |tex| tex.as_ptr());
+
+    result.is_ok_and(|uploaded| {
+      out_frame.planes[plane as usize] = uploaded;
+      true
+    })
+  });
+
+  set_chroma_location(out_frame, source.chroma_location);
+  uploaded
+}
+
+/// `pl_source_frame.discard` callback, freeing a [`SourceFrame`] the queue
+/// dropped without mapping it.
+unsafe extern "C" fn discard_frame(src: *const pl_source_frame) {
+  drop(unsafe { Box::from_raw((*src).frame_data.cast::<SourceFrame>()) });
+}
+
+/// Start times of source frames, accumulated from their durations as they are
+/// seen. Frames past the last one seen are assumed to last `fallback`.
+struct Timeline {
+  /// Start time of every frame seen so far, followed by the end of the last
+  /// one.
+  starts: Vec<f64>,
+
+  /// Duration of frames that have not been seen yet or lack
+  /// `_DurationNum`/`_DurationDen`.
+  fallback: f64,
+}
+
+impl Timeline {
+  fn new(fallback: f64) -> Self {
+    Self {
+      starts: vec![0.0],
+      fallback,
+    }
+  }
+
+  /// The last known start time, and the index of the frame it belongs to.
+  #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+  fn last_start(&self) -> (i32, f64) {
+    (
+      self.starts.len() as i32 - 1,
+      self.starts[self.starts.len() - 1],
+    )
+  }
+
+  /// Start time of frame `index`, in seconds.
+  #[allow(clippy::cast_sign_loss)]
+  fn start(&self, index: i32) -> f64 {
+    let (last, start) = self.last_start();
+    if index < last {
+      self.starts[index as usize]
+    } else {
+      f64::from(index - last).mul_add(self.fallback, start)
+    }
+  }
+
+  /// Index of the frame being displayed at `pts`. Negative before the first
+  /// frame.
+  #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+  fn frame_at(&self, pts: f64) -> i32 {
+    let (last, start) = self.last_start();
+    if pts < 0.0 {
+      -1
+    } else if pts < start {
+      self.starts.partition_point(|&s| s <= pts) as i32 - 1
+    } else {
+      last + ((pts - start) / self.fallback).floor() as i32
+    }
+  }
+
+  /// Records the duration of frame `index`, if it directly follows the frames
+  /// seen so far.
+  fn record(&mut self, index: i32, duration: f64) {
+    let (last, start) = self.last_start();
+    if index == last {
+      self.starts.push(start + duration);
+    }
+  }
+}
+
+/// Rendering state shared between frames. Neither the renderer nor the queue
+/// can be used for more than one output frame at a time.
+struct State {
+  queue: Queue,
+  renderer: Renderer,
+
+  /// Index of the next source frame to push to the queue.
+  next: i32,
+
+  /// Timestamp of the last output frame rendered.
+  pts: f64,
+
+  /// Whether EOF was pushed to the queue.
+  eof: bool,
+}
+
+impl State {
+  /// Empties the queue, so that pushing can start over at source frame
+  /// `first`.
+  fn reset(&mut self, first: i32) {
+    // A fresh queue reuses frame signatures, so cached renders of old frames
+    // must go as well.
+    self.queue.reset();
+    self.renderer.flush_cache();
+    self.next = first;
+    self.eof = false;
+  }
+}
+
+pub struct Filter {
+  node: VideoNode,
+
+  /// Number of frames in the source clip.
+  num_frames: i32,
+
+  /// Start times of the source frames.
+  timeline: Mutex<Timeline>,
+
+  /// Output frame rate.
+  fps: (i64, i64),
+
+  /// Name of the `pl_filter_config` used to mix frames.
+  frame_mixer: CString,
+
+  /// Radius of the frame mixer, in output frames.
+  radius: f32,
+
+  state: Mutex<State>,
+
+  /// Attach GPU statistics to output frames.
+  stats: Option<StatsTimer>,
+
+  vulkan: Vulkan,
+  pl_log: Arc<Log>,
+}
+
+impl Filter {
+  /// Duration of an output frame, in seconds.
+  #[allow(clippy::cast_precision_loss)]
+  fn vsync_duration(&self) -> f64 {
+    self.fps.1 as f64 / self.fps.0 as f64
+  }
+
+  /// Presentation timestamp of output frame `n`, in seconds.
+  fn output_pts(&self, n: i32) -> f64 {
+    f64::from(n) * self.vsync_duration()
+  }
+
+  /// First and last source frame the frame mixer may need for output frame
+  /// `n`. One extra frame is included on each side, so the queue can tell
+  /// where the surrounding frames begin and end.
+  fn source_range(&self, n: i32) -> Result<(i32, i32)> {
+    let pts = self.output_pts(n);
+    let reach = f64::from(self.radius) * self.vsync_duration();
+    let timeline = self
+      .timeline
+      .lock()
+      .map_err(|_| miette!("timeline is poisoned."))?;
+
+    let last = (timeline.frame_at(pts + reach) + 1).clamp(0, self.num_frames - 1);
+    let first = (timeline.frame_at(pts - reach) - 1).clamp(0, last);
+    Ok((first, last))
+  }
+
+  fn render_params(&self) -> Result<pl_render_params> {
+    let frame_mixer =
+      find_filter_config(&self.frame_mixer, pl_filter_usage::PL_FILTER_FRAME_MIXING)
+        .ok_or_else(|| miette!("unknown frame mixer."))?;
+
+    Ok(pl_render_params {
+      frame_mixer,
+      ..unsafe { pl_render_default_params }
+    })
+  }
+
+  /// Adds the durations of source frames `first..` to the timeline and
+  /// returns the index into `frames` of the one displayed at output frame `n`.
+  #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+  fn record_frames(&self, n: i32, first: i32, frames: &[VideoFrame]) -> Result<usize> {
+    let mut timeline = self
+      .timeline
+      .lock()
+      .map_err(|_| miette!("timeline is poisoned."))?;
+
+    for (i, frame) in (first..).zip(frames) {
+      let duration = frame_duration(frame).unwrap_or(timeline.fallback);
+      timeline.record(i, duration);
+    }
+
+    let last = first + frames.len() as i32 - 1;
+    Ok((timeline.frame_at(self.output_pts(n)).clamp(first, last) - first) as usize)
+  }
+
+  /// Renders output frame `n` from source frames `first..`, pushing the ones
+  /// the queue has not seen yet. `frames[reference]` is the frame being
+  /// displayed.
+  #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+  fn interpolate_frame(
+    &self,
+    n: i32,
+    first: i32,
+    frames: Vec<VideoFrame>,
+    reference: usize,
+    dst: &mut VideoFrame,
+    mut stats: Option<&mut FrameStats>,
+  ) -> Result<()> {
+    let pts = self.output_pts(n);
+
+    let mut sources: Vec<SourceFrame> = frames
+      .into_iter()
+      .map(|frame| -> Result<SourceFrame> {
+        let props = frame.properties();
+        let colorimetry = resolve_source(
+          &ColorArgs::default(),
+          frame.get_video_format(),
+          props.as_ref(),
+        )?;
+        let chroma_location = chroma_location(props.as_ref());
+        drop(props);
+
+        Ok(SourceFrame {
+          frame,
+          colorimetry,
+          chroma_location,
+        })
+      })
+      .try_collect()?;
+
+    // Render in the colorimetry of the frame being displayed, so that any
+    // differing neighbours are converted to it.
+    let colorimetry = sources[reference].colorimetry;
+    let location = sources[reference].chroma_location;
+
+    let num_planes = dst.get_video_format().num_planes;
+    let mut texes_out: Vec<Tex> = Vec::with_capacity(num_planes as usize);
+
+    let mut process = || -> Result<()> {
+      for plane in 0..num_planes {
+        texes_out.push(create_output(
+          &self.vulkan,
+          dst.get_video_format(),
+          dst.frame_width(plane),
+          dst.frame_height(plane),
+          plane,
+        )?);
+      }
+
+      let target = frame_from_planes(
+        dst.get_video_format(),
+        &texes_out,
+        &colorimetry,
+        location,
+        None,
+      )?;
+
+      let mut params = self.render_params()?;
+      if let Some(stats) = stats.as_deref_mut() {
+        stats.attach(&mut params);
+      }
+
+      {
+        let mut state = self
+          .state
+          .lock()
+          .map_err(|_| miette!("renderer is poisoned."))?;
+
+        // The queue only moves forward, and can't skip over frames it was
+        // never given.
+        if pts < state.pts || first > state.next {
+          state.reset(first);
+        }
+        state.pts = pts;
+
+        let timeline = self
+          .timeline
+          .lock()
+          .map_err(|_| miette!("timeline is poisoned."))?;
+
+        for (i, source) in (first..).zip(std::mem::take(&mut sources)) {
+          if i < state.next {
+            continue;
+          }
+
+          let duration = frame_duration(&source.frame).unwrap_or(timeline.fallback);
+          state.queue.push(&pl_source_frame {
+            pts: timeline.start(i),
+            duration: duration as f32,
+            frame_data: Box::into_raw(Box::new(source)).cast(),
+            map: Some(map_frame),
+            discard: Some(discard_frame),
+            ..pl_source_frame::default()
+          });
+          state.next = i + 1;
+        }
+        drop(timeline);
+
+        if state.next >= self.num_frames && !state.eof {
+          state.queue.push_eof();
+          state.eof = true;
+        }
+
+        let mix = state.queue.update(&pl_queue_params {
+          pts,
+          radius: self.radius,
+          vsync_duration: self.vsync_duration() as f32,
+          ..pl_queue_params::default()
+        })?;
+
+        if mix.num_frames == 0 {
+          return Err(miette!("no source frames to mix."));
+        }
+
+        state.renderer.render_image_mix(&mix, &target, &params)?;
+      }
+
+      download_planes(&self.vulkan, &texes_out, dst)
+    };
+
+    let result = process();
+
+    destroy_textures(&self.vulkan, &texes_out);
+
+    result
+  }
+}
+
+impl VsFilter for Filter {
+  type Error = CString;
+  type FrameType = VideoFrame;
+  type FilterData = ();
+
+  #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+  fn create<'b>(
+    input: MapRef<'_>,
+    output: MapMut<'_>,
+    _data: Option<Box<Self::FilterData>>,
+    mut core: CoreRef,
+  ) -> Result<(), Self::Error> {
+    let Ok(node) = input.get_video_node(key!("clip"), 0) else {
+      return Err(CString::new("Failed to get clip").unwrap());
+    };
+
+    let n = node.clone();
+    let vi = n.info();
+
+    if let Err(error) = check_render_format(&vi.format) {
+      return Err(CString::new(format!("placebo.Interpolate: input {error}")).unwrap());
+    }
+
+    let fps_num = input.get_int(key!("fpsnum"), 0).unwrap_or(0);
+    let fps_den = input.get_int(key!("fpsden"), 0).unwrap_or(1);
+    if fps_num <= 0 || fps_den <= 0 {
+      return Err(
+        CString::new("placebo.Interpolate: fpsnum and fpsden must be positive.").unwrap(),
+      );
+    }
+    let fps = reduce(fps_num, fps_den);
+
+    let frame_mixer = CString::new(
+      input
+        .get_utf8(key!("frame_mixer"), 0)
+        .unwrap_or("oversample"),
+    )
+    .unwrap();
+    let Some(mixer_config) =
+      find_filter_config(&frame_mixer, pl_filter_usage::PL_FILTER_FRAME_MIXING)
+    else {
+      return Err(
+        CString::new(format!(
+          "placebo.Interpolate: unknown frame_mixer {}.",
+          frame_mixer.to_string_lossy()
+        ))
+        .unwrap(),
+      );
+    };
+
+    let radius = frame_mix_radius(&pl_render_params {
+      frame_mixer: mixer_config,
+      ..unsafe { pl_render_default_params }
+    });
+
+    // Output frames needed to cover the whole source clip. The length of a
+    // variable frame rate clip is unknown until all of its frames are seen,
+    // so it keeps its number of frames.
+    let num_frames = if vi.fps_num > 0 && vi.fps_den > 0 {
+      let num = i64::from(vi.num_frames) * vi.fps_den * fps.0;
+      let den = vi.fps_num * fps.1;
+      (num + den - 1) / den
+    } else {
+      i64::from(vi.num_frames)
+    };
+    let Ok(num_frames) = i32::try_from(num_frames) else {
+      return Err(CString::new("placebo.Interpolate: output clip is too long.").unwrap());
+    };
+
+    let mut vi_out = vi.clone();
+    vi_out.fps_num = fps.0;
+    vi_out.fps_den = fps.1;
+    vi_out.num_frames = num_frames.max(1);
+
+    // libplacebo setup.
+
+    // Log references are held by `Renderer` and `Vulkan`.
+    let pl_log = Arc::new(Log::default());
+
+    let vulkan = create_vulkan(&pl_log)
+      .map_err(|error| CString::new(format!("placebo.Interpolate: {error}")).unwrap())?;
+
+    for (usage, format, kind) in [
+      (TexUsage::Input, &vi.format, "input"),
+      (TexUsage::Output, &vi.format, "output"),
+    ] {
+      if let Err(error) = check_gpu_format(&vulkan, format, usage) {
+        return Err(CString::new(format!("placebo.Interpolate: {kind} {error}")).unwrap());
+      }
+    }
+
+    // Frames without `_DurationNum`/`_DurationDen` last as long as the clip
+    // frame rate says, or as long as an output frame if it has none.
+    let (duration_num, duration_den) = if vi.fps_num > 0 && vi.fps_den > 0 {
+      (vi.fps_den, vi.fps_num)
+    } else {
+      (fps.1, fps.0)
+    };
+
+    let mut filter = Self {
+      node,
+      num_frames: vi.num_frames,
+      timeline: Mutex::new(Timeline::new(duration_num as f64 / duration_den as f64)),
+      fps,
+      frame_mixer,
+      radius,
+      state: Mutex::new(State {
+        queue: Queue::new(&vulkan.gpu()),
+        renderer: Renderer::new(&pl_log, &vulkan.gpu()),
+        next: 0,
+        pts: 0.0,
+        eof: false,
+      }),
+      stats: (input.get_int(key!("stats"), 0).unwrap_or(0) != 0).then(|| StatsTimer::new(&vulkan)),
+      pl_log,
+      vulkan,
+    };
+
+    let deps = [FilterDependency {
+      source: filter.node.as_mut_ptr(),
+      request_pattern: RequestPattern::General,
+    }];
+
+    core.create_video_filter(
+      output,
+      cstr!("Interpolate"),
+      &vi_out,
+      Box::new(filter),
+      Dependencies::new(&deps).unwrap(),
+    );
+
+    Ok(())
+  }
+
+  fn get_frame(
+    &self,
+    n: i32,
+    activation_reason: ActivationReason,
+    frame_data: *mut *mut c_void,
+    mut ctx: FrameContext,
+    core: CoreRef,
+  ) -> Result<Option<VideoFrame>, Self::Error> {
+    match activation_reason {
+      ActivationReason::Initial => {
+        let (first, last) = self
+          .source_range(n)
+          .map_err(|error| CString::new(format!("placebo.Interpolate: {error:?}")).unwrap())?;
+        for i in first..=last {
+          ctx.request_frame_filter(i, &self.node);
+        }
+
+        // The timeline may have changed by the time the frames are ready, so
+        // the requested range is kept for then.
+        unsafe { *frame_data = Box::into_raw(Box::new((first, last))).cast() };
+      }
+      ActivationReason::AllFramesReady => {
+        let (first, last) = *unsafe { Box::from_raw((*frame_data).cast::<(i32, i32)>()) };
+        let frames: Vec<VideoFrame> = (first..=last)
+          .map(|i| self.node.get_frame_filter(i, &mut ctx))
+          .collect();
+
+        let result = self.record_frames(n, first, &frames).and_then(|reference| {
+          let src = &frames[reference];
+          let format = *src.get_video_format();
+          let mut dst =
+            core.new_video_frame(&format, src.frame_width(0), src.frame_height(0), Some(src));
+
+          let mut stats = self
+            .stats
+            .as_ref()
+            .map(|timer| FrameStats::new(&self.vulkan, timer));
+          self.interpolate_frame(n, first, frames, reference, &mut dst, stats.as_mut())?;
+          Ok((dst, stats))
+        });
+
+        let (mut dst, mut stats) = result
+          .map_err(|error| CString::new(format!("placebo.Interpolate: {error:?}")).unwrap())?;
+
+        if let Some(stats) = &mut stats {
+          stats.finish();
+          stats.set_props(&mut dst);
+        }
+
+        if let Some(mut props) = dst.properties_mut() {
+          let _ = props.set_int(key!("_DurationNum"), self.fps.1, AppendMode::Replace);
+          let _ = props.set_int(key!("_DurationDen"), self.fps.0, AppendMode::Replace);
+        }
+
+        return Ok(Some(dst));
+      }
+      ActivationReason::Error => {
+        if unsafe { !(*frame_data).is_null() } {
+          drop(unsafe { Box::from_raw((*frame_data).cast::<(i32, i32)>()) });
+        }
+      }
+    }
+
+    Ok(None)
+  }
+
+  const NAME: &'static CStr = cstr!("Interpolate");
+  const ARGS: &'static CStr = cstr!(
+    "clip:vnode;\
+    fpsnum:int;\
+    fpsden:int:opt;\
+    frame_mixer:data:opt;\
+    stats:int:opt;"
+  );
+  const RETURN_TYPE: &'static CStr = cstr!("clip:vnode;");
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn accumulates_frame_durations() {
+    let mut timeline = Timeline::new(0.5);
+    for (i, duration) in [1.0, 2.0, 0.25].into_iter().enumerate() {
+      timeline.record(i32::try_from(i).unwrap(), duration);
+    }
+
+    // Frames out of order don't extend the timeline.
+    timeline.record(5, 10.0);
+
+    let starts: Vec<f64> = (0..6).map(|i| timeline.start(i)).collect();
+    assert_eq!(starts, [0.0, 1.0, 3.0, 3.25, 3.75, 4.25]);
+
+    timeline.record(1, 10.0);
+    assert_eq!(timeline.start(2), 3.0);
+  }
+
+  #[test]
+  fn finds_displayed_frames() {
+    let mut timeline = Timeline::new(0.5);
+    timeline.record(0, 1.0);
+    timeline.record(1, 2.0);
+
+    let frames: Vec<i32> = [-0.1, 0.0, 0.9, 1.0, 2.9, 3.0, 3.4, 3.5]
+      .into_iter()
+      .map(|pts| timeline.frame_at(pts))
+      .collect();
+    assert_eq!(frames, [-1, 0, 0, 1, 1, 2, 2, 3]);
+  }
+}
@@ -2,9 +2,29 @@
 #![allow(clippy::too_many_lines)]
 #![feature(iterator_try_collect)]
 
+mod color;
+mod color_convert;
+mod convert;
 mod deband;
+mod deinterlace;
+mod expr;
+mod info;
+mod interpolate;
+mod lut;
+mod planes;
+mod shader_dump;
+mod stats;
+#[cfg(test)]
+mod testing;
 
+use crate::color_convert::Filter as ColorConvertFilter;
+use crate::convert::Filter as ConvertFilter;
 use crate::deband::Filter as DebandFilter;
+use crate::deinterlace::Filter as DeinterlaceFilter;
+use crate::expr::Filter as ExprFilter;
+use crate::info::Filter as InfoFilter;
+use crate::interpolate::Filter as InterpolateFilter;
+use crate::lut::Filter as LutFilter;
 use const_str::cstr;
 use vapoursynth4_rs::declare_plugin;
 
@@ -15,5 +35,12 @@ declare_plugin!(
   (1, 0),
   VAPOURSYNTH_API_VERSION,
   0,
-  (DebandFilter, None)
+  (DebandFilter, None),
+  (ColorConvertFilter, None),
+  (LutFilter, None),
+  (DeinterlaceFilter, None),
+  (InterpolateFilter, None),
+  (ConvertFilter, None),
+  (InfoFilter, None),
+  (ExprFilter, None)
 );
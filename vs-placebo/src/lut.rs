@@ -0,0 +1,526 @@
+use const_str::cstr;
+use libplacebo_rs::gpu::Tex;
+use libplacebo_rs::shaders::lut::{CubeDomain, CustomLut};
+use libplacebo_rs::shaders_root::{Shader, ShaderObject};
+use libplacebo_rs::{dispatch::Dispatch, log::Log, vulkan::Vulkan};
+use libplacebo_sys::{
+  pl_color_levels, pl_color_primaries, pl_color_system, pl_color_transfer, pl_custom_shader,
+  pl_desc, pl_desc_binding, pl_desc_type, pl_dispatch_params, pl_fmt_caps, pl_fmt_type,
+  pl_lut_type, pl_shader_desc, pl_shader_params, pl_shader_sig, pl_tex_address_mode, pl_tex_params,
+  pl_tex_sample_mode, pl_tex_transfer_params,
+};
+use miette::{miette, Result};
+use std::ffi::CString;
+use std::sync::Mutex;
+use std::{
+  ffi::{c_void, CStr},
+  sync::Arc,
+};
+use vapoursynth4_rs::{
+  core::CoreRef,
+  frame::{FrameContext, VideoFrame},
+  key,
+  map::{MapMut, MapRef},
+  node::{
+    ActivationReason, Dependencies, Filter as VsFilter, FilterDependency, Node, RequestPattern,
+    VideoNode,
+  },
+};
+
+use crate::color::{resolve_source, set_props, ColorArgs, Colorimetry};
+use crate::planes::{
+  check_format, check_gpu_format, create_vulkan, destroy_textures, download_planes, gather_planes,
+  select_plane, upload_planes, Imports, TexUsage,
+};
+use crate::shader_dump::ShaderDump;
+use crate::stats::{finish_pass, FrameStats, StatsTimer};
+
+/// How 3D LUT entries are interpolated.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Interpolation {
+  /// Handled by `pl_shader_custom_lut()`.
+  Tetrahedral,
+
+  /// Hardware linear filtering of the LUT, uploaded as a 3D texture.
+  Trilinear,
+}
+
+/// Uploads a 3D LUT as an RGBA texture that can be linearly sampled.
+#[allow(clippy::cast_sign_loss)]
+fn upload_lut_texture(vulkan: &Vulkan, lut: &CustomLut) -> Result<Tex> {
+  let [w, h, d] = lut.size();
+
+  let format = vulkan
+    .find_fmt(
+      pl_fmt_type::PL_FMT_FLOAT,
+      4,
+      16,
+      32,
+      pl_fmt_caps::PL_FMT_CAP_SAMPLEABLE | pl_fmt_caps::PL_FMT_CAP_LINEAR,
+    )
+    .ok_or_else(|| miette!("GPU does not support linearly sampled float textures."))?;
+
+  let rgba: Vec<f32> = lut
+    .data()
+    .chunks_exact(3)
+    .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 1.0])
+    .collect();
+
+  let tex = vulkan.tex_create(&pl_tex_params {
+    w,
+    h,
+    d,
+    format: format.as_ptr(),
+    sampleable: true,
+    host_writable: true,
+    debug_tag: c"tex_lut".as_ptr(),
+    ..pl_tex_params::default()
+  });
+
+  let row_pitch = w as usize * 4 * std::mem::size_of::<f32>();
+  let result = vulkan.tex_upload(&pl_tex_transfer_params {
+    tex: tex.as_ptr(),
+    row_pitch,
+    depth_pitch: row_pitch * h as usize,
+    ptr: rgba.as_ptr().cast_mut().cast(),
+    ..pl_tex_transfer_params::default()
+  });
+
+  match result {
+    Ok(()) => Ok(tex),
+    Err(error) => {
+      vulkan.tex_destroy(&tex);
+      Err(error)
+    }
+  }
+}
+
+/// GLSL sampling the texture bound as `lut` at the center of each entry,
+/// after mapping the LUT's `domain` to 0.0 to 1.0.
+fn trilinear_body(size: [i32; 3], domain: &CubeDomain) -> CString {
+  let [r, g, b] = size.map(f64::from);
+  let vec3 = |v: [f32; 3]| format!("vec3({:?}, {:?}, {:?})", v[0], v[1], v[2]);
+  CString::new(format!(
+    "vec3 lut_size = vec3({r:.1}, {g:.1}, {b:.1});\n\
+    vec3 domain_min = {};\n\
+    vec3 domain_max = {};\n\
+    vec3 lut_pos = clamp((color.rgb - domain_min) / (domain_max - domain_min), 0.0, 1.0);\n\
+    lut_pos = lut_pos * (lut_size - 1.0) / lut_size + 0.5 / lut_size;\n\
+    color.rgb = texture(lut, lut_pos).rgb;\n",
+    vec3(domain.min),
+    vec3(domain.max),
+  ))
+  .unwrap()
+}
+
+/// Applies a 3D LUT uploaded by [`upload_lut_texture`] with the GLSL from
+/// [`trilinear_body`].
+fn apply_trilinear(shader: &mut Shader, lut_tex: &Tex, body: &CStr) -> Result<()> {
+  let descriptor = pl_shader_desc {
+    desc: pl_desc {
+      name: cstr!("lut").as_ptr(),
+      type_: pl_desc_type::PL_DESC_SAMPLED_TEX,
+      ..pl_desc::default()
+    },
+    binding: pl_desc_binding {
+      object: lut_tex.as_ptr().cast(),
+      address_mode: pl_tex_address_mode::PL_TEX_ADDRESS_CLAMP,
+      sample_mode: pl_tex_sample_mode::PL_TEX_SAMPLE_LINEAR,
+    },
+    ..pl_shader_desc::default()
+  };
+
+  shader.custom(&pl_custom_shader {
+    description: cstr!("3DLUT (trilinear)").as_ptr(),
+    body: body.as_ptr(),
+    input: pl_shader_sig::PL_SHADER_SIG_COLOR,
+    output: pl_shader_sig::PL_SHADER_SIG_COLOR,
+    descriptors: &descriptor,
+    num_descriptors: 1,
+    ..pl_custom_shader::default()
+  })
+}
+
+pub struct Filter {
+  node: VideoNode,
+
+  lut: CustomLut,
+
+  /// Space the LUT is applied in.
+  lut_type: pl_lut_type,
+
+  interpolation: Interpolation,
+
+  /// Uploaded LUT, only used for trilinear interpolation.
+  lut_tex: Option<Tex>,
+  trilinear_body: CString,
+
+  /// LUT state used by `pl_shader_custom_lut()`. It holds the uploaded LUT,
+  /// so it is shared between frames.
+  lut_state: Mutex<ShaderObject>,
+
+  /// Attach GPU statistics to output frames.
+  stats: Option<StatsTimer>,
+
+  /// Where to write generated shaders, if anywhere.
+  shader_dump: Option<ShaderDump>,
+
+  dispatch: Dispatch,
+  vulkan: Vulkan,
+  pl_log: Arc<Log>,
+}
+
+impl Filter {
+  fn apply_lut(&self, shader: &mut Shader) -> Result<()> {
+    match (self.interpolation, &self.lut_tex) {
+      (Interpolation::Trilinear, Some(lut_tex)) => {
+        apply_trilinear(shader, lut_tex, &self.trilinear_body)
+      }
+      _ => {
+        let lut_state = self
+          .lut_state
+          .lock()
+          .map_err(|_| miette!("LUT state is poisoned."))?;
+        shader.custom_lut(&self.lut, &lut_state);
+        Ok(())
+      }
+    }
+  }
+
+  #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+  fn lut_frame(
+    &self,
+    frame_number: i32,
+    colorimetry: &Colorimetry,
+    texes_in: &[Tex],
+    texes_out: &[Tex],
+    mut stats: Option<&mut FrameStats>,
+  ) -> Result<()> {
+    let native = self.lut_type == pl_lut_type::PL_LUT_NATIVE;
+
+    for (i, tex_out) in texes_out.iter().enumerate() {
+      let mut shader = self.dispatch.begin();
+      shader.reset(&pl_shader_params {
+        gpu: self.vulkan.gpu(),
+        index: frame_number as u8,
+        ..pl_shader_params::default()
+      });
+
+      gather_planes(&mut shader, texes_in)?;
+
+      if native {
+        self.apply_lut(&mut shader)?;
+      } else {
+        let mut repr = colorimetry.repr;
+        shader.decode_color(&mut repr, None);
+        self.apply_lut(&mut shader)?;
+        shader.encode_color(&self.output_colorimetry(colorimetry).repr);
+      }
+
+      select_plane(&mut shader, i)?;
+
+      if let Some(dump) = &self.shader_dump {
+        dump.write("lut", &mut shader)?;
+      }
+
+      finish_pass(
+        &self.dispatch,
+        &pl_dispatch_params {
+          target: tex_out.as_ptr(),
+          shader: &mut shader.as_ptr(),
+          ..pl_dispatch_params::default()
+        },
+        stats.as_deref_mut(),
+      )?;
+    }
+
+    Ok(())
+  }
+
+  /// Color description of the output. Only conversion LUTs change it, and
+  /// only as far as the LUT specifies its output representation and color
+  /// space.
+  fn output_colorimetry(&self, src: &Colorimetry) -> Colorimetry {
+    let mut dst = *src;
+    if self.lut_type == pl_lut_type::PL_LUT_CONVERSION {
+      let repr_out = self.lut.repr_out();
+      if repr_out.sys != pl_color_system::PL_COLOR_SYSTEM_UNKNOWN {
+        dst.repr.sys = repr_out.sys;
+      }
+      if repr_out.levels != pl_color_levels::PL_COLOR_LEVELS_UNKNOWN {
+        dst.repr.levels = repr_out.levels;
+      }
+
+      let color_out = self.lut.color_out();
+      if color_out.primaries != pl_color_primaries::PL_COLOR_PRIM_UNKNOWN {
+        dst.color.primaries = color_out.primaries;
+      }
+      if color_out.transfer != pl_color_transfer::PL_COLOR_TRC_UNKNOWN {
+        dst.color.transfer = color_out.transfer;
+      }
+    }
+    dst
+  }
+}
+
+impl Drop for Filter {
+  fn drop(&mut self) {
+    if let Some(tex) = self.lut_tex.take() {
+      self.vulkan.tex_destroy(&tex);
+    }
+  }
+}
+
+impl VsFilter for Filter {
+  type Error = CString;
+  type FrameType = VideoFrame;
+  type FilterData = ();
+
+  #[allow(clippy::cast_possible_truncation)]
+  fn create<'b>(
+    input: MapRef<'_>,
+    output: MapMut<'_>,
+    _data: Option<Box<Self::FilterData>>,
+    mut core: CoreRef,
+  ) -> Result<(), Self::Error> {
+    let Ok(node) = input.get_video_node(key!("clip"), 0) else {
+      return Err(CString::new("Failed to get clip").unwrap());
+    };
+
+    let n = node.clone();
+    let vi = n.info();
+
+    if let Err(error) = check_format(&vi.format) {
+      return Err(CString::new(format!("placebo.LUT: input {error}")).unwrap());
+    }
+
+    let Ok(path) = input.get_utf8(key!("lut"), 0) else {
+      return Err(CString::new("placebo.LUT: `lut` must be a path to a .cube file.").unwrap());
+    };
+
+    let lut_type = match input.get_int(key!("type"), 0).unwrap_or(2) {
+      1 => pl_lut_type::PL_LUT_NATIVE,
+      2 => pl_lut_type::PL_LUT_NORMALIZED,
+      3 => pl_lut_type::PL_LUT_CONVERSION,
+      _ => {
+        return Err(
+          CString::new("placebo.LUT: type must be 1 (native), 2 (normalized), or 3 (conversion).")
+            .unwrap(),
+        )
+      }
+    };
+
+    let interpolation = match input.get_int(key!("interp"), 0).unwrap_or(0) {
+      0 => Interpolation::Tetrahedral,
+      1 => Interpolation::Trilinear,
+      _ => {
+        return Err(
+          CString::new("placebo.LUT: interp must be 0 (tetrahedral) or 1 (trilinear).").unwrap(),
+        )
+      }
+    };
+
+    let shader_dump = match input.get_utf8(key!("debug_shader_dir"), 0) {
+      Ok(dir) => Some(
+        ShaderDump::new(dir)
+          .map_err(|error| CString::new(format!("placebo.LUT: {error:?}")).unwrap())?,
+      ),
+      Err(_) => None,
+    };
+
+    // libplacebo setup.
+
+    // Log references are held by `Dispatch` and `Vulkan`.
+    let pl_log = Arc::new(Log::default());
+
+    let cube = match std::fs::read_to_string(path) {
+      Ok(cube) => cube,
+      Err(error) => return Err(CString::new(format!("placebo.LUT: {path}: {error}")).unwrap()),
+    };
+    let lut = match CustomLut::parse_cube(Some(pl_log.as_ref()), &cube) {
+      Ok(lut) => lut,
+      Err(error) => return Err(CString::new(format!("placebo.LUT: {path}: {error}")).unwrap()),
+    };
+
+    let vulkan = create_vulkan(&pl_log)
+      .map_err(|error| CString::new(format!("placebo.LUT: {error}")).unwrap())?;
+
+    // 1D LUTs are always handled by libplacebo.
+    let lut_tex = if interpolation == Interpolation::Trilinear && lut.is_3d() {
+      match upload_lut_texture(&vulkan, &lut) {
+        Ok(tex) => Some(tex),
+        Err(error) => return Err(CString::new(format!("placebo.LUT: {error}")).unwrap()),
+      }
+    } else {
+      None
+    };
+
+    for (usage, format, kind) in [
+      (TexUsage::Input, &vi.format, "input"),
+      (TexUsage::Output, &vi.format, "output"),
+    ] {
+      if let Err(error) = check_gpu_format(&vulkan, format, usage) {
+        return Err(CString::new(format!("placebo.LUT: {kind} {error}")).unwrap());
+      }
+    }
+
+    let mut filter = Self {
+      node,
+      trilinear_body: trilinear_body(lut.size(), &CubeDomain::parse(&cube)),
+      lut,
+      lut_type,
+      interpolation,
+      lut_tex,
+      lut_state: Mutex::new(ShaderObject::new()),
+      stats: (input.get_int(key!("stats"), 0).unwrap_or(0) != 0).then(|| StatsTimer::new(&vulkan)),
+      shader_dump,
+      dispatch: Dispatch::new(&pl_log, &vulkan.gpu()),
+      pl_log,
+      vulkan,
+    };
+
+    let deps = [FilterDependency {
+      source: filter.node.as_mut_ptr(),
+      request_pattern: RequestPattern::StrictSpatial,
+    }];
+
+    core.create_video_filter(
+      output,
+      cstr!("LUT"),
+      vi,
+      Box::new(filter),
+      Dependencies::new(&deps).unwrap(),
+    );
+
+    Ok(())
+  }
+
+  fn get_frame(
+    &self,
+    n: i32,
+    activation_reason: ActivationReason,
+    _frame_data: *mut *mut c_void,
+    mut ctx: FrameContext,
+    core: CoreRef,
+  ) -> Result<Option<VideoFrame>, Self::Error> {
+    match activation_reason {
+      ActivationReason::Initial => {
+        ctx.request_frame_filter(n, &self.node);
+      }
+      ActivationReason::AllFramesReady => {
+        let src = self.node.get_frame_filter(n, &mut ctx);
+        let format = *src.get_video_format();
+
+        let mut dst =
+          core.new_video_frame(&format, src.frame_width(0), src.frame_height(0), Some(&src));
+
+        let mut stats = self
+          .stats
+          .as_ref()
+          .map(|timer| FrameStats::new(&self.vulkan, timer));
+
+        let result = resolve_source(&ColorArgs::default(), &format, src.properties().as_ref())
+          .and_then(|colorimetry| {
+            let mut imports = Imports::default();
+            let (texes_in, texes_out) = upload_planes(&self.vulkan, &src, &format, &mut imports)?;
+
+            let result = self
+              .lut_frame(n, &colorimetry, &texes_in, &texes_out, stats.as_mut())
+              .and_then(|()| download_planes(&self.vulkan, &texes_out, &mut dst));
+
+            if let Some(stats) = &mut stats {
+              stats.finish();
+            }
+
+            imports.release(&self.vulkan);
+            destroy_textures(&self.vulkan, &texes_in);
+            destroy_textures(&self.vulkan, &texes_out);
+
+            result.map(|()| self.output_colorimetry(&colorimetry))
+          });
+
+        match result {
+          Ok(colorimetry) => {
+            set_props(&mut dst, &colorimetry);
+            if let Some(stats) = &stats {
+              stats.set_props(&mut dst);
+            }
+          }
+          Err(error) => return Err(CString::new(format!("placebo.LUT: {error:?}")).unwrap()),
+        }
+
+        return Ok(Some(dst));
+      }
+      ActivationReason::Error => {}
+    }
+
+    Ok(None)
+  }
+
+  const NAME: &'static CStr = cstr!("LUT");
+  const ARGS: &'static CStr = cstr!(
+    "clip:vnode;\
+    lut:data;\
+    type:int:opt;\
+    interp:int:opt;\
+    stats:int:opt;\
+    debug_shader_dir:data:opt;"
+  );
+  const RETURN_TYPE: &'static CStr = cstr!("clip:vnode;");
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::Context;
+
+  #[test]
+  fn trilinear_maps_domain_like_tetrahedral() {
+    let Some(ctx) = Context::new() else { return };
+
+    // Identity over 0.0 to 2.0, so that the LUT's grid covers twice the input
+    // range and ignoring the domain would double every value.
+    let cube = include_str!("../../libplacebo-rs/testdata/lut/domain.cube");
+    let lut = CustomLut::parse_cube(Some(&ctx.log), cube).unwrap();
+    let lut_tex = upload_lut_texture(&ctx.vulkan, &lut).unwrap();
+    let body = trilinear_body(lut.size(), &CubeDomain::parse(cube));
+    let lut_state = ShaderObject::new();
+
+    let (width, height) = (16, 4);
+    let texes: Vec<Tex> = (0..3u16)
+      .map(|plane| {
+        let samples: Vec<u16> = (0..width * height)
+          .map(|i| u16::try_from(i).unwrap() * 800 + plane * 5000)
+          .collect();
+        ctx.upload(width, height, 1, &samples)
+      })
+      .collect();
+
+    for plane in 0..3 {
+      let run = |trilinear: bool| {
+        let dst = ctx.texture(width, height, 1);
+        let mut shader = ctx.begin();
+        gather_planes(&mut shader, &texes).unwrap();
+        if trilinear {
+          apply_trilinear(&mut shader, &lut_tex, &body).unwrap();
+        } else {
+          shader.custom_lut(&lut, &lut_state);
+        }
+        select_plane(&mut shader, plane).unwrap();
+        ctx.finish(&shader, &dst);
+        ctx.download(&dst)
+      };
+
+      let (trilinear, tetrahedral) = (run(true), run(false));
+      let input = ctx.download(&texes[plane]);
+      for ((&tri, &tet), &src) in trilinear.iter().zip(&tetrahedral).zip(&input) {
+        assert!(
+          tri.abs_diff(tet) <= 64 && tri.abs_diff(src) <= 64,
+          "plane {plane}: {src} became {tri} (trilinear), {tet} (tetrahedral)"
+        );
+      }
+    }
+
+    ctx.vulkan.tex_destroy(&lut_tex);
+  }
+}
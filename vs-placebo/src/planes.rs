@@ -0,0 +1,580 @@
+//! Helpers for moving VapourSynth frames to and from textures, with every
+//! plane uploaded as its own single-component texture.
+//!
+//! Filters that process all planes of a non-subsampled frame jointly first
+//! gather them into `vec4 color` and, once processing is done, keep only the
+//! channel belonging to the plane they render to. Filters going through the
+//! renderer instead describe the textures as a single `pl_frame`.
+//!
+//! Where the GPU can import host memory and a plane is suitably aligned,
+//! transfers go directly between the texture and the frame's memory.
+
+use const_str::cstr;
+use libplacebo_rs::{
+  gpu::{Buf, Tex},
+  log::Log,
+  shaders::icc::Icc,
+  shaders_root::Shader,
+  utils::upload::{self, download_tex, pitch_alignment, ColorFamily, PlanarFormat, PlaneData},
+  vulkan::Vulkan,
+};
+use libplacebo_sys::{
+  pl_bit_encoding, pl_chroma_location, pl_color_system, pl_custom_shader, pl_desc, pl_desc_binding,
+  pl_desc_type, pl_fmt_caps, pl_fmt_type, pl_frame, pl_frame_set_chroma_location, pl_plane_data,
+  pl_shader_desc, pl_shader_sig, pl_tex, pl_tex_address_mode, pl_tex_params, pl_tex_sample_mode,
+  pl_tex_transfer_params, pl_vk_inst_params, pl_vulkan_params,
+};
+use miette::{miette, Result};
+use std::{ffi::CStr, slice};
+use vapoursynth4_rs::{
+  ffi::{VSColorFamily, VSSampleType},
+  frame::{VideoFormat, VideoFrame},
+};
+
+use crate::color::Colorimetry;
+
+/// Names of the input textures, as seen from GLSL.
+const SRC_NAMES: [&CStr; 3] = [cstr!("src0"), cstr!("src1"), cstr!("src2")];
+
+/// Gathers the three input planes into `color`.
+const GATHER_BODY: &CStr = cstr!(
+  "ivec2 p = ivec2(gl_FragCoord.xy);\n\
+  color = vec4(texelFetch(src0, p, 0).r, texelFetch(src1, p, 0).r, texelFetch(src2, p, 0).r, 1.0);\n"
+);
+
+/// Moves the channel belonging to the output plane `i` into `color.r`.
+const SELECT_BODIES: [&CStr; 3] = [
+  cstr!("color = vec4(color.r, 0.0, 0.0, 1.0);\n"),
+  cstr!("color = vec4(color.g, 0.0, 0.0, 1.0);\n"),
+  cstr!("color = vec4(color.b, 0.0, 0.0, 1.0);\n"),
+];
+
+/// Creates the Vulkan device a filter runs on. `log` must outlive it.
+///
+/// # Errors
+///
+/// Will return `Err` if there is no usable Vulkan device.
+pub fn create_vulkan(log: &Log) -> Result<Vulkan> {
+  Vulkan::try_new(
+    log,
+    &pl_vulkan_params {
+      async_compute: true,
+      async_transfer: true,
+      queue_count: 1,
+      instance_params: &pl_vk_inst_params {
+        debug: true,
+        ..pl_vk_inst_params::default()
+      },
+      ..pl_vulkan_params::default()
+    },
+  )
+  .ok_or_else(|| miette!("no Vulkan device"))
+}
+
+/// Bit encoding of samples stored in `format`.
+pub fn bit_encoding(format: &VideoFormat) -> pl_bit_encoding {
+  pl_bit_encoding {
+    sample_depth: format.bytes_per_sample * 8,
+    color_depth: format.bits_per_sample,
+    bit_shift: 0,
+  }
+}
+
+/// Planar layout of `format`.
+pub fn planar_format(format: &VideoFormat) -> PlanarFormat {
+  PlanarFormat {
+    color_family: match format.color_family {
+      VSColorFamily::Gray => ColorFamily::Gray,
+      VSColorFamily::RGB => ColorFamily::Rgb,
+      _ => ColorFamily::Yuv,
+    },
+    sample_type: if format.sample_type == VSSampleType::Integer {
+      pl_fmt_type::PL_FMT_UNORM
+    } else {
+      pl_fmt_type::PL_FMT_FLOAT
+    },
+    bits_per_sample: format.bits_per_sample,
+    bytes_per_sample: format.bytes_per_sample,
+    sub_sampling_w: format.sub_sampling_w,
+    sub_sampling_h: format.sub_sampling_h,
+  }
+}
+
+/// Describes a single `width`x`height` plane of `format`, without any pixel
+/// data attached.
+pub fn plane_data(
+  format: &VideoFormat,
+  width: i32,
+  height: i32,
+  plane: i32,
+) -> Result<pl_plane_data> {
+  planar_format(format)
+    .plane_data(plane, width, height)
+    .map(|(data, _)| data)
+}
+
+/// Checks that `format` can be processed by the helpers in this module.
+pub fn check_format(format: &VideoFormat) -> Result<(), &'static str> {
+  if format.num_planes != 3 {
+    return Err("only RGB and YUV formats are supported.");
+  }
+
+  if format.sub_sampling_w != 0 || format.sub_sampling_h != 0 {
+    return Err("subsampled formats are not supported.");
+  }
+
+  check_depth(format)
+}
+
+/// Checks that `format` can be described by [`frame_from_planes`]. Unlike
+/// [`check_format`], this accepts gray and subsampled formats, which the
+/// renderer handles as needed.
+pub fn check_render_format(format: &VideoFormat) -> Result<(), &'static str> {
+  match (format.color_family, format.num_planes) {
+    (VSColorFamily::Gray, 1) | (VSColorFamily::RGB | VSColorFamily::YUV, 3) => {}
+    _ => return Err("only gray, RGB and YUV formats are supported."),
+  }
+
+  check_depth(format)
+}
+
+fn check_depth(format: &VideoFormat) -> Result<(), &'static str> {
+  match (format.sample_type, format.bits_per_sample) {
+    (VSSampleType::Integer, 8..=16) | (VSSampleType::Float, 32) => Ok(()),
+    _ => Err("bit depth must be 8-16 (integer) or 32 (float)."),
+  }
+}
+
+/// How the textures of a filter are used, which decides the capabilities their
+/// formats need.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum TexUsage {
+  /// Uploaded and sampled from.
+  Input,
+
+  /// Rendered to and downloaded.
+  Output,
+}
+
+impl TexUsage {
+  fn caps(self) -> pl_fmt_caps {
+    match self {
+      Self::Input => pl_fmt_caps::PL_FMT_CAP_SAMPLEABLE,
+      Self::Output => pl_fmt_caps::PL_FMT_CAP_RENDERABLE | pl_fmt_caps::PL_FMT_CAP_HOST_READABLE,
+    }
+  }
+}
+
+/// Checks that the GPU has a texture format for every plane of `format` that
+/// supports `usage`, so that filters can reject the clip up front instead of
+/// failing on the first frame.
+pub fn check_gpu_format(
+  vulkan: &Vulkan,
+  format: &VideoFormat,
+  usage: TexUsage,
+) -> Result<(), String> {
+  let planar = planar_format(format);
+  let sample_type = if planar.sample_type == pl_fmt_type::PL_FMT_FLOAT {
+    "float"
+  } else {
+    "integer"
+  };
+
+  for plane in 0..planar.num_planes() {
+    let data = planar
+      .plane_data(plane, 1, 1)
+      .map_err(|error| error.to_string())?
+      .0;
+
+    let Some(tex_format) = vulkan.plane_find_fmt(&data) else {
+      return Err(format!(
+        "{}-bit {sample_type} samples have no matching GPU texture format.",
+        format.bits_per_sample
+      ));
+    };
+
+    if !tex_format.supports(usage.caps()) {
+      return Err(format!(
+        "{}-bit {sample_type} textures ({}) cannot be {} on this GPU.",
+        format.bits_per_sample,
+        tex_format.name().to_string_lossy(),
+        match usage {
+          TexUsage::Input => "sampled",
+          TexUsage::Output => "rendered to and downloaded",
+        },
+      ));
+    }
+  }
+
+  Ok(())
+}
+
+pub fn destroy_textures(vulkan: &Vulkan, texes: &[Tex]) {
+  for tex in texes {
+    vulkan.tex_destroy(tex);
+  }
+}
+
+/// Describes a single plane of `frame`, borrowing its pixel data.
+#[allow(clippy::cast_sign_loss)]
+pub fn frame_plane_data(frame: &VideoFrame, plane: i32) -> Result<PlaneData<'_>> {
+  let layout = plane_data(
+    frame.get_video_format(),
+    frame.frame_width(plane),
+    frame.frame_height(plane),
+    plane,
+  )?;
+  let pixels = unsafe { slice::from_raw_parts(frame.plane(plane), frame_plane_size(frame, plane)) };
+  PlaneData::with_layout(pixels, &layout, frame.stride(plane) as usize)
+}
+
+/// Number of bytes `plane` of `frame` occupies in memory.
+#[allow(clippy::cast_sign_loss)]
+fn frame_plane_size(frame: &VideoFrame, plane: i32) -> usize {
+  frame.stride(plane) as usize * frame.frame_height(plane) as usize
+}
+
+/// Wraps `size` bytes of frame memory at `ptr`, with rows `row_pitch` bytes
+/// apart, in a buffer, so that transfers of `texel_size`-byte texels skip
+/// libplacebo's internal staging copy. Returns `None` if the GPU cannot
+/// import host memory or the memory or its rows are not suitably aligned.
+fn import_plane(
+  vulkan: &Vulkan,
+  ptr: *mut u8,
+  size: usize,
+  row_pitch: usize,
+  texel_size: usize,
+) -> Option<Buf> {
+  let alignment = vulkan.host_ptr_alignment()?;
+  if ptr as usize % alignment != 0
+    || size % alignment != 0
+    || row_pitch % pitch_alignment(vulkan.as_gpu(), texel_size) != 0
+  {
+    return None;
+  }
+
+  // SAFETY: the frame outlives the buffer, which `release_buf()` only
+  // destroys once the GPU is done with it: uploads keep it in `Imports` until
+  // their frame has been downloaded, and downloads until they are done.
+  unsafe { vulkan.buf_import_host_ptr(ptr, size) }.ok()
+}
+
+/// Waits for the GPU to finish using `buf`, then destroys it.
+fn release_buf(vulkan: &Vulkan, buf: &Buf) {
+  while vulkan.buf_poll(buf, u64::MAX) {}
+  vulkan.buf_destroy(buf);
+}
+
+/// Frame memory imported as buffers by [`upload_plane`]. The GPU reads from
+/// them asynchronously, so they are kept until the passes sampling the
+/// uploaded textures have been downloaded, instead of waiting after each
+/// upload. The frames they were imported from must outlive them.
+#[derive(Default)]
+pub struct Imports(Vec<Buf>);
+
+impl Imports {
+  /// Destroys the buffers. Call this once the output has been downloaded,
+  /// after which the GPU is normally done with them.
+  pub fn release(self, vulkan: &Vulkan) {
+    for buf in &self.0 {
+      release_buf(vulkan, buf);
+    }
+  }
+}
+
+/// Uploads a single plane of `frame` to a new texture. If its memory can be
+/// imported, the buffer is added to `imports`.
+pub fn upload_plane(
+  vulkan: &Vulkan,
+  frame: &VideoFrame,
+  plane: i32,
+  imports: &mut Imports,
+) -> Result<Tex> {
+  let mut data = frame_plane_data(frame, plane)?;
+  let mut tex = None;
+
+  // Upload straight from the frame's memory if it can be imported.
+  let imported = import_plane(
+    vulkan,
+    frame.plane(plane).cast_mut(),
+    frame_plane_size(frame, plane),
+    data.row_stride(),
+    data.pixel_stride(),
+  );
+  if let Some(buf) = &imported {
+    data = data.with_buf(buf);
+  }
+
+  let result = upload::upload_plane(vulkan.as_gpu(), &mut tex, &data);
+  imports.0.extend(imported);
+
+  match (result, tex) {
+    (Ok(_), Some(tex)) => Ok(tex),
+    (result, tex) => {
+      if let Some(tex) = &tex {
+        vulkan.tex_destroy(tex);
+      }
+      Err(
+        result
+          .err()
+          .unwrap_or_else(|| miette!("Failed to upload plane.")),
+      )
+    }
+  }
+}
+
+/// Creates a texture that plane `plane` of a `format` frame can be rendered
+/// to and downloaded from.
+pub fn create_output(
+  vulkan: &Vulkan,
+  format: &VideoFormat,
+  width: i32,
+  height: i32,
+  plane: i32,
+) -> Result<Tex> {
+  let data = plane_data(format, width, height, plane)?;
+  let tex_format = vulkan
+    .plane_find_fmt(&data)
+    .ok_or_else(|| miette!("Failed to find a suitable output texture format."))?;
+
+  Ok(vulkan.tex_create(&pl_tex_params {
+    w: width,
+    h: height,
+    format: tex_format.as_ptr(),
+    renderable: true,
+    host_readable: true,
+    debug_tag: c"tex_out".as_ptr(),
+    ..pl_tex_params::default()
+  }))
+}
+
+/// Uploads every plane of `src` and creates matching output textures in
+/// `out_format`. On failure, everything is released, including `imports`.
+pub fn upload_planes(
+  vulkan: &Vulkan,
+  src: &VideoFrame,
+  out_format: &VideoFormat,
+  imports: &mut Imports,
+) -> Result<(Vec<Tex>, Vec<Tex>)> {
+  let mut texes_in = Vec::with_capacity(3);
+  let mut texes_out = Vec::with_capacity(3);
+
+  let result = (0..3).try_for_each(|plane| {
+    texes_in.push(upload_plane(vulkan, src, plane, imports)?);
+    texes_out.push(create_output(
+      vulkan,
+      out_format,
+      src.frame_width(plane),
+      src.frame_height(plane),
+      plane,
+    )?);
+    Ok(())
+  });
+
+  match result {
+    Ok(()) => Ok((texes_in, texes_out)),
+    Err(error) => {
+      std::mem::take(imports).release(vulkan);
+      destroy_textures(vulkan, &texes_in);
+      destroy_textures(vulkan, &texes_out);
+      Err(error)
+    }
+  }
+}
+
+/// Starts `shader` by sampling all of `texes` into `vec4 color`.
+///
+/// # Errors
+///
+/// Will return `Err` if the custom shader could not be applied.
+#[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+pub fn gather_planes(shader: &mut Shader, texes: &[Tex]) -> Result<()> {
+  let descriptors: Vec<pl_shader_desc> = texes
+    .iter()
+    .zip(SRC_NAMES)
+    .map(|(tex, name)| pl_shader_desc {
+      desc: pl_desc {
+        name: name.as_ptr(),
+        type_: pl_desc_type::PL_DESC_SAMPLED_TEX,
+        ..pl_desc::default()
+      },
+      binding: pl_desc_binding {
+        object: tex.as_ptr().cast(),
+        address_mode: pl_tex_address_mode::PL_TEX_ADDRESS_CLAMP,
+        sample_mode: pl_tex_sample_mode::PL_TEX_SAMPLE_NEAREST,
+      },
+      ..pl_shader_desc::default()
+    })
+    .collect();
+
+  shader.custom(&pl_custom_shader {
+    description: cstr!("gather planes").as_ptr(),
+    body: GATHER_BODY.as_ptr(),
+    input: pl_shader_sig::PL_SHADER_SIG_NONE,
+    output: pl_shader_sig::PL_SHADER_SIG_COLOR,
+    output_w: texes[0].width(),
+    output_h: texes[0].params().h,
+    descriptors: descriptors.as_ptr(),
+    num_descriptors: descriptors.len() as i32,
+    ..pl_custom_shader::default()
+  })
+}
+
+/// Ends `shader` by keeping only the channel that belongs to `plane`.
+///
+/// # Errors
+///
+/// Will return `Err` if the custom shader could not be applied.
+pub fn select_plane(shader: &mut Shader, plane: usize) -> Result<()> {
+  shader.custom(&pl_custom_shader {
+    description: cstr!("select plane").as_ptr(),
+    body: SELECT_BODIES[plane].as_ptr(),
+    input: pl_shader_sig::PL_SHADER_SIG_COLOR,
+    output: pl_shader_sig::PL_SHADER_SIG_COLOR,
+    ..pl_custom_shader::default()
+  })
+}
+
+/// Describes `texes`, one per plane of `format`, as a single frame for the
+/// renderer. Chroma planes of YUV frames are positioned according to
+/// `chroma_location`. If `icc` is set, the renderer decodes (or, for targets,
+/// encodes) the frame with that profile instead of `colorimetry.color`.
+pub fn frame_from_planes(
+  format: &VideoFormat,
+  texes: &[Tex],
+  colorimetry: &Colorimetry,
+  chroma_location: pl_chroma_location,
+  icc: Option<&Icc>,
+) -> Result<pl_frame> {
+  let textures: Vec<pl_tex> = texes.iter().map(Tex::as_ptr).collect();
+  let mut frame = planar_format(format).frame(&textures, &colorimetry.repr, &colorimetry.color)?;
+  frame.icc = icc.map_or(std::ptr::null(), Icc::as_ptr);
+
+  set_chroma_location(&mut frame, chroma_location);
+  Ok(frame)
+}
+
+/// Positions the chroma planes of a YUV `frame`. Must be called after its
+/// planes have been set up.
+pub fn set_chroma_location(frame: &mut pl_frame, chroma_location: pl_chroma_location) {
+  if frame.repr.sys != pl_color_system::PL_COLOR_SYSTEM_RGB {
+    unsafe { pl_frame_set_chroma_location(frame, chroma_location) };
+  }
+}
+
+/// Downloads every texture in `texes_out` into the matching plane of `dst`.
+pub fn download_planes(vulkan: &Vulkan, texes_out: &[Tex], dst: &mut VideoFrame) -> Result<()> {
+  let mut imports = Imports::default();
+  let result = (0..)
+    .zip(texes_out)
+    .try_for_each(|(plane, tex)| download_plane(vulkan, tex, dst, plane, &mut imports));
+
+  // The frame must not be handed out before the downloads are done.
+  imports.release(vulkan);
+  result
+}
+
+/// Downloads `tex` into plane `plane` of `dst`. If the frame's memory can be
+/// imported, the download only completes once the buffer added to `imports`
+/// is released.
+#[allow(clippy::cast_sign_loss)]
+pub fn download_plane(
+  vulkan: &Vulkan,
+  tex: &Tex,
+  dst: &mut VideoFrame,
+  plane: i32,
+  imports: &mut Imports,
+) -> Result<()> {
+  let size = frame_plane_size(dst, plane);
+  let row_pitch = dst.stride(plane) as usize;
+  let ptr = dst.plane_mut(plane);
+
+  // Download straight into the frame's memory if it can be imported.
+  if let Some(buf) = import_plane(vulkan, ptr, size, row_pitch, tex.format().texel_size()) {
+    let result = vulkan.tex_download(&pl_tex_transfer_params {
+      tex: tex.as_ptr(),
+      row_pitch,
+      buf: buf.as_ptr(),
+      ..pl_tex_transfer_params::default()
+    });
+    imports.0.push(buf);
+    result
+  } else {
+    let pixels = unsafe { slice::from_raw_parts_mut(ptr, size) };
+    download_tex(vulkan.as_gpu(), tex, pixels, row_pitch)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::{format, Context};
+
+  #[test]
+  fn checks_formats() {
+    let rgb = format(VSColorFamily::RGB, 16, (0, 0));
+    let yuv420 = format(VSColorFamily::YUV, 10, (1, 1));
+    let gray = format(VSColorFamily::Gray, 8, (0, 0));
+    let float = VideoFormat {
+      sample_type: VSSampleType::Float,
+      bits_per_sample: 32,
+      bytes_per_sample: 4,
+      ..rgb
+    };
+
+    assert!(check_format(&rgb).is_ok());
+    assert!(check_format(&float).is_ok());
+    assert!(check_format(&yuv420).is_err());
+    assert!(check_format(&gray).is_err());
+    assert!(check_format(&format(VSColorFamily::YUV, 20, (0, 0))).is_err());
+
+    assert!(check_render_format(&yuv420).is_ok());
+    assert!(check_render_format(&gray).is_ok());
+    assert!(check_render_format(&VideoFormat {
+      bits_per_sample: 16,
+      ..float
+    })
+    .is_err());
+  }
+
+  #[test]
+  fn describes_formats() {
+    let yuv420 = format(VSColorFamily::YUV, 10, (1, 1));
+
+    let bits = bit_encoding(&yuv420);
+    assert_eq!((bits.sample_depth, bits.color_depth), (16, 10));
+
+    let planar = planar_format(&yuv420);
+    assert_eq!(planar.color_family, ColorFamily::Yuv);
+    assert_eq!(planar.sample_type, pl_fmt_type::PL_FMT_UNORM);
+    assert_eq!((planar.sub_sampling_w, planar.sub_sampling_h), (1, 1));
+  }
+
+  #[test]
+  fn gathers_and_selects_planes() {
+    let Some(ctx) = Context::new() else { return };
+
+    let (width, height) = (8, 4);
+    let planes: Vec<Vec<u16>> = (0..3u16)
+      .map(|plane| {
+        (0..width * height)
+          .map(|i| u16::try_from(i).unwrap() * 1000 + plane * 17)
+          .collect()
+      })
+      .collect();
+    let texes: Vec<Tex> = planes
+      .iter()
+      .map(|samples| ctx.upload(width, height, 1, samples))
+      .collect();
+
+    for (i, expected) in planes.iter().enumerate() {
+      let dst = ctx.texture(width, height, 1);
+      let mut shader = ctx.begin();
+      gather_planes(&mut shader, &texes).unwrap();
+      select_plane(&mut shader, i).unwrap();
+      ctx.finish(&shader, &dst);
+
+      assert_eq!(&ctx.download(&dst), expected, "plane {i}");
+    }
+  }
+}
@@ -0,0 +1,109 @@
+//! Writes the shaders a filter generates to disk, for filters created with
+//! `debug_shader_dir`. Each unique shader is written once, as
+//! `<name>-<signature>.glsl`, with its description and variables in a leading
+//! comment. Passes run by the renderer only expose their description, so
+//! those are written as `<name>-<signature>.txt` instead.
+
+use libplacebo_rs::shaders_root::{var_values, Shader, ShaderInfo, ShaderRes};
+use miette::{miette, IntoDiagnostic, Result};
+use std::collections::HashSet;
+use std::ffi::CStr;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+pub struct ShaderDump {
+  dir: PathBuf,
+
+  /// Signatures of the shaders written so far.
+  written: Mutex<HashSet<u64>>,
+}
+
+impl ShaderDump {
+  pub fn new(dir: impl Into<PathBuf>) -> Result<Self> {
+    let dir = dir.into();
+    fs::create_dir_all(&dir).into_diagnostic()?;
+
+    Ok(Self {
+      dir,
+      written: Mutex::default(),
+    })
+  }
+
+  /// Finalizes `shader` and writes it out, unless a shader with the same
+  /// signature was written before. The shader can still be dispatched.
+  pub fn write(&self, name: &str, shader: &mut Shader) -> Result<()> {
+    let res = shader.finalize()?;
+    let signature = res.info().signature();
+    if !self.is_new(signature)? {
+      return Ok(());
+    }
+
+    let path = self.dir.join(format!("{name}-{signature:016x}.glsl"));
+    fs::write(path, describe(&res)).into_diagnostic()
+  }
+
+  /// Writes out the description of a pass run by the renderer, unless a
+  /// shader with the same signature was written before.
+  pub fn write_info(&self, name: &str, info: &ShaderInfo) -> Result<()> {
+    let signature = info.signature();
+    if !self.is_new(signature)? {
+      return Ok(());
+    }
+
+    let path = self.dir.join(format!("{name}-{signature:016x}.txt"));
+    fs::write(path, describe_info(info)).into_diagnostic()
+  }
+
+  /// Records `signature` as written, returning whether it was not before.
+  fn is_new(&self, signature: u64) -> Result<bool> {
+    Ok(
+      self
+        .written
+        .lock()
+        .map_err(|_| miette!("shader dump is poisoned."))?
+        .insert(signature),
+    )
+  }
+}
+
+/// Description, signature and steps of a shader, as a comment.
+fn describe_info(info: &ShaderInfo) -> String {
+  let mut out = String::new();
+
+  let _ = writeln!(out, "// {}", info.description().to_string_lossy());
+  let _ = writeln!(out, "// signature: {:016x}", info.signature());
+
+  for step in info.steps() {
+    let _ = writeln!(out, "// step: {}", step.to_string_lossy());
+  }
+
+  out
+}
+
+/// GLSL of `res`, preceded by a comment describing it.
+fn describe(res: &ShaderRes) -> String {
+  let mut out = describe_info(&res.info());
+  let _ = writeln!(out, "// input: {:?}", res.input());
+  let _ = writeln!(out, "// output: {:?}", res.output());
+
+  for var in res.variables() {
+    let name = unsafe { CStr::from_ptr(var.var.name) };
+    let _ = writeln!(
+      out,
+      "// var: {} {:?}[{}x{}][{}]{} = {:?}",
+      name.to_string_lossy(),
+      var.var.type_,
+      var.var.dim_v,
+      var.var.dim_m,
+      var.var.dim_a,
+      if var.dynamic { " (dynamic)" } else { "" },
+      unsafe { var_values(var) },
+    );
+  }
+
+  out.push('\n');
+  out.push_str(&res.glsl().to_string_lossy());
+  out
+}
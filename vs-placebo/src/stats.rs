@@ -0,0 +1,214 @@
+//! Per-frame GPU statistics, attached to output frames when a filter is
+//! created with `stats=True`.
+//!
+//! Passes dispatched by the filters themselves are timed with a `pl_timer`
+//! owned by the filter, see [`StatsTimer`]. Passes run by the renderer are
+//! reported through its `info_callback`, whose timings are those of the most
+//! recent execution of each shader and may therefore lag behind by a frame.
+
+use std::{
+  ffi::{c_void, CStr},
+  ptr::null_mut,
+  sync::{Mutex, PoisonError},
+};
+
+use libplacebo_rs::{
+  dispatch::Dispatch,
+  gpu::{self, Gpu},
+  vulkan::Vulkan,
+};
+use libplacebo_sys::{
+  pl_dispatch_info, pl_dispatch_params, pl_render_info, pl_render_params, pl_timer_destroy,
+};
+use miette::Result;
+use vapoursynth4_rs::{frame::VideoFrame, key, map::AppendMode};
+
+/// Timer for the passes a filter dispatches, kept for the lifetime of the
+/// filter. Its measurements become available once the GPU has executed the
+/// passes, which is not always the case by the time their frame is finished;
+/// those are reported with the next frame to be finished instead of being
+/// lost. With several frames in flight, a frame's time may therefore include
+/// passes of others, but no measurement is dropped or counted twice.
+///
+/// Must be declared before the filter's `vulkan` field, so that it is
+/// destroyed first.
+pub struct StatsTimer {
+  gpu: Gpu,
+
+  /// `None` if the GPU has no timers. The lock is held while dispatching
+  /// timed passes and querying, as `pl_timer` is not thread-safe.
+  timer: Mutex<Option<gpu::Timer>>,
+}
+
+unsafe impl Send for StatsTimer {}
+unsafe impl Sync for StatsTimer {}
+
+impl StatsTimer {
+  pub fn new(vulkan: &Vulkan) -> Self {
+    Self {
+      gpu: vulkan.as_gpu(),
+      timer: Mutex::new(vulkan.timer_create()),
+    }
+  }
+}
+
+impl Drop for StatsTimer {
+  fn drop(&mut self) {
+    let timer = self.timer.get_mut().unwrap_or_else(PoisonError::into_inner);
+    if let Some(timer) = timer.take() {
+      unsafe { pl_timer_destroy(self.gpu.as_ptr(), &mut timer.as_ptr()) };
+    }
+  }
+}
+
+/// Execution times of a pass run by the renderer, from its `pl_dispatch_info`.
+/// libplacebo does not report compilation statistics.
+struct PassInfo {
+  description: String,
+  last_ns: u64,
+  average_ns: u64,
+  peak_ns: u64,
+}
+
+pub struct FrameStats<'a> {
+  vulkan: &'a Vulkan,
+
+  timer: &'a StatsTimer,
+
+  /// Whether any pass was timed, by `timer` or the renderer.
+  timed: bool,
+
+  /// Sum of the measured pass durations, in nanoseconds.
+  gpu_time_ns: u64,
+
+  /// Number of shader passes run for the frame.
+  passes: i64,
+
+  /// Passes reported by the renderer, in execution order.
+  render_passes: Vec<PassInfo>,
+}
+
+impl<'a> FrameStats<'a> {
+  pub const fn new(vulkan: &'a Vulkan, timer: &'a StatsTimer) -> Self {
+    Self {
+      vulkan,
+      timer,
+      timed: false,
+      gpu_time_ns: 0,
+      passes: 0,
+      render_passes: Vec::new(),
+    }
+  }
+
+  /// Makes the renderer report its passes. `self` must stay in place until
+  /// rendering with `params` is done.
+  pub fn attach(&mut self, params: &mut pl_render_params) {
+    params.info_callback = Some(collect_render_info);
+    params.info_priv = std::ptr::from_mut(self).cast();
+  }
+
+  /// Collects the timer measurements available so far. Call this once the
+  /// frame has been downloaded, which waits for the GPU to finish its passes.
+  pub fn finish(&mut self) {
+    let timer = self
+      .timer
+      .timer
+      .lock()
+      .unwrap_or_else(PoisonError::into_inner);
+    if let Some(timer) = &*timer {
+      loop {
+        let time = self.vulkan.timer_query(timer);
+        if time == 0 {
+          break;
+        }
+
+        self.gpu_time_ns += time;
+        self.timed = true;
+      }
+    }
+  }
+
+  /// Counts a pass reported by the renderer. For filters whose own
+  /// `info_callback` replaces the one set by [`Self::attach`].
+  pub fn record_render_pass(&mut self, pass: &pl_dispatch_info) {
+    let description = if pass.shader.is_null() || unsafe { (*pass.shader).description.is_null() } {
+      String::new()
+    } else {
+      unsafe { CStr::from_ptr((*pass.shader).description) }
+        .to_string_lossy()
+        .into_owned()
+    };
+
+    self.passes += 1;
+    self.gpu_time_ns += pass.last;
+    self.timed |= pass.last != 0;
+    self.render_passes.push(PassInfo {
+      description,
+      last_ns: pass.last,
+      average_ns: pass.average,
+      peak_ns: pass.peak,
+    });
+  }
+
+  #[allow(clippy::cast_possible_wrap)]
+  pub fn set_props(&self, frame: &mut VideoFrame) {
+    if let Some(mut props) = frame.properties_mut() {
+      let _ = props.set_int(key!("PlaceboPasses"), self.passes, AppendMode::Replace);
+
+      if self.timed {
+        let _ = props.set_int(
+          key!("PlaceboGpuTimeNs"),
+          self.gpu_time_ns as i64,
+          AppendMode::Replace,
+        );
+      }
+
+      for pass in &self.render_passes {
+        let _ = props.set_utf8(
+          key!("PlaceboPassDescriptions"),
+          &pass.description,
+          AppendMode::Append,
+        );
+        for (key, value) in [
+          (key!("PlaceboPassLastNs"), pass.last_ns),
+          (key!("PlaceboPassAverageNs"), pass.average_ns),
+          (key!("PlaceboPassPeakNs"), pass.peak_ns),
+        ] {
+          let _ = props.set_int(key, value as i64, AppendMode::Append);
+        }
+      }
+    }
+  }
+}
+
+unsafe extern "C" fn collect_render_info(priv_: *mut c_void, info: *const pl_render_info) {
+  let stats = &mut *priv_.cast::<FrameStats>();
+  stats.record_render_pass(&*(*info).pass);
+}
+
+/// Dispatches a shader like `Dispatch::finish`, timing and counting the pass
+/// if `stats` are collected.
+pub fn finish_pass(
+  dispatch: &Dispatch,
+  params: &pl_dispatch_params,
+  stats: Option<&mut FrameStats>,
+) -> Result<()> {
+  let Some(stats) = stats else {
+    return dispatch.finish(params);
+  };
+
+  {
+    let timer = stats
+      .timer
+      .timer
+      .lock()
+      .unwrap_or_else(PoisonError::into_inner);
+    dispatch.finish(&pl_dispatch_params {
+      timer: timer.as_ref().map_or(null_mut(), gpu::Timer::as_ptr),
+      ..*params
+    })?;
+  }
+  stats.passes += 1;
+
+  Ok(())
+}
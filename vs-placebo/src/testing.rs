@@ -0,0 +1,164 @@
+//! Helpers for tests that run the filters' GPU code. Like libplacebo-rs'
+//! golden tests, they are skipped when there is no Vulkan device, and prefer
+//! software devices like lavapipe.
+
+use std::cell::RefCell;
+
+use libplacebo_rs::{
+  dispatch::Dispatch,
+  gpu::Tex,
+  log::Log,
+  shaders_root::Shader,
+  utils::upload::{download_tex, upload_tex},
+  vulkan::Vulkan,
+};
+use libplacebo_sys::{
+  pl_dispatch_params, pl_fmt_caps, pl_fmt_type, pl_shader_params, pl_tex_params, pl_vulkan_params,
+};
+use vapoursynth4_rs::{
+  ffi::{VSColorFamily, VSSampleType},
+  frame::VideoFormat,
+};
+
+/// A `bits`-bit integer format of `color_family`, subsampled by
+/// `sub_sampling`.
+pub const fn format(
+  color_family: VSColorFamily,
+  bits: i32,
+  sub_sampling: (i32, i32),
+) -> VideoFormat {
+  VideoFormat {
+    color_family,
+    sample_type: VSSampleType::Integer,
+    bits_per_sample: bits,
+    bytes_per_sample: if bits > 8 { 2 } else { 1 },
+    sub_sampling_w: sub_sampling.0,
+    sub_sampling_h: sub_sampling.1,
+    num_planes: if matches!(color_family, VSColorFamily::Gray) {
+      1
+    } else {
+      3
+    },
+  }
+}
+
+/// A Vulkan device and dispatch, with single-component 16-bit textures like
+/// the ones [`crate::planes`] uploads planes of 16-bit clips to. Textures
+/// created through it are destroyed along with it.
+pub struct Context {
+  pub dispatch: Dispatch,
+  textures: RefCell<Vec<Tex>>,
+  pub vulkan: Vulkan,
+  pub log: Log,
+}
+
+impl Context {
+  /// Creates a context, or returns `None` if there is no Vulkan device, in
+  /// which case the calling test should return early.
+  pub fn new() -> Option<Self> {
+    let log = Log::default();
+    let Some(vulkan) = Vulkan::try_new(
+      &log,
+      &pl_vulkan_params {
+        allow_software: true,
+        queue_count: 1,
+        ..pl_vulkan_params::default()
+      },
+    ) else {
+      eprintln!("No Vulkan device available, skipping.");
+      return None;
+    };
+
+    Some(Self {
+      dispatch: Dispatch::new(&log, &vulkan.gpu()),
+      textures: RefCell::new(Vec::new()),
+      vulkan,
+      log,
+    })
+  }
+
+  /// A blank `width`x`height` texture with `channels` 16-bit components.
+  #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+  pub fn texture(&self, width: usize, height: usize, channels: usize) -> Tex {
+    let format = self
+      .vulkan
+      .find_fmt(
+        pl_fmt_type::PL_FMT_UNORM,
+        channels as i32,
+        16,
+        16,
+        pl_fmt_caps::PL_FMT_CAP_SAMPLEABLE
+          | pl_fmt_caps::PL_FMT_CAP_RENDERABLE
+          | pl_fmt_caps::PL_FMT_CAP_HOST_READABLE,
+      )
+      .unwrap_or_else(|| panic!("No 16-bit format with {channels} components."));
+
+    let tex = self.vulkan.tex_create(&pl_tex_params {
+      w: width as i32,
+      h: height as i32,
+      format: format.as_ptr(),
+      sampleable: true,
+      renderable: true,
+      host_writable: true,
+      host_readable: true,
+      ..pl_tex_params::default()
+    });
+
+    self.textures.borrow_mut().push(tex.clone());
+    tex
+  }
+
+  /// A texture holding `samples`, with `channels` interleaved components per
+  /// pixel.
+  pub fn upload(&self, width: usize, height: usize, channels: usize, samples: &[u16]) -> Tex {
+    assert_eq!(samples.len(), width * height * channels);
+
+    let tex = self.texture(width, height, channels);
+    let bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_ne_bytes()).collect();
+    upload_tex(self.vulkan.as_gpu(), &tex, &bytes, 0).unwrap();
+    tex
+  }
+
+  /// The samples of `tex`, which must have 16-bit components.
+  #[allow(clippy::cast_sign_loss)]
+  pub fn download(&self, tex: &Tex) -> Vec<u16> {
+    let len = tex.width() as usize * tex.height() as usize * tex.num_components() as usize;
+    let mut bytes = vec![0; len * 2];
+    download_tex(self.vulkan.as_gpu(), tex, &mut bytes, 0).unwrap();
+
+    bytes
+      .chunks_exact(2)
+      .map(|s| u16::from_ne_bytes([s[0], s[1]]))
+      .collect()
+  }
+
+  /// A blank shader, set up the way the filters set up theirs.
+  pub fn begin(&self) -> Shader {
+    let mut shader = self.dispatch.begin();
+    shader.reset(&pl_shader_params {
+      gpu: self.vulkan.gpu(),
+      ..pl_shader_params::default()
+    });
+    shader
+  }
+
+  /// Runs `shader`, rendering into `target`.
+  pub fn finish(&self, shader: &Shader, target: &Tex) {
+    self
+      .dispatch
+      .finish(&pl_dispatch_params {
+        target: target.as_ptr(),
+        shader: &mut shader.as_ptr(),
+        ..pl_dispatch_params::default()
+      })
+      .unwrap();
+  }
+}
+
+impl Drop for Context {
+  fn drop(&mut self) {
+    for tex in self.textures.borrow().iter() {
+      self.vulkan.tex_destroy(tex);
+    }
+  }
+}